@@ -0,0 +1,201 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, eyre};
+
+/// External programs spawned by the tools, by default looked up in the PATH
+#[derive(Debug, Clone)]
+pub struct BinaryPaths {
+    pub av1an: PathBuf,
+    pub vspipe: PathBuf,
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    pub ffmsindex: PathBuf,
+}
+
+impl Default for BinaryPaths {
+    fn default() -> Self {
+        Self {
+            av1an: PathBuf::from("av1an"),
+            vspipe: PathBuf::from("vspipe"),
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+            ffmsindex: PathBuf::from("ffmsindex"),
+        }
+    }
+}
+
+static BINARY_PATHS: OnceLock<BinaryPaths> = OnceLock::new();
+
+/// Overrides the programs used by every later command, has to be called before the first one
+pub fn set_binary_paths(paths: BinaryPaths) -> Result<()> {
+    BINARY_PATHS
+        .set(paths)
+        .map_err(|_| eyre!("The binary paths were already set"))
+}
+
+pub fn binary_paths() -> &'static BinaryPaths {
+    BINARY_PATHS.get_or_init(BinaryPaths::default)
+}
+
+pub fn av1an_command() -> Command {
+    Command::new(&binary_paths().av1an)
+}
+
+pub fn vspipe_command() -> Command {
+    Command::new(&binary_paths().vspipe)
+}
+
+pub fn ffmpeg_command() -> Command {
+    Command::new(&binary_paths().ffmpeg)
+}
+
+pub fn ffprobe_command() -> Command {
+    Command::new(&binary_paths().ffprobe)
+}
+
+pub fn ffmsindex_command() -> Command {
+    Command::new(&binary_paths().ffmsindex)
+}
+
+/// Limits of the external commands: a command running longer than `timeout` in total is killed
+/// with the processes it started and run again, up to `retries` times. av1an resumes its encode
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommandLimits {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+}
+
+static COMMAND_LIMITS: OnceLock<CommandLimits> = OnceLock::new();
+
+/// Sets the limits of every later command, has to be called before the first one
+pub fn set_command_limits(limits: CommandLimits) -> Result<()> {
+    COMMAND_LIMITS
+        .set(limits)
+        .map_err(|_| eyre!("The command limits were already set"))
+}
+
+pub fn command_limits() -> CommandLimits {
+    *COMMAND_LIMITS.get_or_init(CommandLimits::default)
+}
+
+/// Interval between two checks of a running command with a timeout
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Puts `command` in a process group of its own when commands have a timeout, so
+/// [`kill_process_tree`] also reaches the encoders and vspipe it starts. Only done with a
+/// timeout, Ctrl+C is only sent to the process group of the terminal
+pub fn isolate_process_group(command: &mut Command) -> &mut Command {
+    #[cfg(unix)]
+    if command_limits().timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command
+}
+
+/// Kills `child` and the processes it started. On Unix `child` has to lead its process group,
+/// see [`isolate_process_group`]
+pub fn kill_process_tree(child: &mut Child) {
+    let pid = child.id().to_string();
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{pid}")])
+        .stderr(Stdio::null())
+        .status();
+    #[cfg(windows)]
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    // Already exited if this fails
+    let _ = child.kill();
+}
+
+/// Waits for `child`, killing it and the processes it started (see [`kill_process_tree`]) once
+/// `timeout` has passed. Returns `None` when it was killed
+pub fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(child.wait()?));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill_process_tree(child);
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL);
+    }
+}
+
+/// Lines of the av1an log shown in the error of a failed run
+const LOG_TAIL_LINES: usize = 20;
+
+/// Runs the av1an `command` writing its log to `log_file`. Its output is inherited, so the
+/// progress bar still shows, and a failed run errors with the last lines of the log.
+/// A run exceeding the timeout of [`CommandLimits`] is killed with its encoders and run again
+/// with `--resume`, keeping the chunks already encoded
+pub fn run_av1an(mut command: Command, log_file: &Path) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let limits = command_limits();
+    command.arg("--log-file").arg(log_file);
+    isolate_process_group(&mut command);
+
+    for attempt in 0..=limits.retries {
+        if attempt == 1 && !command.get_args().any(|arg| arg == "--resume") {
+            command.arg("--resume");
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| eyre!("Failed to run {program}: {e}"))?;
+
+        let Some(status) = wait_with_timeout(&mut child, limits.timeout)? else {
+            eprintln!(
+                "{program} timed out after {}s (attempt {}/{})",
+                limits.timeout.unwrap_or_default().as_secs(),
+                attempt + 1,
+                limits.retries + 1
+            );
+            continue;
+        };
+
+        if !status.success() {
+            return Err(eyre!(
+                "{program} failed ({status}), last lines of {}:\n{}",
+                log_file.display(),
+                log_tail(log_file)
+            ));
+        }
+
+        return Ok(());
+    }
+
+    Err(eyre!(
+        "{program} timed out {} times, giving up",
+        limits.retries + 1
+    ))
+}
+
+/// Last non-empty lines of `log_file`, or a note if it can't be read
+fn log_tail(log_file: &Path) -> String {
+    let Ok(log) = fs::read_to_string(log_file) else {
+        return "(no log written)".to_owned();
+    };
+    let lines: Vec<&str> = log.lines().filter(|line| !line.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n")
+}
@@ -89,10 +89,73 @@ impl fmt::Display for Chapters {
 }
 
 impl Chapters {
+    /// Parses Matroska XML or OGM text chapters, detected by extension or by content
     pub fn parse(path: &Path) -> eyre::Result<Chapters> {
-        let xml_data = fs::read_to_string(path)?;
-        let chapters: Chapters = quick_xml::de::from_str(&xml_data)?;
-        Ok(chapters)
+        let data = fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let is_xml = match extension.as_deref() {
+            Some("xml") => true,
+            Some("txt") => false,
+            _ => !data.trim_start().starts_with("CHAPTER"),
+        };
+
+        if is_xml {
+            let chapters: Chapters = quick_xml::de::from_str(&data)?;
+            Ok(chapters)
+        } else {
+            Self::parse_ogm(&data)
+        }
+    }
+
+    /// Parses OGM text chapters: `CHAPTER01=00:00:00.000` followed by `CHAPTER01NAME=Title`
+    pub fn parse_ogm(data: &str) -> eyre::Result<Chapters> {
+        let mut chapters: Vec<ChapterAtom> = Vec::new();
+
+        for line in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| eyre::eyre!("Invalid OGM chapter line '{line}'"))?;
+            let Some(id) = key.strip_prefix("CHAPTER") else {
+                return Err(eyre::eyre!("Invalid OGM chapter line '{line}'"));
+            };
+
+            if let Some(id) = id.strip_suffix("NAME") {
+                let chapter = chapters
+                    .last_mut()
+                    .filter(|chapter| chapter.uid == id)
+                    .ok_or_else(|| eyre::eyre!("Chapter name '{line}' has no chapter time"))?;
+                chapter.display.string = value.trim().to_string();
+            } else {
+                chapters.push(ChapterAtom {
+                    uid: id.to_string(),
+                    time_start: value.trim().to_string(),
+                    flag_hidden: None,
+                    flag_enabled: None,
+                    display: ChapterDisplay {
+                        string: format!("Chapter {id}"),
+                        language: "und".to_string(),
+                        language_ietf: None,
+                    },
+                });
+            }
+        }
+
+        if chapters.is_empty() {
+            return Err(eyre::eyre!("No chapters found in OGM chapter file"));
+        }
+
+        Ok(Chapters {
+            edition_entry: EditionEntry {
+                flag_hidden: None,
+                flag_default: None,
+                flag_ordered: None,
+                uid: "1".to_string(),
+                chapters,
+            },
+        })
     }
 
     pub fn write<'a>(&self, path: &'a Path) -> eyre::Result<&'a Path> {
@@ -113,6 +176,47 @@ pub struct ZoneChapter {
     pub start: u32,
     pub end: u32,
     pub crf: f64,
+    #[serde(default)]
+    pub target_quality: Option<f64>,
+    #[serde(default)]
+    pub target_percentile: Option<u8>,
+}
+
+/// Target percentile of frame-boost, with overrides for some chapters
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetPercentile {
+    pub default: u8,
+    pub chapters: Vec<(String, u8)>,
+}
+
+/// Parses "50", "50,Ending:5" or "Ending:5": a global percentile (50 when omitted) and
+/// {Chapter}:{PERCENTILE} overrides, like the chapters zoning
+pub fn parse_target_percentile(arg: &str) -> Result<TargetPercentile, String> {
+    let parse_percentile = |value: &str| -> Result<u8, String> {
+        let percentile: u8 = value
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid percentile '{value}': {e}"))?;
+        if percentile > 100 {
+            return Err(format!("Percentile {percentile} is above 100"));
+        }
+        Ok(percentile)
+    };
+
+    let mut target_percentile = TargetPercentile {
+        default: 50,
+        chapters: Vec::new(),
+    };
+    for item in arg.split(',').filter(|item| !item.trim().is_empty()) {
+        match item.split_once(':') {
+            Some((chapter, value)) => target_percentile
+                .chapters
+                .push((chapter.trim().to_owned(), parse_percentile(value)?)),
+            None => target_percentile.default = parse_percentile(item)?,
+        }
+    }
+
+    Ok(target_percentile)
 }
 
 impl fmt::Display for ZoneChapters {
@@ -145,15 +249,25 @@ impl fmt::Display for ZoneChapters {
             } else {
                 format!("{:.2}", chapter.crf)
             };
+            let target_quality_str = match chapter.target_quality {
+                Some(target_quality) => format!("{target_quality:.2}"),
+                None => "-".to_string(),
+            };
+            let target_percentile_str = match chapter.target_percentile {
+                Some(target_percentile) => target_percentile.to_string(),
+                None => "-".to_string(),
+            };
 
             writeln!(
                 f,
-                "  {:>2}. Chapter: {:<width_name$} | frames: {:>width_start$}–{:>width_end$} | CRF: {:>5}",
+                "  {:>2}. Chapter: {:<width_name$} | frames: {:>width_start$}–{:>width_end$} | CRF: {:>5} | TQ: {:>5} | P: {:>3}",
                 i + 1,
                 chapter.name,
                 chapter.start,
                 chapter.end,
                 crf_str,
+                target_quality_str,
+                target_percentile_str,
                 width_name = max_name,
                 width_start = max_start,
                 width_end = max_end
@@ -165,12 +279,33 @@ impl fmt::Display for ZoneChapters {
 }
 
 impl ZoneChapters {
-    /// Basic conversion from Chapters to ZoneChapters without CRF values
-    pub fn from_chapters(video: &VideoNode, chapters: Chapters) -> Self {
+    /// Sorted starts and ends of the chapters that zone something, the frames where a scene
+    /// has to be cut so it belongs to a single chapter
+    pub fn zone_boundaries(&self) -> Vec<u32> {
+        let mut boundaries: Vec<u32> = self
+            .chapters
+            .iter()
+            .filter(|chapter| {
+                !chapter.crf.is_nan()
+                    || chapter.target_quality.is_some()
+                    || chapter.target_percentile.is_some()
+            })
+            .flat_map(|chapter| [chapter.start, chapter.end])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Basic conversion from Chapters to ZoneChapters without CRF values.
+    /// `frame_offset` is the first source frame of `video` (trim start), chapters are shifted
+    /// to the trimmed clip and the ones outside of it are dropped
+    pub fn from_chapters(video: &VideoNode, chapters: Chapters, frame_offset: u32) -> Self {
         let info = video.info();
         let fps_num = info.fps_num as f64;
         let fps_den = info.fps_den as f64;
         let fps = fps_num / fps_den;
+        let num_frames: u32 = info.num_frames.try_into().unwrap();
 
         let mut zone_chapters = Vec::new();
         let chapter_atoms = chapters.edition_entry.chapters;
@@ -186,7 +321,7 @@ impl ZoneChapters {
                 Self::time_to_frame(&chapter_atoms[i + 1].time_start, fps)
             } else {
                 // For the last chapter, use the total frames
-                info.num_frames.try_into().unwrap()
+                frame_offset + num_frames
             };
 
             if end_frame < start_frame {
@@ -196,11 +331,19 @@ impl ZoneChapters {
                 );
             }
 
+            let start_frame = start_frame.saturating_sub(frame_offset).min(num_frames);
+            let end_frame = end_frame.saturating_sub(frame_offset).min(num_frames);
+            if start_frame == end_frame {
+                continue;
+            }
+
             zone_chapters.push(ZoneChapter {
                 name: current_chapter.display.string.clone(),
                 start: start_frame,
                 end: end_frame,
                 crf: f64::NAN,
+                target_quality: None,
+                target_percentile: None,
             });
         }
 
@@ -209,29 +352,50 @@ impl ZoneChapters {
         }
     }
 
-    /// Adds CRF values to existing ZoneChapters based on the CRF string
+    /// Adds CRF values or target qualities to existing ZoneChapters based on the zoning string
     pub fn with_crfs(&mut self, crfs: String) {
         if crfs.is_empty() {
             return;
         }
 
-        // Parse CRF values from the string in format "Chapter:CRF,Chapter:CRF"
+        // Parse values from the string in format "Chapter:CRF,Chapter:tq=TARGET"
         let mut crf_map = HashMap::new();
+        let mut target_quality_map = HashMap::new();
         for pair in crfs.split(',') {
             let parts: Vec<&str> = pair.split(':').collect();
             if parts.len() == 2 {
                 let chapter_name = parts[0].trim();
-                if let Ok(crf_value) = parts[1].trim().parse::<f64>() {
+                let value = parts[1].trim();
+                if let Some(target_quality) = value.strip_prefix("tq=") {
+                    if let Ok(target_quality) = target_quality.trim().parse::<f64>() {
+                        target_quality_map.insert(chapter_name.to_string(), target_quality);
+                    }
+                } else if let Ok(crf_value) = value.parse::<f64>() {
                     crf_map.insert(chapter_name.to_string(), crf_value);
                 }
             }
         }
 
-        // Apply CRF values to matching chapters
+        // Apply the values to matching chapters
         for zone_chapter in &mut self.chapters {
             if let Some(crf) = crf_map.get(&zone_chapter.name) {
                 zone_chapter.crf = *crf;
             }
+            if let Some(target_quality) = target_quality_map.get(&zone_chapter.name) {
+                zone_chapter.target_quality = Some(*target_quality);
+            }
+        }
+    }
+
+    /// Sets the target percentiles of the chapters named in `percentiles`
+    pub fn with_percentiles(&mut self, percentiles: &[(String, u8)]) {
+        for zone_chapter in &mut self.chapters {
+            if let Some((_, percentile)) = percentiles
+                .iter()
+                .find(|(name, _)| *name == zone_chapter.name)
+            {
+                zone_chapter.target_percentile = Some(*percentile);
+            }
         }
     }
 
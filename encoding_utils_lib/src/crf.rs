@@ -1,41 +1,138 @@
 use eyre::{Context, Result, eyre};
+use std::{fs, path::Path};
 
-/// Enhanced CRF parser that enforces strictly descending values
+/// Direction the CRF values must follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrfOrder {
+    Ascending,
+    Descending,
+}
+
+/// Enhanced CRF parser that enforces strictly ascending or descending values
 /// Supported formats:
 /// - Single values (35 or 35.5) → [35.0] or [35.5]
 /// - Comma-separated lists (35,27.2,21) → [35.0, 27.2, 21.0]
 /// - Backward ranges (36..21) → [36.0, 35.0, ..., 21.0]
 /// - Stepped backward ranges (36..21:1.5) → [36.0, 34.5, 33.0, ..., 21.0]
-pub fn crf_parser(s: &str) -> Result<Vec<f64>> {
+/// - Forward ranges (21..36) → [21.0, 22.0, ..., 36.0]
+/// - Stepped forward ranges (21..36:1.5) → [21.0, 22.5, 24.0, ..., 36.0]
+/// - Logarithmic ranges (36..21:log:6) → [36.0, 32.3, 29.0, 26.1, 23.4, 21.0]
+/// - File reference (@ladder.txt) → every line parsed with the formats above and
+///   concatenated, `#` starts a comment
+pub fn crf_parser(s: &str, order: CrfOrder) -> Result<Vec<f64>> {
     // Parse the raw values first
-    let values = parse_raw_crf_values(s)?;
+    let values = match s.strip_prefix('@') {
+        Some(path) => parse_crf_file(Path::new(path))?,
+        None => parse_raw_crf_values(s)?,
+    };
 
-    // Validate descending order
-    validate_descending(&values).wrap_err_with(|| {
-        format!("CRF values must be in strictly descending order (got {values:?})")
-    })?;
+    match order {
+        CrfOrder::Descending => validate_descending(&values).wrap_err_with(|| {
+            format!("CRF values must be in strictly descending order (got {values:?})")
+        })?,
+        CrfOrder::Ascending => validate_ascending(&values).wrap_err_with(|| {
+            format!("CRF values must be in strictly ascending order (got {values:?})")
+        })?,
+    }
 
     Ok(values)
 }
 
-/// Core parsing logic
-pub fn parse_raw_crf_values(s: &str) -> Result<Vec<f64>> {
-    const CRF_RANGE: std::ops::RangeInclusive<f64> = 1.0..=70.0;
-
-    let validate_crf = |value: f64| {
-        if !CRF_RANGE.contains(&value) {
-            Err(eyre!(
-                "CRF must be between {}-{} (got {})",
-                CRF_RANGE.start(),
-                CRF_RANGE.end(),
-                value
-            ))
-        } else {
-            Ok(value)
-        }
+/// Expands a CRF search `X:floor` or `X:floor:step` into the descending ladder
+/// X, X - step, ... down to floor (step 1 by default, the floor is always the last value),
+/// e.g. 35:30 → [35.0, 34.0, ..., 30.0], 35:30:2 → [35.0, 33.0, 31.0, 30.0]
+pub fn parse_crf_search(s: &str) -> Result<Vec<f64>> {
+    let mut parts = s.split(':');
+    let (Some(start_str), Some(floor_str)) = (parts.next(), parts.next()) else {
+        return Err(eyre!(
+            "CRF search must be X:floor or X:floor:step (got '{s}')"
+        ));
+    };
+    let step_str = parts.next();
+    if parts.next().is_some() {
+        return Err(eyre!(
+            "CRF search must be X:floor or X:floor:step (got '{s}')"
+        ));
+    }
+
+    let start: f64 = start_str
+        .trim()
+        .parse()
+        .wrap_err_with(|| format!("Invalid search start: '{start_str}'"))?;
+    let floor: f64 = floor_str
+        .trim()
+        .parse()
+        .wrap_err_with(|| format!("Invalid search floor: '{floor_str}'"))?;
+    let step: f64 = match step_str {
+        Some(step_str) => step_str
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("Invalid step value: '{step_str}'"))?,
+        None => 1.0,
     };
 
-    // Handle stepped ranges (36..21:1.5 or 36.0..21.0:1.5)
+    if floor >= start {
+        return Err(eyre!(
+            "CRF search floor must be below the start CRF (got {start}:{floor})"
+        ));
+    }
+    if step <= 0.0 {
+        return Err(eyre!("Step value must be positive"));
+    }
+
+    let mut values = crf_parser(&format!("{start}..{floor}:{step}"), CrfOrder::Descending)?;
+    // A step that doesn't divide the range still ends at the floor
+    if values.last().is_some_and(|&last| last > floor) {
+        values.push(floor);
+    }
+
+    Ok(values)
+}
+
+/// Parses a CRF ladder file, one value, list or range per line
+pub fn parse_crf_file(path: &Path) -> Result<Vec<f64>> {
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read CRF file '{}'", path.display()))?;
+
+    let mut values = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        values.extend(
+            parse_raw_crf_values(line)
+                .wrap_err_with(|| format!("{}:{}: invalid CRF line", path.display(), i + 1))?,
+        );
+    }
+
+    if values.is_empty() {
+        return Err(eyre!("CRF file '{}' has no values", path.display()));
+    }
+
+    Ok(values)
+}
+
+/// CRFs accepted by the parsers and in scene files
+pub const CRF_RANGE: std::ops::RangeInclusive<f64> = 1.0..=70.0;
+
+/// Returns `value` if it's within [`CRF_RANGE`]
+pub fn validate_crf(value: f64) -> Result<f64> {
+    if !CRF_RANGE.contains(&value) {
+        Err(eyre!(
+            "CRF must be between {}-{} (got {})",
+            CRF_RANGE.start(),
+            CRF_RANGE.end(),
+            value
+        ))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Core parsing logic
+pub fn parse_raw_crf_values(s: &str) -> Result<Vec<f64>> {
+    // Handle stepped ranges (36..21:1.5, 21..36:1.5 or 36.0..21.0:1.5)
     if let Some((range_part, step_str)) = s.split_once(':')
         && let Some((start_str, end_str)) = range_part.split_once("..")
     {
@@ -45,31 +142,32 @@ pub fn parse_raw_crf_values(s: &str) -> Result<Vec<f64>> {
         let end: f64 = end_str
             .parse()
             .wrap_err_with(|| format!("Invalid range end: '{end_str}'"))?;
+
+        // Handle logarithmic ranges (36..21:log:6)
+        if let Some(count_str) = step_str.strip_prefix("log:") {
+            let count: usize = count_str
+                .parse()
+                .wrap_err_with(|| format!("Invalid value count: '{count_str}'"))?;
+            let start = validate_crf(start)?;
+            let end = validate_crf(end)?;
+            return log_range_values(start, end, count);
+        }
+
         let step: f64 = step_str
             .parse()
             .wrap_err_with(|| format!("Invalid step value: '{step_str}'"))?;
 
-        if start < end {
-            return Err(eyre!(
-                "Backward range requires start >= end (got {start}..{end})"
-            ));
-        }
         if step <= 0.0 {
             return Err(eyre!("Step value must be positive"));
         }
 
-        let mut values = Vec::new();
-        let mut current = start;
-        while current >= end {
-            values.push(validate_crf(current)?);
-            current -= step;
-            // Handle floating point precision issues by rounding
-            current = (current * 1000.0).round() / 1000.0;
-        }
-        return Ok(values);
+        return range_values(start, end, step)
+            .into_iter()
+            .map(validate_crf)
+            .collect();
     }
 
-    // Handle simple ranges (36..21 or 36.0..21.0)
+    // Handle simple ranges (36..21, 21..36 or 36.0..21.0)
     if let Some((start_str, end_str)) = s.split_once("..") {
         let start: f64 = start_str
             .parse()
@@ -78,23 +176,11 @@ pub fn parse_raw_crf_values(s: &str) -> Result<Vec<f64>> {
             .parse()
             .wrap_err_with(|| format!("Invalid range end: '{end_str}'"))?;
 
-        if start < end {
-            return Err(eyre!(
-                "Backward range requires start >= end (got {start}..{end})"
-            ));
-        }
-
-        // For floating point ranges, we need to generate the sequence manually
-        let mut values = Vec::new();
-        let step = 1.0; // Default step for simple ranges
-        let mut current = start;
-        while current >= end {
-            values.push(validate_crf(current)?);
-            current -= step;
-            // Handle floating point precision issues
-            current = (current * 1000.0).round() / 1000.0;
-        }
-        return Ok(values);
+        // Default step for simple ranges
+        return range_values(start, end, 1.0)
+            .into_iter()
+            .map(validate_crf)
+            .collect();
     }
 
     // Handle comma-separated or single value
@@ -108,6 +194,68 @@ pub fn parse_raw_crf_values(s: &str) -> Result<Vec<f64>> {
         .collect()
 }
 
+/// Generates the values from `start` to `end` (inclusive), walking in whichever
+/// direction `end` lies
+fn range_values(start: f64, end: f64, step: f64) -> Vec<f64> {
+    let mut values = Vec::new();
+    let mut current = start;
+    if start >= end {
+        while current >= end {
+            values.push(current);
+            current -= step;
+            // Handle floating point precision issues by rounding
+            current = (current * 1000.0).round() / 1000.0;
+        }
+    } else {
+        while current <= end {
+            values.push(current);
+            current += step;
+            current = (current * 1000.0).round() / 1000.0;
+        }
+    }
+    values
+}
+
+/// Generates `count` values from `start` to `end` (both included) evenly spaced in log space:
+///
+/// `value_i = start * (end / start)^(i / (count - 1))`, for `i` in `0..count`
+///
+/// Every step changes the CRF by the same ratio instead of the same amount, so the
+/// high CRF end (where a point of CRF is perceptually small) gets fewer probes.
+/// Values are rounded to one decimal.
+fn log_range_values(start: f64, end: f64, count: usize) -> Result<Vec<f64>> {
+    if count < 2 {
+        return Err(eyre!(
+            "Logarithmic range needs at least 2 values (got {count})"
+        ));
+    }
+    if start == end {
+        return Err(eyre!(
+            "Logarithmic range requires start != end (got {start}..{end})"
+        ));
+    }
+
+    let ratio = end / start;
+    let last = count - 1;
+    let values: Vec<f64> = (0..count)
+        .map(|i| match i {
+            0 => start,
+            i if i == last => end,
+            i => start * ratio.powf(i as f64 / last as f64),
+        })
+        .map(|value| (value * 10.0).round() / 10.0)
+        .collect();
+
+    // Too many values for a narrow range collapse into duplicates after rounding
+    if values.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(eyre!(
+            "Logarithmic range {start}..{end} can't fit {count} distinct values at one decimal"
+        ));
+    }
+
+    Ok(values)
+}
+
 /// Validate strict descending order
 pub fn validate_descending(values: &[f64]) -> Result<()> {
     if values.windows(2).any(|pair| pair[0] <= pair[1]) {
@@ -116,3 +264,48 @@ pub fn validate_descending(values: &[f64]) -> Result<()> {
         Ok(())
     }
 }
+
+/// Validate strict ascending order
+pub fn validate_ascending(values: &[f64]) -> Result<()> {
+    if values.windows(2).any(|pair| pair[0] >= pair[1]) {
+        Err(eyre!("Sequence contains non-ascending values"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_range_endpoints_and_count() {
+        for (start, end) in [(36.0, 21.0), (21.0, 36.0), (50.5, 10.2), (1.0, 70.0)] {
+            for count in [2, 3, 6, 10] {
+                let values = log_range_values(start, end, count).unwrap();
+                assert_eq!(values.len(), count, "{start}..{end}:log:{count}");
+                assert_eq!(values.first(), Some(&start), "{start}..{end}:log:{count}");
+                assert_eq!(values.last(), Some(&end), "{start}..{end}:log:{count}");
+            }
+        }
+
+        assert_eq!(
+            crf_parser("36..21:log:6", CrfOrder::Descending).unwrap(),
+            [36.0, 32.3, 29.0, 26.1, 23.4, 21.0]
+        );
+    }
+
+    #[test]
+    fn log_range_rejections() {
+        assert!(log_range_values(36.0, 21.0, 0).is_err());
+        assert!(log_range_values(36.0, 21.0, 1).is_err());
+        assert!(log_range_values(30.0, 30.0, 6).is_err());
+        // More values than one decimal can tell apart
+        assert!(log_range_values(30.0, 30.5, 10).is_err());
+
+        // A range walking the other way than the ladder has to
+        assert!(crf_parser("21..36:log:6", CrfOrder::Descending).is_err());
+        assert!(crf_parser("36..21:log:6", CrfOrder::Ascending).is_err());
+        assert!(crf_parser("21..36:log:6", CrfOrder::Ascending).is_ok());
+    }
+}
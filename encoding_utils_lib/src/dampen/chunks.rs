@@ -1,8 +1,8 @@
-use eyre::Result;
+use eyre::{Result, eyre};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, path::Path};
 
-use crate::dampen::dampen_loop::SceneSizeList;
+use crate::{dampen::dampen_loop::SceneSizeList, scenes::SceneList, vapoursynth::write_atomic};
 
 #[derive(Debug, Serialize, Deserialize)]
 // #[serde(rename_all = "snake_case")]
@@ -213,6 +213,57 @@ impl ChunkList {
         Ok(())
     }
 
+    /// Checks that every scene of `scene_list` (indexed) has the chunk of the same index
+    /// with the same frame range, so the sizes of the chunks can be matched to the scenes
+    pub fn validate_against(&self, scene_list: &SceneList) -> Result<()> {
+        const HINT: &str = "The encode has to be made with this scene file (av1an --scenes) and without extra splits";
+
+        if self.chunks.len() != scene_list.split_scenes.len() {
+            return Err(eyre!(
+                "The scene file has {} scenes but chunks.json has {} chunks. {HINT}",
+                scene_list.split_scenes.len(),
+                self.chunks.len()
+            ));
+        }
+
+        let chunks: HashMap<u32, &Chunk> = self
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.index, chunk))
+            .collect();
+        let mut mismatches = Vec::new();
+        for scene in &scene_list.split_scenes {
+            match chunks.get(&scene.index) {
+                None => mismatches.push(format!(
+                    "scene {} (frames {}-{}) has no chunk",
+                    scene.index, scene.start_frame, scene.end_frame
+                )),
+                Some(chunk)
+                    if chunk.start_frame != scene.start_frame
+                        || chunk.end_frame != scene.end_frame =>
+                {
+                    mismatches.push(format!(
+                        "scene {} covers frames {}-{} but its chunk covers {}-{}",
+                        scene.index,
+                        scene.start_frame,
+                        scene.end_frame,
+                        chunk.start_frame,
+                        chunk.end_frame
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+
+        if let Some(first) = mismatches.first() {
+            return Err(eyre!(
+                "The scene file and chunks.json don't match: {first} ({} mismatched scenes). {HINT}",
+                mismatches.len()
+            ));
+        }
+        Ok(())
+    }
+
     pub fn parse_chunks_file(json_path: &Path) -> Result<ChunkList> {
         let json_data = fs::read_to_string(json_path)?;
         let chunks: Vec<Chunk> = serde_json::from_str(&json_data)?;
@@ -221,7 +272,7 @@ impl ChunkList {
 
     pub fn write_chunks_to_file<'a>(&self, path: &'a Path) -> Result<&'a Path> {
         let json = serde_json::to_string_pretty(&self.chunks)?; // pretty format for readability
-        fs::write(path, json)?;
+        write_atomic(path, json)?;
         Ok(path)
     }
 }
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::{self};
 use std::path::{Path, PathBuf};
 
@@ -5,11 +6,13 @@ use crate::dampen::chunks::ChunkList;
 use crate::dampen::done::Done;
 use crate::encode::resume_encode;
 use crate::scenes::SceneList;
+use crate::vapoursynth::write_atomic;
 use bytesize::ByteSize;
-use eyre::{Context, OptionExt, Result};
+use eyre::{Context, OptionExt, Result, eyre};
 use fs_extra::dir::{CopyOptions, copy};
 use fs_extra::file::{CopyOptions as FileCopyOptions, copy as copy_file};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 #[allow(clippy::too_many_arguments)]
 pub fn dampen_loop<'a>(
@@ -19,16 +22,17 @@ pub fn dampen_loop<'a>(
     scene_dampened: &'a Path,
     av1an_params: &'a str,
     crfs: &[f64],
-    size_threshold: ByteSize,
+    size_threshold: SizeThreshold,
     velocity_input: Option<&'a Path>,
     velocity_preset: i32,
     crf_data_file: Option<&'a Path>,
     temp_folder: &'a Path,
     backup: bool,
     keep_files: bool,
+    reclaim: bool,
 ) -> Result<&'a Path> {
     println!("\nRunning size-dampener\n");
-    println!("Size Threshold: {:3.2}", size_threshold.display());
+    println!("Size Threshold: {size_threshold}");
 
     let size_folder = temp_folder.join("size_dampener");
     fs::create_dir_all(&size_folder)?;
@@ -42,8 +46,16 @@ pub fn dampen_loop<'a>(
     let done_path = temp_folder.join("done.json");
     let chunks_path = temp_folder.join("chunks.json");
     let encode_scenes_path = temp_folder.join("encode");
+    let scene_sizes_path = size_folder.join("scene_sizes.json");
 
-    if backup {
+    // A previous run was interrupted, chunks.json and the encodes are already modified
+    let resumed_state = if scene_sizes_path.exists() {
+        Some(SceneSizeState::parse_state_file(&scene_sizes_path)?)
+    } else {
+        None
+    };
+
+    if backup && resumed_state.is_none() {
         // BackUp paths
         let done_backup = size_folder.join("done_backup.json");
         let chunks_backup = size_folder.join("chunks_backup.json");
@@ -87,6 +99,7 @@ pub fn dampen_loop<'a>(
     // Load state files
     let mut done = Done::parse_done_file(&done_path)?;
     let mut chunk_list = ChunkList::parse_chunks_file(&chunks_path)?;
+    chunk_list.validate_against(&scene_list)?;
 
     // Process CRF values
     // Process CRF values
@@ -101,25 +114,56 @@ pub fn dampen_loop<'a>(
         .collect::<Vec<f64>>();
 
     // Initialize scene size tracking
-    let mut scene_sizes = SceneSizeList::new(
-        encode_scenes_path,
-        &chunk_list,
-        size_threshold,
-        max_crf,
-        crfs,
-    )?;
+    let (mut scene_sizes, mut iteration) = match resumed_state {
+        Some(state) => {
+            println!(
+                "Resuming from {} at iteration {}",
+                scene_sizes_path.display(),
+                state.iteration
+            );
+            let iteration = state.iteration;
+            let scene_sizes = state
+                .into_scene_size_list(
+                    encode_scenes_path,
+                    &chunk_list,
+                    size_threshold,
+                    max_crf,
+                    crfs,
+                )
+                .wrap_err_with(|| {
+                    format!(
+                        "Can't resume from {}, delete it to start over",
+                        scene_sizes_path.display()
+                    )
+                })?;
+            (scene_sizes, iteration)
+        }
+        None => {
+            let scene_sizes = SceneSizeList::new(
+                encode_scenes_path,
+                &chunk_list,
+                size_threshold,
+                max_crf,
+                crfs,
+                reclaim,
+            )?;
+
+            // Early exit if all scenes meet threshold
+            if !scene_sizes.is_not_ready() {
+                println!("ALL SCENES BELOW THE SIZE THRESHOLD");
+                return Ok(scene_dampened);
+            }
 
-    // Early exit if all scenes meet threshold
-    if !scene_sizes.is_not_ready() {
-        println!("ALL SCENES BELOW THE SIZE THRESHOLD");
-        return Ok(scene_dampened);
-    }
+            SceneSizeState::from_scene_size_list(&scene_sizes, 0)
+                .write_state_file(&scene_sizes_path)?;
+            (scene_sizes, 0)
+        }
+    };
 
     // Change preset
     chunk_list.update_preset_from_scene_sizes(&scene_sizes, velocity_preset)?;
 
     // Main processing loop
-    let mut iteration = 0;
     while scene_sizes.is_not_ready() {
         println!("\n\n=== Iteration {} ===", iteration);
 
@@ -166,6 +210,9 @@ pub fn dampen_loop<'a>(
         }
 
         iteration += 1;
+
+        SceneSizeState::from_scene_size_list(&scene_sizes, iteration)
+            .write_state_file(&scene_sizes_path)?;
     }
 
     // Restore original preset
@@ -187,9 +234,10 @@ pub fn dampen_loop<'a>(
     )?;
 
     // Final status report
-    scene_sizes.update_sizes()?;
+    scene_sizes.update_final_sizes()?;
     scene_sizes.update_crfs();
     scene_sizes.print_updated_scenes();
+    scene_sizes.print_summary();
 
     // Output new scene.json file
     scene_list.update_crfs_from_sizes(&scene_sizes)?;
@@ -197,9 +245,132 @@ pub fn dampen_loop<'a>(
     scene_list.write_scene_list_to_file(scene_dampened)?;
     scene_list.write_crf_data(crf_data_file, input, None, false)?;
 
+    // Dampening finished, a new run starts from scratch
+    fs::remove_file(&scene_sizes_path)?;
+
     Ok(scene_dampened)
 }
 
+/// Dampener state saved after every iteration, so an interrupted run can resume
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SceneSizeState {
+    pub iteration: u32,
+    pub scenes: Vec<SceneSizeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SceneSizeEntry {
+    pub index: u32,
+    pub original_size: u64,
+    pub new_size: u64,
+    pub original_crf: f64,
+    pub new_crf: f64,
+    pub original_preset: i32,
+    pub size_threshold: u64,
+    pub ready: bool,
+    #[serde(default)]
+    pub reclaiming: bool,
+}
+
+impl SceneSizeState {
+    pub fn from_scene_size_list(scene_sizes: &SceneSizeList, iteration: u32) -> SceneSizeState {
+        let scenes = scene_sizes
+            .scenes
+            .iter()
+            .map(|scene| SceneSizeEntry {
+                index: scene.index,
+                original_size: scene.original_size.as_u64(),
+                new_size: scene.new_size.as_u64(),
+                original_crf: scene.original_crf,
+                new_crf: scene.new_crf,
+                original_preset: scene.original_preset,
+                size_threshold: scene.size_threshold.as_u64(),
+                ready: scene.ready,
+                reclaiming: scene.reclaiming,
+            })
+            .collect();
+
+        SceneSizeState { iteration, scenes }
+    }
+
+    /// Errors out if the saved scenes aren't the chunks of `chunk_list`, or if their
+    /// thresholds differ from the ones `size_threshold` gives now
+    pub fn into_scene_size_list(
+        self,
+        scenes_path: PathBuf,
+        chunk_list: &ChunkList,
+        size_threshold: SizeThreshold,
+        max_crf: f64,
+        crfs: Vec<f64>,
+    ) -> Result<SceneSizeList> {
+        let chunk_thresholds = size_threshold.chunk_thresholds(chunk_list);
+
+        let saved_indexes: HashSet<u32> = self.scenes.iter().map(|scene| scene.index).collect();
+        let missing = chunk_thresholds
+            .keys()
+            .filter(|index| !saved_indexes.contains(index))
+            .count();
+        if missing > 0 || saved_indexes.len() != self.scenes.len() {
+            return Err(eyre!(
+                "The saved state has {} scenes but chunks.json has {} chunks",
+                self.scenes.len(),
+                chunk_list.chunks.len()
+            ));
+        }
+
+        let scenes = self
+            .scenes
+            .into_iter()
+            .map(|scene| {
+                let threshold = *chunk_thresholds.get(&scene.index).ok_or_else(|| {
+                    eyre!("Scene {} isn't in chunks.json", scene.index)
+                })?;
+                if threshold.as_u64() != scene.size_threshold {
+                    return Err(eyre!(
+                        "Scene {} was dampened to {:3.2} but the size threshold ({size_threshold}) gives {:3.2}",
+                        scene.index,
+                        ByteSize(scene.size_threshold).display(),
+                        threshold.display()
+                    ));
+                }
+
+                Ok(SceneSize {
+                    index: scene.index,
+                    original_size: ByteSize(scene.original_size),
+                    new_size: ByteSize(scene.new_size),
+                    original_crf: scene.original_crf,
+                    new_crf: scene.new_crf,
+                    original_preset: scene.original_preset,
+                    size_threshold: threshold,
+                    ready: scene.ready,
+                    reclaiming: scene.reclaiming,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(SceneSizeList {
+            scenes_path,
+            scenes,
+            max_crf,
+            crfs,
+        })
+    }
+
+    pub fn parse_state_file(json_path: &Path) -> Result<SceneSizeState> {
+        let json_data = fs::read_to_string(json_path)
+            .wrap_err_with(|| format!("Failed to read {}", json_path.display()))?;
+        let state: SceneSizeState = serde_json::from_str(&json_data)?;
+        Ok(state)
+    }
+
+    /// Rewritten every iteration, atomically so a crash can't leave a truncated state behind
+    pub fn write_state_file<'a>(&self, path: &'a Path) -> Result<&'a Path> {
+        let json = serde_json::to_string_pretty(&self)?;
+        write_atomic(path, json)?;
+        Ok(path)
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct SceneSize {
     pub index: u32,
@@ -208,14 +379,148 @@ pub struct SceneSize {
     pub original_crf: f64,
     pub new_crf: f64,
     pub original_preset: i32,
+    pub size_threshold: ByteSize,
     pub ready: bool,
+    /// Under `RECLAIM_RATIO` of the threshold, trying lower CRFs to spend the spare bits
+    pub reclaiming: bool,
+}
+
+/// Scenes under this share of their threshold try lower CRFs with `--reclaim`
+pub const RECLAIM_RATIO: f64 = 0.6;
+
+impl SceneSize {
+    fn reclaimable(&self) -> bool {
+        (self.new_size.as_u64() as f64) <= self.size_threshold.as_u64() as f64 * RECLAIM_RATIO
+    }
+
+    /// Next step of a reclaiming scene: one CRF lower while it stays well under the
+    /// threshold, back to the last CRF that fit as soon as it goes over
+    fn reclaim_step(&mut self, crfs: &[f64]) {
+        if self.new_size > self.size_threshold {
+            let last_fit = crfs
+                .iter()
+                .copied()
+                .find(|&crf| crf > self.new_crf && crf < self.original_crf);
+            match last_fit {
+                Some(crf) => self.new_crf = crf,
+                None => {
+                    self.new_crf = self.original_crf;
+                    self.new_size = self.original_size;
+                }
+            }
+            self.ready = true;
+        } else if let Some(lower_crf) = crfs
+            .iter()
+            .copied()
+            .rev()
+            .find(|&crf| crf < self.new_crf)
+            .filter(|_| self.reclaimable())
+        {
+            self.new_crf = lower_crf;
+        } else {
+            self.ready = true;
+        }
+
+        if self.ready {
+            self.reclaiming = false;
+        }
+    }
+}
+
+/// How the size threshold of each scene is obtained
+#[derive(Debug, Clone, Copy)]
+pub enum SizeThreshold {
+    /// Same threshold for every scene
+    PerScene(ByteSize),
+    /// Budget for the whole encode, split across scenes by frame count.
+    /// No scene gets less than `min_scene_size`
+    Total {
+        budget: ByteSize,
+        min_scene_size: ByteSize,
+    },
+}
+
+impl std::fmt::Display for SizeThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SizeThreshold::PerScene(size) => write!(f, "{:3.2} per scene", size.display()),
+            SizeThreshold::Total {
+                budget,
+                min_scene_size,
+            } => write!(
+                f,
+                "{:3.2} total (min {:3.2} per scene)",
+                budget.display(),
+                min_scene_size.display()
+            ),
+        }
+    }
+}
+
+impl SizeThreshold {
+    /// Threshold of every chunk, indexed like `chunk_list.chunks`
+    pub fn chunk_thresholds(&self, chunk_list: &ChunkList) -> HashMap<u32, ByteSize> {
+        let (budget, min_scene_size) = match *self {
+            SizeThreshold::PerScene(size) => {
+                return chunk_list
+                    .chunks
+                    .iter()
+                    .map(|chunk| (chunk.index, size))
+                    .collect();
+            }
+            SizeThreshold::Total {
+                budget,
+                min_scene_size,
+            } => (budget.as_u64(), min_scene_size.as_u64()),
+        };
+
+        // Scenes whose share falls below the floor get the floor, the rest of the
+        // budget is split again among the remaining scenes until no share changes
+        let mut floored: HashMap<u32, u64> = HashMap::new();
+        loop {
+            let remaining_budget = budget.saturating_sub(floored.values().sum());
+            let remaining_frames: u64 = chunk_list
+                .chunks
+                .iter()
+                .filter(|chunk| !floored.contains_key(&chunk.index))
+                .map(|chunk| (chunk.end_frame - chunk.start_frame) as u64)
+                .sum();
+
+            let shares: HashMap<u32, u64> = chunk_list
+                .chunks
+                .iter()
+                .filter(|chunk| !floored.contains_key(&chunk.index))
+                .map(|chunk| {
+                    let frames = (chunk.end_frame - chunk.start_frame) as u64;
+                    let share = (remaining_budget as u128 * frames as u128
+                        / remaining_frames.max(1) as u128) as u64;
+                    (chunk.index, share)
+                })
+                .collect();
+
+            let below_floor: Vec<u32> = shares
+                .iter()
+                .filter(|(_, share)| **share < min_scene_size)
+                .map(|(index, _)| *index)
+                .collect();
+
+            if below_floor.is_empty() {
+                return shares
+                    .into_iter()
+                    .chain(floored)
+                    .map(|(index, size)| (index, ByteSize(size)))
+                    .collect();
+            }
+
+            floored.extend(below_floor.into_iter().map(|index| (index, min_scene_size)));
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct SceneSizeList {
     pub scenes_path: PathBuf,
     pub scenes: Vec<SceneSize>,
-    pub size_threshold: ByteSize,
     pub max_crf: f64,
     pub crfs: Vec<f64>,
 }
@@ -224,11 +529,13 @@ impl SceneSizeList {
     pub fn new(
         scenes_path: PathBuf,
         chunk_list: &ChunkList,
-        size_threshold: ByteSize,
+        size_threshold: SizeThreshold,
         max_crf: f64,
         crfs: Vec<f64>,
+        reclaim: bool,
     ) -> eyre::Result<SceneSizeList> {
         let mut result = Vec::new();
+        let chunk_thresholds = size_threshold.chunk_thresholds(chunk_list);
 
         for entry in fs::read_dir(&scenes_path)? {
             let entry = entry?;
@@ -271,6 +578,8 @@ impl SceneSizeList {
             // println!("Size: {size}");
             // println!("Size Threshold: {size_threshold}");
 
+            let size_threshold = *chunk_thresholds.get(&index).ok_or_eyre("Scene not found")?;
+
             let ready = original_size <= size_threshold || original_crf >= max_crf;
 
             // let new_crf = crfs
@@ -281,7 +590,7 @@ impl SceneSizeList {
 
             let new_crf = if ready { original_crf } else { max_crf };
 
-            let scene_size = SceneSize {
+            let mut scene_size = SceneSize {
                 index,
                 original_size,
                 new_size: original_size,
@@ -289,20 +598,40 @@ impl SceneSizeList {
                 new_crf,
                 ready,
                 original_preset,
+                size_threshold,
+                reclaiming: false,
             };
+
+            // Scenes far under the threshold spend some bits on a lower CRF of the ladder
+            if reclaim
+                && scene_size.reclaimable()
+                && let Some(lower_crf) = crfs.iter().copied().rev().find(|&crf| crf < original_crf)
+            {
+                scene_size.new_crf = lower_crf;
+                scene_size.ready = false;
+                scene_size.reclaiming = true;
+            }
             result.push(scene_size);
         }
 
         Ok(SceneSizeList {
             scenes: result,
             scenes_path,
-            size_threshold,
             max_crf,
             crfs,
         })
     }
 
     pub fn update_sizes(&mut self) -> eyre::Result<()> {
+        self.read_sizes(false)
+    }
+
+    /// Sizes of every scene after the final encode, which used the original presets again
+    pub fn update_final_sizes(&mut self) -> eyre::Result<()> {
+        self.read_sizes(true)
+    }
+
+    fn read_sizes(&mut self, include_ready: bool) -> eyre::Result<()> {
         for entry in fs::read_dir(&self.scenes_path)? {
             let entry = entry?;
             let path = entry.path();
@@ -327,7 +656,7 @@ impl SceneSizeList {
 
             // Find matching scene and update its size
             if let Some(scene) = self.scenes.iter_mut().find(|s| s.index == index)
-                && !scene.ready
+                && (include_ready || !scene.ready)
             {
                 scene.new_size = size;
             }
@@ -347,8 +676,13 @@ impl SceneSizeList {
                 continue;
             }
 
+            if scene.reclaiming {
+                scene.reclaim_step(&self.crfs);
+                continue;
+            }
+
             // If current size is still over threshold, try a higher CRF
-            if scene.new_size > self.size_threshold {
+            if scene.new_size > scene.size_threshold {
                 // Find the next higher CRF in the list
                 if let Some(higher_crf) =
                     self.crfs.iter().find(|&&crf| crf > scene.new_crf).copied()
@@ -379,7 +713,12 @@ impl SceneSizeList {
                 continue;
             }
 
-            if scene.new_size > self.size_threshold {
+            if scene.reclaiming {
+                scene.reclaim_step(&self.crfs);
+                continue;
+            }
+
+            if scene.new_size > scene.size_threshold {
                 // Already using max_crf and still over threshold - mark ready
                 scene.ready = true;
             } else {
@@ -435,6 +774,40 @@ impl SceneSizeList {
         println!("-----------------\n");
     }
 
+    /// Prints the total size before and after dampening, and the scenes still over their
+    /// threshold at max_crf
+    pub fn print_summary(&self) {
+        let original_total: u64 = self.scenes.iter().map(|s| s.original_size.as_u64()).sum();
+        let new_total: u64 = self.scenes.iter().map(|s| s.new_size.as_u64()).sum();
+        let saved = original_total as i128 - new_total as i128;
+        let saved_percent = if original_total > 0 {
+            saved as f64 / original_total as f64 * 100.0
+        } else {
+            0.0
+        };
+        let over_at_max_crf = self
+            .scenes
+            .iter()
+            .filter(|s| s.new_crf >= self.max_crf && s.new_size > s.size_threshold)
+            .count();
+
+        println!("Summary:");
+        println!("-----------------");
+        println!(
+            "Total size: {:3.2} → {:3.2} ({}{:3.2}, {:.2}% saved)",
+            ByteSize(original_total).display(),
+            ByteSize(new_total).display(),
+            if saved < 0 { "+" } else { "-" },
+            ByteSize(saved.unsigned_abs() as u64).display(),
+            saved_percent
+        );
+        println!(
+            "Scenes at max CRF {:.2} still over the threshold: {over_at_max_crf}",
+            self.max_crf
+        );
+        println!("-----------------\n");
+    }
+
     /// Prints scenes that changed after updates
     pub fn print_updated_scenes(&self) {
         println!("\n\nFinal - Updated Scenes:");
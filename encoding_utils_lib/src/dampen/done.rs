@@ -2,7 +2,7 @@ use eyre::Result;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, path::Path};
 
-use crate::dampen::dampen_loop::SceneSizeList;
+use crate::{dampen::dampen_loop::SceneSizeList, vapoursynth::write_atomic};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FrameInfo {
@@ -39,7 +39,10 @@ impl Done {
     }
 
     /// Updates the Done struct based on the SceneSizeList, removing entries for scenes that were modified
-    pub fn update_from_modified_scene_sizes(&mut self, scene_sizes: &SceneSizeList) -> eyre::Result<()> {
+    pub fn update_from_modified_scene_sizes(
+        &mut self,
+        scene_sizes: &SceneSizeList,
+    ) -> eyre::Result<()> {
         // First collect all unmodified scene indices as strings with leading zeros
         let unmodified_scenes: std::collections::HashSet<String> = scene_sizes
             .scenes
@@ -63,7 +66,7 @@ impl Done {
 
     pub fn write_done_to_file<'a>(&self, path: &'a Path) -> Result<&'a Path> {
         let json = serde_json::to_string_pretty(&self)?; // pretty format for readability
-        fs::write(path, json)?;
+        write_atomic(path, json)?;
         Ok(path)
     }
 }
@@ -1,10 +1,16 @@
 use std::{
+    fmt,
     fs::{self, create_dir_all},
     path::Path,
-    process::{Command, Stdio},
 };
 
-use eyre::{OptionExt, Result};
+use bytesize::ByteSize;
+use eyre::{OptionExt, Result, eyre};
+
+use crate::{
+    binaries::{av1an_command, run_av1an},
+    scenes::SceneList,
+};
 
 pub fn encode_frames<'a>(
     input: &'a Path,
@@ -63,11 +69,9 @@ pub fn encode_frames<'a>(
     println!("{:?}", args.join(" "));
     println!();
 
-    Command::new("av1an")
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()?;
+    let mut command = av1an_command();
+    command.args(args);
+    run_av1an(command, &encode_path.with_extension("log"))?;
 
     Ok(encode_path)
 }
@@ -120,11 +124,110 @@ pub fn resume_encode<'a>(
     println!("{:?}", args.join(" "));
     println!();
 
-    Command::new("av1an")
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()?;
+    let mut command = av1an_command();
+    command.args(args);
+    run_av1an(command, &encode_path.with_extension("log"))?;
 
     Ok(encode_path)
 }
+
+/// IVF file header and per frame header sizes, not part of the AV1 bitstream
+const IVF_HEADER_SIZE: u64 = 32;
+const IVF_FRAME_HEADER_SIZE: u64 = 12;
+
+/// Predicted size of a scene, extrapolated from its calibration chunk
+#[derive(Debug)]
+pub struct SceneSizeEstimate {
+    pub start_frame: u32,
+    pub end_frame: u32,
+    pub crf: f64,
+    pub sample_frames: u32,
+    pub sample_size: u64,
+    pub estimated_size: u64,
+}
+
+#[derive(Debug)]
+pub struct SizeEstimate {
+    pub scenes: Vec<SceneSizeEstimate>,
+}
+
+impl SizeEstimate {
+    pub fn total_size(&self) -> u64 {
+        self.scenes.iter().map(|scene| scene.estimated_size).sum()
+    }
+}
+
+impl fmt::Display for SizeEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for scene in &self.scenes {
+            writeln!(
+                f,
+                "frames {:6}-{:6}, crf: {:5.2}, sample: {:4} frames {:>10}, estimate: {:>10}",
+                scene.start_frame,
+                scene.end_frame,
+                scene.crf,
+                scene.sample_frames,
+                ByteSize(scene.sample_size).display().to_string(),
+                ByteSize(scene.estimated_size).display().to_string()
+            )?;
+        }
+        write!(
+            f,
+            "Estimated total size: {:3.2}",
+            ByteSize(self.total_size()).display()
+        )
+    }
+}
+
+/// Estimates the size of the final encode from a calibration encode of the measured
+/// frames of every scene at its final CRF. `calibration_chunks` is the av1an `encode`
+/// folder of that encode, with a `{index:05}.ivf` chunk per scene with frames, in order.
+/// The size per frame of each chunk is extrapolated to the whole scene. The samples are
+/// short and not contiguous, so the estimate leans high. Scenes without measured frames
+/// get the mean size per frame of the others
+pub fn estimate_size(scene_list: &SceneList, calibration_chunks: &Path) -> Result<SizeEstimate> {
+    let mut chunks = 0;
+    let mut scenes = Vec::with_capacity(scene_list.split_scenes.len());
+    for scene in &scene_list.split_scenes {
+        let sample_frames = scene.frame_scores.len() as u32;
+        let sample_size = if sample_frames > 0 {
+            let chunk = calibration_chunks.join(format!("{chunks:05}.ivf"));
+            chunks += 1;
+            let size = fs::metadata(&chunk)
+                .map_err(|e| eyre!("Calibration chunk {} not found: {e}", chunk.display()))?
+                .len();
+            size.saturating_sub(IVF_HEADER_SIZE + IVF_FRAME_HEADER_SIZE * sample_frames as u64)
+        } else {
+            0
+        };
+
+        scenes.push(SceneSizeEstimate {
+            start_frame: scene.start_frame,
+            end_frame: scene.end_frame,
+            crf: scene.crf,
+            sample_frames,
+            sample_size,
+            estimated_size: 0,
+        });
+    }
+
+    let (total_sample_size, total_sample_frames) =
+        scenes.iter().fold((0, 0), |(size, frames), scene| {
+            (
+                size + scene.sample_size,
+                frames + scene.sample_frames as u64,
+            )
+        });
+    let mean_frame_size = total_sample_size as f64 / total_sample_frames.max(1) as f64;
+
+    for scene in &mut scenes {
+        let frame_size = if scene.sample_frames > 0 {
+            scene.sample_size as f64 / scene.sample_frames as f64
+        } else {
+            mean_frame_size
+        };
+        scene.estimated_size = (frame_size * (scene.end_frame - scene.start_frame) as f64) as u64;
+    }
+
+    Ok(SizeEstimate { scenes })
+}
@@ -1,16 +1,27 @@
+use std::collections::BTreeSet;
 use std::fs::{self};
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use crate::chapters::{Chapters, ZoneChapters};
-use crate::encode::encode_frames;
+use crate::encode::{encode_frames, estimate_size};
+use crate::grain::generate_photon_noise_table;
+use crate::math;
 use crate::scenes::{
-    FramesDistribution, MetricsCache, SceneDetectionMethod, SceneList, get_scene_file,
+    AggregationMode, CrfSmoothing, FramesDistribution, MetricsCache, QualityFlag,
+    SceneDetectionMethod, SceneList, ScoreTarget, ZoneOverrides, encoder_preset, get_scene_file,
+    probe_keyframes,
 };
 use crate::ssimulacra2::ssimu2_frames_selected;
-use crate::transnetv2::transnet::run_transnetv2;
-use crate::vapoursynth::{SourcePlugin, prepare_clip, seconds_to_frames};
+use crate::transnetv2::inference::{InferenceWindow, MergeDirection};
+use crate::transnetv2::onnx::ExecutionProvider;
+use crate::transnetv2::transnet::{TRANSNET_INPUT_SIZE, run_transnetv2};
+use crate::vapoursynth::{
+    DownscaleKernel, Ivtc, SourcePlugin, parse_resolution, parse_trim, prepare_clip,
+    seconds_to_frames,
+};
 use crate::vpy_files::create_vpy_file;
-use eyre::{OptionExt, Result};
+use eyre::{OptionExt, Result, eyre};
 use vapoursynth4_rs::core::Core;
 
 #[allow(clippy::too_many_arguments)]
@@ -26,22 +37,31 @@ pub fn run_frame_loop<'a>(
     n_frames: Option<u32>,
     s_frames: f64,
     frames_distribution: FramesDistribution,
+    seed: u64,
     scene_detection_method: SceneDetectionMethod,
+    scene_file_input: Option<&'a Path>,
     filter_frames: bool,
+    merge_scenes: bool,
+    dry_run: bool,
     chapters: Option<&'a Path>,
     crf_chapters: String,
     zoning_params: &'a str,
     overlap_chapters: f64,
     workers: u32,
+    metric_threads: usize,
     importer_metrics: &SourcePlugin,
     importer_encoding: &SourcePlugin,
     importer_scene: &SourcePlugin,
     crf_data_file: Option<&'a Path>,
+    curves_file: Option<&'a Path>,
+    crf_data_csv: Option<&'a Path>,
     crop: Option<&str>,
     downscale: f64,
+    downscale_kernel: DownscaleKernel,
     resize: Option<&str>,
     trim: Option<&str>,
-    detelecine: bool,
+    detelecine: Option<Ivtc>,
+    luma_only: bool,
     clean: bool,
     verbose: bool,
     verbose_verbose: bool,
@@ -55,15 +75,34 @@ pub fn run_frame_loop<'a>(
     min_scene_len: Option<i64>,
     threshold: f32,
     fade_threshold_low: f32,
+    fade_threshold_high: Option<f32>,
     min_fade_len: i64,
     merge_gap: i64,
     enable_fade_detection: bool,
     scene_predictions: bool,
     percentile: u8,
+    chapter_percentiles: &[(String, u8)],
+    aggregation: AggregationMode,
+    score_targets: &[ScoreTarget],
+    max_crf_step: Option<f64>,
+    crf_smooth: Option<usize>,
+    crf_smooth_mode: CrfSmoothing,
+    interpolate_crf: bool,
+    estimate_output_size: bool,
+    max_cycles: Option<usize>,
+    scene_stride: Option<usize>,
     hardcut_scenes: bool,
     cpu: bool,
+    vspipe_args: &[String],
+    prefilter: Option<&str>,
+    photon_noise: Option<u32>,
+    adaptive_grain: bool,
+    frame_scores: &[(f64, String)],
+    seek_safe: bool,
 ) -> Result<&'a Path> {
     println!("\nRunning frame-boost");
+    let quality_flag = QualityFlag::from_encoder(get_encoder(av1an_params).as_deref());
+    quality_flag.check_ladder(crf)?;
     let core = Core::builder().build();
 
     let scenes_folder = temp_folder.join("scenes");
@@ -78,7 +117,35 @@ pub fn run_frame_loop<'a>(
 
     let scene_path = scenes_folder.join("scenes.json");
 
-    let mut scene_list = if scene_path.exists() {
+    let mut scene_list = if let Some(scene_file_input) = scene_file_input {
+        println!("Using scene file {}\n", scene_file_input.display());
+        let scene_list = SceneList::parse_scene_file(scene_file_input)?;
+        let source = prepare_clip(
+            &core,
+            input,
+            importer_scene,
+            &indexes_folder,
+            verbose_verbose_verbose,
+            encoder_params,
+            crop,
+            trim,
+            downscale,
+            downscale_kernel,
+            detelecine,
+        )?;
+        let source_frames = source.info().num_frames as u32;
+        let last_end_frame = scene_list
+            .split_scenes
+            .last()
+            .ok_or_eyre("Scene file has no scenes")?
+            .end_frame;
+        if last_end_frame != source_frames {
+            return Err(eyre!(
+                "Scene file ends at frame {last_end_frame} but the source has {source_frames} frames"
+            ));
+        }
+        scene_list
+    } else if scene_path.exists() {
         SceneList::parse_scene_file(&scene_path)?
     } else {
         match scene_detection_method {
@@ -95,6 +162,7 @@ pub fn run_frame_loop<'a>(
                 } else {
                     scene_av1an_params
                 };
+                let scene_av1an_params = update_vspipe_args(&scene_av1an_params, vspipe_args);
 
                 let vpy_scene_path = scenes_folder.join("scene.vpy");
 
@@ -105,12 +173,16 @@ pub fn run_frame_loop<'a>(
                     importer_scene,
                     crop,
                     downscale,
+                    downscale_kernel,
                     resize,
                     trim,
                     detelecine,
                     encoder_params,
                     temp_folder,
                     clean,
+                    vspipe_args,
+                    // Only the temp encodes are prefiltered, the cuts don't depend on it
+                    None,
                 )?;
                 let original_scenes_file =
                     get_scene_file(vpy_scene_file, &scenes_folder, &scene_av1an_params, clean)?;
@@ -123,7 +195,16 @@ pub fn run_frame_loop<'a>(
                     &core,
                     input,
                     None,
-                    cpu,
+                    if cpu {
+                        ExecutionProvider::Cpu
+                    } else {
+                        ExecutionProvider::Auto
+                    },
+                    None,
+                    None,
+                    false,
+                    InferenceWindow::default(),
+                    TRANSNET_INPUT_SIZE,
                     *importer_scene,
                     &indexes_folder,
                     verbose_verbose_verbose,
@@ -137,12 +218,17 @@ pub fn run_frame_loop<'a>(
                     extra_split_frames_fades,
                     min_scene_len_sec,
                     min_scene_len,
+                    MergeDirection::default(),
+                    false,
+                    None,
                     threshold,
                     fade_threshold_low,
+                    fade_threshold_high,
                     min_fade_len,
                     merge_gap,
                     enable_fade_detection,
                     scene_predictions,
+                    false,
                 )?;
                 println!();
                 if hardcut_scenes {
@@ -163,6 +249,71 @@ pub fn run_frame_loop<'a>(
         }
     };
 
+    // Chapters used for zoning
+    let zone_chapters = load_zone_chapters(
+        &core,
+        input,
+        chapters,
+        crf_chapters,
+        chapter_percentiles,
+        importer_scene,
+        &indexes_folder,
+        verbose_verbose_verbose,
+        zoning_params,
+        crop,
+        trim,
+        downscale,
+        downscale_kernel,
+        detelecine,
+    )?;
+
+    // Each scene has to belong to a single chapter before zoning
+    if let Some(zone_chapters) = &zone_chapters {
+        scene_list.split_at_chapter_boundaries(zone_chapters);
+    }
+
+    if seek_safe {
+        if input
+            .extension()
+            .is_some_and(|extension| extension == "vpy")
+        {
+            return Err(eyre!(
+                "--seek-safe reads the keyframes of a video file, not a .vpy"
+            ));
+        }
+        if detelecine.is_some() {
+            return Err(eyre!("--seek-safe can't map source keyframes through IVTC"));
+        }
+        // Keyframes are source frames, the scenes are frames of the trimmed clip
+        let trim_start = match trim
+            .map(parse_trim)
+            .transpose()?
+            .and_then(|trim| trim.first)
+        {
+            Some(first) if first < 0 => {
+                return Err(eyre!(
+                    "--seek-safe needs a trim start counted from the beginning"
+                ));
+            }
+            Some(first) => first as u32,
+            None => 0,
+        };
+        let keyframes: Vec<u32> = probe_keyframes(input)?
+            .into_iter()
+            .filter_map(|keyframe| keyframe.checked_sub(trim_start))
+            .collect();
+        // Starts can't move back into the previous chapter, the scenes were split at them
+        let boundaries = zone_chapters
+            .as_ref()
+            .map(ZoneChapters::zone_boundaries)
+            .unwrap_or_default();
+        let moved = scene_list.snap_to_keyframes(&keyframes, &boundaries);
+        println!(
+            "Seek safe: {moved} scene starts moved to a keyframe ({} keyframes)",
+            keyframes.len()
+        );
+    }
+
     let first_crf = crf.first().unwrap();
     scene_list.assign_indexes();
     scene_list.update_crf(*first_crf);
@@ -174,12 +325,25 @@ pub fn run_frame_loop<'a>(
     let temp_av1an_params =
         update_extra_split_and_min_scene_len(&temp_av1an_params, Some(0), Some(0), Some(0));
     let temp_av1an_params = update_workers(&temp_av1an_params, workers);
+    let temp_av1an_params = update_vspipe_args(&temp_av1an_params, vspipe_args);
     let temp_encoder_params = remove_crf_param(encoder_params);
-    let temp_encoder_params = update_preset(velocity_preset, &temp_encoder_params);
+    let temp_encoder_params = update_preset(
+        velocity_preset,
+        &temp_encoder_params,
+        get_encoder(av1an_params).as_deref(),
+    );
 
     // crfs
     let crfs = crf.to_vec();
     let iter_crfs: Vec<f64> = crfs[..crfs.len().saturating_sub(1)].to_vec();
+    if let Some((crf, _)) = frame_scores
+        .iter()
+        .find(|(crf, _)| !iter_crfs.contains(crf))
+    {
+        return Err(eyre!(
+            "Frame scores given for CRF {crf}, which is not measured (CRFs: {iter_crfs:?})"
+        ));
+    }
 
     if crfs.len() == 1 {
         scene_list.update_crf(crfs[0]);
@@ -190,109 +354,214 @@ pub fn run_frame_loop<'a>(
     scene_list_frames.with_zone_overrides(&temp_av1an_params, &temp_encoder_params);
 
     // Zoning Chapters
-    if !crf_chapters.is_empty()
-        && let Some(chapters) = chapters
-    {
-        let video = prepare_clip(
-            &core,
-            input,
-            importer_scene,
-            &indexes_folder,
-            verbose_verbose_verbose,
-            zoning_params,
-            crop,
-            trim,
-            downscale,
-            detelecine,
-        )?;
-
-        let chapters = Chapters::parse(chapters)?;
-        let mut zone_chapters = ZoneChapters::from_chapters(&video, chapters);
-        zone_chapters.with_crfs(crf_chapters);
-        println!("{}", zone_chapters);
+    if let Some(zone_chapters) = &zone_chapters {
         scene_list_frames.update_crf(f64::MAX);
-        scene_list_frames.apply_zone_chapters(&zone_chapters, overlap_chapters, zoning_params);
+        scene_list_frames.apply_zone_chapters(zone_chapters, overlap_chapters, zoning_params);
         if !zoning_params.is_empty() {
             scene_list.sync_encoder_params_by_index(&scene_list_frames);
         }
         scene_list_frames.update_crf_if_unzoned(*first_crf);
         scene_list.sync_crf_by_index(&scene_list_frames);
+        scene_list.sync_targets_by_index(&scene_list_frames);
     }
 
     let n_frames = match n_frames {
         Some(n_frames) => n_frames,
-        None => seconds_to_frames(&core, s_frames, input, importer_scene, &indexes_folder)?,
+        None => seconds_to_frames(
+            &core,
+            s_frames,
+            input,
+            importer_scene,
+            &indexes_folder,
+            None,
+        )?,
     };
 
     scene_list_frames = match frames_distribution {
         FramesDistribution::Center => scene_list_frames.with_center_expanding_frames(n_frames),
         FramesDistribution::Evenly => scene_list_frames.with_evenly_spaced_frames(n_frames),
         FramesDistribution::StartMiddleEnd => scene_list.with_start_middle_end_frames(n_frames),
+        FramesDistribution::StartEnd => scene_list_frames.with_start_end_frames(n_frames),
+        FramesDistribution::Random => scene_list_frames.with_random_frames(n_frames, seed),
+        FramesDistribution::MotionWeighted => {
+            let source = prepare_clip(
+                &core,
+                input,
+                importer_scene,
+                &indexes_folder,
+                verbose_verbose_verbose,
+                encoder_params,
+                crop,
+                trim,
+                downscale,
+                downscale_kernel,
+                detelecine,
+            )?;
+            scene_list_frames.with_motion_weighted_frames(&core, &source, n_frames)?
+        }
     };
 
     scene_list_frames.filter_by_zoning();
 
-    for (i, crf) in iter_crfs.iter().enumerate() {
+    // Quick preview: only every Nth unzoned scene is measured, the others copy a neighbor's CRF
+    let scene_stride = scene_stride.unwrap_or(1).max(1);
+    let mut skipped_scenes = BTreeSet::new();
+    if scene_stride > 1 {
+        let mut position = 0;
+        scene_list_frames.split_scenes.retain(|scene| {
+            let keep = position % scene_stride == 0;
+            if !keep {
+                skipped_scenes.insert(scene.index);
+            }
+            position += 1;
+            keep
+        });
+    }
+    let sampled_scenes: BTreeSet<u32> = scene_list_frames
+        .split_scenes
+        .iter()
+        .map(|scene| scene.index)
+        .collect();
+
+    if dry_run {
+        let selected_frames: usize = scene_list_frames
+            .split_scenes
+            .iter()
+            .map(|scene| scene.frame_scores.len())
+            .sum();
+        scene_list.write_scene_list_to_file(&scene_path)?;
+
+        println!("\n✧ DRY RUN\n");
+        println!("Scenes: {}", scene_list.split_scenes.len());
+        println!("Total frames: {}", scene_list.frames);
+        println!("CRF ladder: {crfs:?}");
+        println!("Frames per scene: {n_frames} ({frames_distribution:?})");
+        if !skipped_scenes.is_empty() {
+            println!(
+                "Scene stride: {scene_stride} ({} scenes get an approximate CRF)",
+                skipped_scenes.len()
+            );
+        }
+        println!(
+            "Scenes to encode: {} ({} frames)",
+            scene_list_frames.split_scenes.len(),
+            selected_frames
+        );
+        println!(
+            "Estimated encodes: up to {} ({} frames at most)",
+            iter_crfs.len(),
+            selected_frames * iter_crfs.len()
+        );
+        println!("Scene file: {}", scene_path.display());
+        return Ok(scene_boosted);
+    }
+
+    // Scores of every scene at every CRF, kept across cycles and runs
+    let metrics_cache_path = metrics_folder.join("metrics.json");
+    let mut metrics_cache = if metrics_cache_path.exists() {
+        MetricsCache::parse_metrics_cache(&metrics_cache_path)?
+    } else {
+        MetricsCache {
+            frames: scene_list.frames,
+            scene_metrics: Vec::new(),
+        }
+    };
+    if metrics_cache.frames != scene_list.frames {
+        return Err(eyre!(
+            "Metrics cache frame count mismatch: expected {}, got {}. Remove {}",
+            scene_list.frames,
+            metrics_cache.frames,
+            metrics_cache_path.display()
+        ));
+    }
+
+    // (CRF, scenes measured, scenes still under target) of each cycle
+    let mut convergence: Vec<(f64, usize, usize)> = Vec::with_capacity(iter_crfs.len());
+    let cycles = max_cycles.map_or(iter_crfs.len(), |max| max.min(iter_crfs.len()));
+    for (i, crf) in iter_crfs.iter().enumerate().take(cycles) {
         println!("\n\n✧ CYCLE: {i}, CRF: {crf}\n");
+        let measured_scenes = scene_list_frames.split_scenes.len();
         let scenes_path = scenes_folder.join(format!("scenes_{crf}.json"));
         let vpy_path = encodes_folder.join(format!("encode_{crf}.vpy"));
         let encode_path = encodes_folder.join(format!("encode_{crf}.mkv"));
-        let metrics_cache_path = metrics_folder.join(format!("metrics_{crf}.json"));
 
         scene_list_frames = scene_list_frames.with_contiguous_frames();
-        let filter_scene_file = scene_list_frames.write_scene_list_to_file(&scenes_path)?;
 
-        // Temp encode
-        let vpy_file = create_vpy_file(
-            input,
-            &vpy_path,
-            Some(&scene_list_frames),
-            importer_encoding,
-            crop,
-            downscale,
-            resize,
-            trim,
-            detelecine,
-            encoder_params,
-            &indexes_folder,
-            clean,
-        )?;
-        let encode = if !encode_path.exists() {
-            encode_frames(
-                vpy_file,
-                filter_scene_file,
-                &encode_path,
-                &temp_av1an_params,
-                &temp_encoder_params,
-                clean,
-                &encodes_folder,
-            )?
+        // Only encode and measure the scenes not measured at this CRF yet
+        let mut pending_scenes = scene_list_frames
+            .split_by_metrics_cache(&metrics_cache)
+            .with_contiguous_frames();
+        if pending_scenes.split_scenes.is_empty() {
+            println!("All scenes found in the metrics cache");
         } else {
-            &encode_path
-        };
+            if let Some((_, csv)) = frame_scores.iter().find(|(score_crf, _)| score_crf == crf) {
+                println!("Using the precomputed frame scores");
+                pending_scenes.apply_scores_from_csv(csv)?;
+            } else {
+                let filter_scene_file = pending_scenes.write_scene_list_to_file(&scenes_path)?;
+
+                // Temp encode
+                let vpy_file = create_vpy_file(
+                    input,
+                    &vpy_path,
+                    Some(&pending_scenes),
+                    importer_encoding,
+                    crop,
+                    downscale,
+                    downscale_kernel,
+                    resize,
+                    trim,
+                    detelecine,
+                    encoder_params,
+                    &indexes_folder,
+                    clean,
+                    vspipe_args,
+                    prefilter,
+                )?;
+                let encode = if !encode_path.exists() {
+                    encode_frames(
+                        vpy_file,
+                        filter_scene_file,
+                        &encode_path,
+                        &temp_av1an_params,
+                        &temp_encoder_params,
+                        clean,
+                        &encodes_folder,
+                    )?
+                } else {
+                    &encode_path
+                };
 
-        // Scores
-        if !metrics_cache_path.exists() {
-            ssimu2_frames_selected(
-                &core,
-                input,
-                encode,
-                &mut scene_list_frames,
-                importer_metrics,
-                &indexes_folder,
-                verbose_verbose_verbose,
-                encoder_params,
-                crop,
-                downscale,
-                resize,
-                detelecine,
-                trim,
-            )?;
-            let metrics_cache = scene_list_frames.to_metrics_cache();
+                // Scores
+                ssimu2_frames_selected(
+                    &core,
+                    input,
+                    encode,
+                    &mut pending_scenes,
+                    importer_metrics,
+                    &indexes_folder,
+                    verbose_verbose_verbose,
+                    encoder_params,
+                    crop,
+                    downscale,
+                    downscale_kernel,
+                    resize,
+                    detelecine,
+                    trim,
+                    luma_only,
+                    metric_threads,
+                    &format!("SSIMU2 CRF {crf}"),
+                )?;
+
+                if clean {
+                    fs::remove_file(&scenes_path)?;
+                    fs::remove_file(&vpy_path)?;
+                    fs::remove_file(&encode_path)?;
+                }
+            }
+            metrics_cache.insert_scenes(&pending_scenes);
             metrics_cache.write_metrics_cache(&metrics_cache_path)?;
-        } else {
-            let metrics_cache = MetricsCache::parse_metrics_cache(&metrics_cache_path)?;
-            scene_list_frames.apply_metrics_cache(&metrics_cache)?;
+            scene_list_frames.sync_scores_by_index(&pending_scenes);
         }
 
         scene_list.sync_scores_by_index(&scene_list_frames);
@@ -303,6 +572,8 @@ pub fn run_frame_loop<'a>(
                 min_target_quality,
                 crfs[i + 1],
                 percentile,
+                aggregation,
+                score_targets,
             );
         } else {
             scene_list_frames.update_crf(crfs[i + 1]);
@@ -319,19 +590,208 @@ pub fn run_frame_loop<'a>(
 
         scene_list.print_crf_percentages();
 
-        if clean {
-            fs::remove_file(&scenes_path)?;
-            fs::remove_file(&vpy_path)?;
-            fs::remove_file(&encode_path)?;
-        }
+        let remaining_scenes = scene_list_frames.split_scenes.len();
+        println!("Scenes under target at CRF {crf}: {remaining_scenes}/{measured_scenes}");
+        convergence.push((*crf, measured_scenes, remaining_scenes));
 
         if scene_list_frames.split_scenes.is_empty() {
             break;
         }
     }
 
+    if !convergence.is_empty() {
+        println!("\n✧ CONVERGENCE\n");
+    }
+    for (crf, measured_scenes, remaining_scenes) in &convergence {
+        println!(
+            "CRF {crf:>6.2}: {measured_scenes:>5} scenes measured, {remaining_scenes:>5} under target"
+        );
+    }
+    if convergence.len() < iter_crfs.len()
+        && let Some(&(_, _, remaining_scenes)) = convergence.last()
+        && remaining_scenes > 0
+    {
+        println!(
+            "Stopped after {} cycles (--max-cycles): {remaining_scenes} scenes get CRF {} unmeasured",
+            convergence.len(),
+            crfs[convergence.len()]
+        );
+    }
+
+    if interpolate_crf {
+        let changed =
+            scene_list.interpolate_crf(&metrics_cache, quality_flag.crf_step(), |scene| {
+                let mut targets = aggregation.score_targets(
+                    scene.target_quality.unwrap_or(target_quality),
+                    min_target_quality,
+                    scene.target_percentile.unwrap_or(percentile),
+                );
+                targets.extend_from_slice(score_targets);
+                targets
+            });
+        println!("Interpolated CRF: {changed} scenes between ladder steps");
+    }
+    if !skipped_scenes.is_empty() {
+        let filled = scene_list.fill_crf_from_nearest(&sampled_scenes, &skipped_scenes);
+        println!("\n✧ APPROXIMATE (--scene-stride {scene_stride})\n");
+        println!(
+            "Measured {} scenes, {filled} unmeasured scenes got the CRF of the nearest measured one",
+            sampled_scenes.len()
+        );
+        println!("Run without --scene-stride for the final encode");
+    }
+    if let Some(window) = crf_smooth {
+        let lowered = scene_list.smooth_crf(window, crf_smooth_mode, &crfs);
+        println!(
+            "CRF smoothing over {window} scenes ({crf_smooth_mode:?}): lowered the CRF of {lowered} scenes"
+        );
+    }
+    if let Some(max_crf_step) = max_crf_step {
+        let lowered = scene_list.clamp_crf_delta(max_crf_step);
+        println!("Max CRF step {max_crf_step}: lowered the CRF of {lowered} scenes");
+    }
+
+    if estimate_output_size {
+        // Calibration encode of the measured frames at the final CRFs, with the real preset
+        let mut sampled = scene_list.clone();
+        sampled
+            .split_scenes
+            .retain(|scene| !scene.frame_scores.is_empty());
+        let mut calibration = sampled.with_contiguous_frames();
+        for scene in &mut calibration.split_scenes {
+            // One chunk per scene, so the chunk sizes map back to the scenes
+            if let Some(overrides) = &mut scene.zone_overrides {
+                overrides.extra_splits_len = Some(0);
+                overrides.min_scene_len = Some(0);
+            }
+        }
+
+        let calibration_scenes_path = scenes_folder.join("scenes_calibration.json");
+        let calibration_scenes = calibration.write_scene_list_to_file(&calibration_scenes_path)?;
+        let vpy_path = encodes_folder.join("calibration.vpy");
+        let vpy_file = create_vpy_file(
+            input,
+            &vpy_path,
+            Some(&sampled),
+            importer_encoding,
+            crop,
+            downscale,
+            downscale_kernel,
+            resize,
+            trim,
+            detelecine,
+            encoder_params,
+            &indexes_folder,
+            true,
+            vspipe_args,
+            prefilter,
+        )?;
+        encode_frames(
+            vpy_file,
+            calibration_scenes,
+            &encodes_folder.join("calibration.mkv"),
+            &temp_av1an_params,
+            &remove_crf_param(encoder_params),
+            false,
+            &encodes_folder,
+        )?;
+
+        let estimate = estimate_size(
+            &scene_list,
+            &encodes_folder.join("calibration").join("encode"),
+        )?;
+        println!("\nSize estimate\n{estimate}");
+    }
+
+    // Before merging, the curves are keyed by the scene indexes of the cache
+    let curves_file = match (curves_file, crf_data_file) {
+        (Some(curves_file), _) => Some(curves_file.to_path_buf()),
+        (None, Some(_)) => Some(input.with_file_name(format!(
+            "[CURVES]_{}.json",
+            input
+                .file_stem()
+                .ok_or_eyre("No file name")?
+                .to_str()
+                .ok_or_eyre("Invalid UTF-8 in input path")?
+        ))),
+        (None, None) => None,
+    };
+    if let Some(curves_file) = curves_file {
+        metrics_cache.write_curves(&scene_list, percentile, &curves_file)?;
+        println!("CRF curves: {}", curves_file.display());
+    }
+
     scene_list.update_scenes();
     scene_list.write_crf_data(crf_data_file, input, Some(percentile), true)?;
+    if let Some(crf_data_csv) = crf_data_csv {
+        scene_list.write_crf_data_csv(crf_data_csv)?;
+    }
+    if merge_scenes {
+        scene_list.merge_same_crf_scenes();
+        scene_list.update_scenes();
+    }
+
+    if let Some(iso) = photon_noise
+        && adaptive_grain
+    {
+        let clip = prepare_clip(
+            &core,
+            input,
+            importer_scene,
+            &indexes_folder,
+            false,
+            encoder_params,
+            crop,
+            trim,
+            downscale,
+            downscale_kernel,
+            detelecine,
+        )?;
+        scene_list.assign_grain(&core, &clip, iso)?;
+    } else if let Some(iso) = photon_noise {
+        // --photon-noise-width/height and --chroma-noise of the av1an params still apply
+        let overrides = ZoneOverrides::from_params(av1an_params, encoder_params, 0.0);
+        let (width, height) = match (overrides.photon_noise_width, overrides.photon_noise_height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => match resize.filter(|s| !s.is_empty()) {
+                Some(resize) => parse_resolution(resize)?,
+                None => {
+                    let clip = prepare_clip(
+                        &core,
+                        input,
+                        importer_encoding,
+                        &indexes_folder,
+                        false,
+                        encoder_params,
+                        crop,
+                        trim,
+                        downscale,
+                        downscale_kernel,
+                        detelecine,
+                    )?;
+                    let info = clip.info();
+                    (info.width as u32, info.height as u32)
+                }
+            },
+        };
+        let stem = scene_boosted
+            .file_stem()
+            .ok_or_eyre("No file name")?
+            .to_string_lossy();
+        let table = generate_photon_noise_table(
+            &scene_boosted.with_file_name(format!("{stem}_grain.tbl")),
+            iso,
+            width,
+            height,
+            overrides.chroma_noise,
+        )?;
+        scene_list.set_grain_table(&table)?;
+        println!(
+            "Grain table (ISO {iso}, {width}x{height}): {}",
+            table.display()
+        );
+    }
+
     scene_list.write_scene_list_to_file(scene_boosted)?;
 
     if clean && temp_folder.exists() {
@@ -341,6 +801,182 @@ pub fn run_frame_loop<'a>(
     Ok(scene_boosted)
 }
 
+/// Chapters of `chapters` on the prepared clip with their CRFs and target percentiles, `None`
+/// when no chapter zones anything
+#[allow(clippy::too_many_arguments)]
+pub fn load_zone_chapters(
+    core: &Core,
+    input: &Path,
+    chapters: Option<&Path>,
+    crf_chapters: String,
+    chapter_percentiles: &[(String, u8)],
+    importer: &SourcePlugin,
+    indexes_folder: &Path,
+    verbose: bool,
+    params: &str,
+    crop: Option<&str>,
+    trim: Option<&str>,
+    downscale: f64,
+    downscale_kernel: DownscaleKernel,
+    detelecine: Option<Ivtc>,
+) -> Result<Option<ZoneChapters>> {
+    if !chapter_percentiles.is_empty() && chapters.is_none() {
+        return Err(eyre!("Chapter target percentiles need a chapters file"));
+    }
+    let Some(chapters) =
+        chapters.filter(|_| !crf_chapters.is_empty() || !chapter_percentiles.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let video = prepare_clip(
+        core,
+        input,
+        importer,
+        indexes_folder,
+        verbose,
+        params,
+        crop,
+        trim,
+        downscale,
+        downscale_kernel,
+        detelecine,
+    )?;
+
+    let chapters = Chapters::parse(chapters)?;
+    // Chapters are timed on the source, a trim from the end needs its length
+    let trim_first = trim
+        .map(parse_trim)
+        .transpose()?
+        .and_then(|trim| trim.first);
+    let trim_start = match trim_first {
+        Some(first) if first < 0 => {
+            let source = prepare_clip(
+                core,
+                input,
+                importer,
+                indexes_folder,
+                verbose,
+                params,
+                crop,
+                None,
+                downscale,
+                downscale_kernel,
+                detelecine,
+            )?;
+            i64::from(source.info().num_frames) + first
+        }
+        Some(first) => first,
+        None => 0,
+    };
+    let mut zone_chapters = ZoneChapters::from_chapters(&video, chapters, trim_start.try_into()?);
+    zone_chapters.with_crfs(crf_chapters);
+    zone_chapters.with_percentiles(chapter_percentiles);
+    println!("{}", zone_chapters);
+    Ok(Some(zone_chapters))
+}
+
+/// Measures every frame of the scenes of `scene_file` on the final `encode`, prints the scenes
+/// whose minimum score is below `min_target_quality` and returns how many there are
+#[allow(clippy::too_many_arguments)]
+pub fn verify_encode(
+    input: &Path,
+    encode: &Path,
+    scene_file: &Path,
+    importer_metrics: &SourcePlugin,
+    temp_folder: &Path,
+    verbose: bool,
+    encoder_params: &str,
+    crop: Option<&str>,
+    downscale: f64,
+    downscale_kernel: DownscaleKernel,
+    resize: Option<&str>,
+    trim: Option<&str>,
+    detelecine: Option<Ivtc>,
+    luma_only: bool,
+    min_target_quality: f64,
+    percentile: u8,
+    chapters: Option<&Path>,
+    chapter_percentiles: &[(String, u8)],
+    overlap_chapters: f64,
+    metric_threads: usize,
+) -> Result<usize> {
+    println!("\nVerifying {}", encode.display());
+    let core = Core::builder().build();
+    let indexes_folder = temp_folder.join("indexes");
+    fs::create_dir_all(&indexes_folder)?;
+
+    let mut scene_list = SceneList::parse_scene_file(scene_file)?;
+    scene_list.sync_crf_from_zone_overrides()?;
+    // Chapter percentiles, the same the boost loop used
+    if let Some(zone_chapters) = load_zone_chapters(
+        &core,
+        input,
+        chapters,
+        String::new(),
+        chapter_percentiles,
+        importer_metrics,
+        &indexes_folder,
+        verbose,
+        encoder_params,
+        crop,
+        trim,
+        downscale,
+        downscale_kernel,
+        detelecine,
+    )? {
+        scene_list.apply_zone_chapters(&zone_chapters, overlap_chapters, "");
+    }
+    let mut scene_list = scene_list.with_all_frames();
+
+    ssimu2_frames_selected(
+        &core,
+        input,
+        encode,
+        &mut scene_list,
+        importer_metrics,
+        &indexes_folder,
+        verbose,
+        encoder_params,
+        crop,
+        downscale,
+        downscale_kernel,
+        resize,
+        detelecine,
+        trim,
+        luma_only,
+        metric_threads,
+        "SSIMU2 verify",
+    )?;
+
+    let mut missed = 0;
+    for (i, scene) in scene_list.split_scenes.iter().enumerate() {
+        let min = math::min_score(&scene.frame_scores);
+        let percentile = scene.target_percentile.unwrap_or(percentile);
+        if min < min_target_quality {
+            missed += 1;
+            println!(
+                "scene: {:4}, crf: {:3.2}, frame-range: {:6} {:6}, {} percentile: {:6.2}, min: {:6.2}",
+                i,
+                scene.crf,
+                scene.start_frame,
+                scene.end_frame,
+                percentile,
+                math::percentile(&scene.frame_scores, percentile),
+                min
+            );
+        }
+    }
+
+    println!(
+        "\n{missed} of {} scenes below the minimum target quality ({min_target_quality})",
+        scene_list.split_scenes.len()
+    );
+    scene_list.print_stats()?;
+
+    Ok(missed)
+}
+
 #[derive(Debug)]
 pub struct CrfRange {
     pub min: u32,
@@ -374,7 +1010,10 @@ pub fn parse_crf_and_strip(params: &str) -> (Option<CrfRange>, String) {
     (crf, new_params.join(" "))
 }
 
-pub fn update_preset(velocity_preset: i32, encoder_params: &str) -> String {
+/// Replaces the preset of the encoder params with the velocity preset, named for x264/x265
+/// (`encoder` is the av1an `--encoder`)
+pub fn update_preset(velocity_preset: i32, encoder_params: &str, encoder: Option<&str>) -> String {
+    let preset = encoder_preset(encoder, velocity_preset);
     let mut args: Vec<String> = encoder_params
         .split_whitespace()
         .map(String::from)
@@ -383,7 +1022,9 @@ pub fn update_preset(velocity_preset: i32, encoder_params: &str) -> String {
     if let Some(index) = args.iter().position(|arg| arg == "--preset")
         && index + 1 < args.len()
     {
-        args[index + 1] = velocity_preset.to_string();
+        args[index + 1] = preset;
+    } else if let Some(index) = args.iter().position(|arg| arg.starts_with("--preset=")) {
+        args[index] = format!("--preset={preset}");
     }
 
     args.join(" ")
@@ -501,14 +1142,25 @@ pub fn update_workers(params: &str, new_workers: u32) -> String {
     updated_tokens.join(" ")
 }
 
+/// Removes the quality value of the encoder params (`--crf`, `--quantizer` or `--cq-level`,
+/// separate or joined with `=`), the zones set their own
 pub fn remove_crf_param(params: &str) -> String {
+    let flags = [
+        QualityFlag::Crf,
+        QualityFlag::Quantizer,
+        QualityFlag::CqLevel,
+    ];
     let mut tokens = params.split_whitespace().peekable();
     let mut updated_tokens: Vec<String> = Vec::new();
 
     while let Some(token) = tokens.next() {
-        if token == "--crf" {
-            tokens.next(); // Skip the value following --crf
-        } else {
+        if flags.iter().any(|flag| token == flag.as_str()) {
+            tokens.next(); // Skip the value following the flag
+        } else if !flags.iter().any(|flag| {
+            token
+                .strip_prefix(flag.as_str())
+                .is_some_and(|rest| rest.starts_with('='))
+        }) {
             updated_tokens.push(token.to_string());
         }
     }
@@ -516,6 +1168,20 @@ pub fn remove_crf_param(params: &str) -> String {
     updated_tokens.join(" ")
 }
 
+/// Encoder of the av1an params, given with `--encoder x265`, `--encoder=x265` or `-e x265`
+pub fn get_encoder(av1an_params: &str) -> Option<String> {
+    let mut tokens = av1an_params.split_whitespace();
+    while let Some(token) = tokens.next() {
+        if token == "--encoder" || token == "-e" {
+            return tokens.next().map(str::to_owned);
+        }
+        if let Some(encoder) = token.strip_prefix("--encoder=") {
+            return Some(encoder.to_owned());
+        }
+    }
+    None
+}
+
 /// Extracts the value of a command-line argument from a parameter string
 pub fn get_arg_value(params: &str, arg_name: &str) -> Option<String> {
     let mut tokens = params.split_whitespace().peekable();
@@ -530,14 +1196,63 @@ pub fn get_arg_value(params: &str, arg_name: &str) -> Option<String> {
     None
 }
 
-/// Checks the chunk method in the params and returns the corresponding ImporterPlugin
-pub fn check_chunk_method(params: &str) -> Option<SourcePlugin> {
-    let chunk_method = get_arg_value(params, "--chunk-method")?;
+/// Parses a `CRF=path` pair of precomputed frame scores, `-` reads the CSV from stdin
+pub fn parse_frame_scores_arg(arg: &str) -> Result<(f64, PathBuf), String> {
+    let (crf, path) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("Expected CRF=path, got '{arg}'"))?;
+    let crf: f64 = crf
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid CRF '{crf}': {e}"))?;
+    if path.is_empty() {
+        return Err(format!("No scores file in '{arg}'"));
+    }
+    Ok((crf, PathBuf::from(path)))
+}
+
+/// Reads the CSVs of `--frame-scores`, at most one of them from stdin
+pub fn read_frame_scores(args: &[(f64, PathBuf)]) -> Result<Vec<(f64, String)>> {
+    if args
+        .iter()
+        .filter(|(_, path)| path.as_os_str() == "-")
+        .count()
+        > 1
+    {
+        return Err(eyre!("Only one CRF can read its frame scores from stdin"));
+    }
+
+    args.iter()
+        .map(|(crf, path)| {
+            let csv = if path.as_os_str() == "-" {
+                io::read_to_string(io::stdin())?
+            } else {
+                fs::read_to_string(path)
+                    .map_err(|e| eyre!("Failed to read {}: {e}", path.display()))?
+            };
+            Ok((*crf, csv))
+        })
+        .collect()
+}
+
+/// Checks the chunk method in the params and returns the corresponding ImporterPlugin.
+/// `None` if there is no chunk method or if it doesn't use a VapourSynth source
+/// (select, segment, hybrid, dgdecnv), an error if av1an doesn't know it
+pub fn check_chunk_method(params: &str) -> Result<Option<SourcePlugin>> {
+    let Some(chunk_method) =
+        get_arg_value(params, "--chunk-method").or_else(|| get_arg_value(params, "-m"))
+    else {
+        return Ok(None);
+    };
 
     match chunk_method.as_str() {
-        "lsmash" => Some(SourcePlugin::Lsmash),
-        "bestsource" => Some(SourcePlugin::Bestsource),
-        _ => None,
+        "lsmash" => Ok(Some(SourcePlugin::Lsmash)),
+        "ffms2" => Ok(Some(SourcePlugin::Ffms2)),
+        "bestsource" => Ok(Some(SourcePlugin::Bestsource)),
+        "select" | "segment" | "hybrid" | "dgdecnv" => Ok(None),
+        other => Err(eyre!(
+            "Unknown chunk method '{other}' (lsmash, ffms2, bestsource, dgdecnv, hybrid, select or segment)"
+        )),
     }
 }
 
@@ -584,6 +1299,30 @@ pub fn update_min_scene_len(params: &str, new_value: i64) -> String {
     update_flag_with_value(params, "--min-scene-len", new_value)
 }
 
+/// Replaces the `--vspipe-args` values with `vspipe_args`, params are unchanged when it's empty
+pub fn update_vspipe_args(params: &str, vspipe_args: &[String]) -> String {
+    if vspipe_args.is_empty() {
+        return params.to_owned();
+    }
+
+    let mut tokens = params.split_whitespace().peekable();
+    let mut updated_tokens: Vec<String> = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        if token == "--vspipe-args" {
+            // Skip the old values, up to the next flag
+            while tokens.next_if(|value| !value.starts_with('-')).is_some() {}
+        } else {
+            updated_tokens.push(token.to_string());
+        }
+    }
+
+    updated_tokens.push("--vspipe-args".to_string());
+    updated_tokens.extend(vspipe_args.iter().cloned());
+
+    updated_tokens.join(" ")
+}
+
 /// Helper function to update or insert a flag and its value
 fn update_flag_with_value(params: &str, flag: &str, new_value: i64) -> String {
     let mut tokens = params.split_whitespace().peekable();
@@ -608,3 +1347,52 @@ fn update_flag_with_value(params: &str, flag: &str, new_value: i64) -> String {
 
     updated_tokens.join(" ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x265_encoder_lookup() {
+        assert_eq!(get_encoder("-e x265 --workers 4").as_deref(), Some("x265"));
+        assert_eq!(get_encoder("--encoder x265").as_deref(), Some("x265"));
+        assert_eq!(get_encoder("--encoder=x265").as_deref(), Some("x265"));
+        assert_eq!(get_encoder("--workers 4"), None);
+    }
+
+    #[test]
+    fn x265_update_preset() {
+        assert_eq!(
+            update_preset(4, "--preset slower --crf 20", Some("x265")),
+            "--preset slow --crf 20"
+        );
+        assert_eq!(
+            update_preset(8, "--preset=slower --crf 20", Some("x265")),
+            "--preset=fast --crf 20"
+        );
+        assert_eq!(
+            update_preset(
+                8,
+                "--preset 4 --crf 30",
+                get_encoder("-e svt-av1").as_deref()
+            ),
+            "--preset 8 --crf 30"
+        );
+    }
+
+    #[test]
+    fn x265_remove_crf_param() {
+        assert_eq!(
+            remove_crf_param("--preset slow --crf 20 --tune grain"),
+            "--preset slow --tune grain"
+        );
+        assert_eq!(
+            remove_crf_param("--crf=20.5 --preset slow"),
+            "--preset slow"
+        );
+        assert_eq!(
+            remove_crf_param("--cq-level=30 --quantizer 120 --cpu-used 4"),
+            "--cpu-used 4"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf, absolute},
+};
+
+use eyre::{Result, eyre};
+
+use crate::vapoursynth::write_atomic;
+
+// Photon noise model of libaom (examples/photon_noise_table.c), the one av1an uses for
+// --photon-noise: shot noise of a 36x24mm sensor plus read noise and PRNU, encoded with
+// the BT.1886 transfer function
+const PHOTONS_PER_LX_S_PER_UM2: f64 = 11260.0;
+const EFFECTIVE_QUANTUM_EFFICIENCY: f64 = 0.2;
+const PHOTO_RESPONSE_NON_UNIFORMITY: f64 = 0.005;
+const INPUT_REFERRED_READ_NOISE: f64 = 1.5;
+const MID_TONE: f64 = 0.18;
+const GAMMA: f64 = 2.4;
+const NUM_Y_POINTS: usize = 14;
+const RANDOM_SEED: u32 = 7391;
+
+/// Scaling points `(value, strength)` of the luma grain for an ISO at a resolution
+pub fn photon_noise_scaling_points(iso: u32, width: u32, height: u32) -> Vec<(u8, u8)> {
+    let to_linear = |x: f64| x.powf(GAMMA);
+    let from_linear = |x: f64| x.powf(1.0 / GAMMA);
+
+    // Focal plane exposure of a mid-tone, in lx·s
+    let mid_tone_exposure = 10.0 / iso as f64;
+    let pixel_area_um2 = (36000.0 * 24000.0) / (width as f64 * height as f64);
+    let mid_tone_electrons_per_pixel = EFFECTIVE_QUANTUM_EFFICIENCY
+        * PHOTONS_PER_LX_S_PER_UM2
+        * mid_tone_exposure
+        * pixel_area_um2;
+    let max_electrons_per_pixel = mid_tone_electrons_per_pixel / MID_TONE;
+
+    (0..NUM_Y_POINTS)
+        .map(|i| {
+            let x = i as f64 / (NUM_Y_POINTS - 1) as f64;
+            let linear = to_linear(x);
+            let electrons_per_pixel = max_electrons_per_pixel * linear;
+            // Shot noise is sqrt(electrons), summed in quadrature with the other sources
+            let noise_in_electrons = (INPUT_REFERRED_READ_NOISE.powi(2)
+                + electrons_per_pixel
+                + (PHOTO_RESPONSE_NON_UNIFORMITY * electrons_per_pixel).powi(2))
+            .sqrt();
+            let linear_noise = noise_in_electrons / max_electrons_per_pixel;
+            let range_start = (linear - 2.0 * linear_noise).max(0.0);
+            let range_end = (linear + 2.0 * linear_noise).min(1.0);
+            let tf_slope =
+                (from_linear(range_end) - from_linear(range_start)) / (range_end - range_start);
+            let encoded_noise = (255.0 * 7.88 * linear_noise * tf_slope).round().min(255.0);
+
+            ((255.0 * x).round() as u8, encoded_noise as u8)
+        })
+        .collect()
+}
+
+/// Writes an AV1 film grain table (`filmgrn1`, as read by aomenc --film-grain-table and
+/// SVT-AV1 --fgs-table) with photon noise of `iso` for the whole video, and returns its
+/// absolute path. `chroma` also applies the grain to the chroma planes, scaled from luma
+pub fn generate_photon_noise_table(
+    path: &Path,
+    iso: u32,
+    width: u32,
+    height: u32,
+    chroma: bool,
+) -> Result<PathBuf> {
+    if iso == 0 {
+        return Err(eyre!("The photon noise ISO has to be above 0"));
+    }
+    if width == 0 || height == 0 {
+        return Err(eyre!("Invalid photon noise resolution {width}x{height}"));
+    }
+
+    let points = photon_noise_scaling_points(iso, width, height);
+
+    let mut table = String::from("filmgrn1\n");
+    // Whole video: start 0 to the max timestamp, apply_grain, seed, update_parameters
+    writeln!(table, "E 0 {} 1 {RANDOM_SEED} 1", i64::MAX)?;
+    // ar_coeff_lag, ar_coeff_shift, grain_scale_shift, scaling_shift,
+    // chroma_scaling_from_luma, overlap_flag, then the cb/cr mult, luma_mult and offset
+    writeln!(
+        table,
+        "\tp 0 6 0 8 {} 1 128 192 256 128 192 256",
+        u8::from(chroma)
+    )?;
+    write!(table, "\tsY {NUM_Y_POINTS} ")?;
+    for (value, strength) in &points {
+        write!(table, " {value} {strength}")?;
+    }
+    // No AR coefficients at lag 0, except the luma one of the chroma planes
+    table.push_str("\n\tsCb 0\n\tsCr 0\n\tcY\n\tcCb 0\n\tcCr 0\n");
+
+    write_atomic(path, table)?;
+    Ok(absolute(path)?)
+}
@@ -1,7 +1,9 @@
+pub mod binaries;
 pub mod crf;
 pub mod dampen;
 pub mod encode;
 pub mod frame_loop;
+pub mod grain;
 pub mod math;
 pub mod scenes;
 pub mod ssimulacra2;
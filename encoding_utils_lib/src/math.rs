@@ -1,8 +1,10 @@
-use std::{collections::HashMap, fs::File, io::Write, path::Path};
+use std::{collections::HashMap, io::Write, path::Path};
 
 use eyre::{Ok, OptionExt, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::vapoursynth::write_atomic;
+
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct FrameScore {
     pub frame: u32,
@@ -27,7 +29,7 @@ impl From<Vec<FrameScore>> for ScoreList {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Percentile {
     pub n: u32,
     pub score: FrameScore,
@@ -38,11 +40,40 @@ pub struct PercentileList {
     pub percentiles: Vec<Percentile>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Mode {
     pub value: u32,
     pub count: usize,
 }
+
+/// Structured version of the `get_stats` text block
+#[derive(Debug, Serialize)]
+pub struct StatsJson {
+    pub mean: f64,
+    pub standard_deviation: f64,
+    pub standard_error: f64,
+    pub confidence_interval_95: (f64, f64),
+    pub samples: usize,
+    pub mode: Mode,
+    pub percentiles: Vec<Percentile>,
+    pub min: FrameScore,
+}
+
+/// Stats of one distorted file, as written by simple-ssimu2 `--json-out`
+#[derive(Debug, Serialize)]
+pub struct StatsSummary {
+    pub reference: String,
+    pub distorted: String,
+    pub steps: u32,
+    pub ssimu2: StatsJson,
+}
+
+pub fn write_stats_json(path: &Path, summaries: &[StatsSummary]) -> Result<()> {
+    let json = serde_json::to_string_pretty(summaries)?;
+    write_atomic(path, json)?;
+    Ok(())
+}
+
 pub fn mean(scores: &[FrameScore]) -> f64 {
     if scores.is_empty() {
         0.0
@@ -50,6 +81,14 @@ pub fn mean(scores: &[FrameScore]) -> f64 {
         scores.iter().map(|score| score.value).sum::<f64>() / scores.len() as f64
     }
 }
+/// Harmonic mean, pulled down by the worst frames. 0 if any score is not positive
+pub fn harmonic_mean(scores: &[FrameScore]) -> f64 {
+    if scores.is_empty() || scores.iter().any(|score| score.value <= 0.0) {
+        return 0.0;
+    }
+    scores.len() as f64 / scores.iter().map(|score| 1.0 / score.value).sum::<f64>()
+}
+
 /// Returns the value at the given percentile (e.g., 50 for median).
 pub fn percentile(scores: &[FrameScore], percentile: u8) -> f64 {
     if scores.is_empty() {
@@ -87,6 +126,24 @@ pub fn standard_deviation(scores: &[FrameScore]) -> f64 {
     variance(scores).sqrt()
 }
 
+/// Below this many scores the mean of a scene is too noisy to trust
+pub const MIN_RELIABLE_SAMPLES: usize = 8;
+
+/// Standard error of the mean
+pub fn standard_error(scores: &[FrameScore]) -> f64 {
+    if scores.is_empty() {
+        return 0.0;
+    }
+    standard_deviation(scores) / (scores.len() as f64).sqrt()
+}
+
+/// 95% confidence interval of the mean (normal approximation)
+pub fn confidence_interval_95(scores: &[FrameScore]) -> (f64, f64) {
+    let mean = mean(scores);
+    let margin = 1.96 * standard_error(scores);
+    (mean - margin, mean + margin)
+}
+
 pub fn max(scores: &[FrameScore]) -> Result<ScoreList> {
     let max_score = scores
         .iter()
@@ -225,12 +282,46 @@ pub fn mode(score_list: &ScoreList) -> Result<Mode> {
     })
 }
 
+/// SSIMU2 scores, with PSNR and SSIM of the same frames when requested
+#[derive(Debug)]
+pub struct MultiScoreList {
+    pub ssimu2: ScoreList,
+    pub psnr: Option<ScoreList>,
+    pub ssim: Option<ScoreList>,
+}
+
+impl MultiScoreList {
+    /// SSIMU2 stats followed by the PSNR and SSIM summary
+    pub fn get_stats(&self) -> Result<String> {
+        use std::fmt::Write;
+
+        let mut output = self.ssimu2.get_stats()?;
+
+        for (name, score_list) in [("PSNR", &self.psnr), ("SSIM", &self.ssim)] {
+            if let Some(score_list) = score_list {
+                writeln!(output, "\n[STATS - {name}]")?;
+                writeln!(output, "Mean: {:.4}", mean(&score_list.scores))?;
+                writeln!(
+                    output,
+                    "Standard Deviation: {:.4}",
+                    standard_deviation(&score_list.scores)
+                )?;
+                writeln!(output, "Min: {:.4}", min_score(&score_list.scores))?;
+            }
+        }
+
+        Ok(output)
+    }
+}
+
 impl ScoreList {
     pub fn get_stats(&self) -> Result<String> {
         use std::fmt::Write;
 
         let mean = mean(&self.scores);
         let deviation = standard_deviation(&self.scores);
+        let error = standard_error(&self.scores);
+        let (ci_low, ci_high) = confidence_interval_95(&self.scores);
         // let median = median(self)?;
         let mode = mode(self)?;
         let percentiles = percentiles(&self.scores)?;
@@ -242,6 +333,18 @@ impl ScoreList {
         writeln!(output, "[STATS - SSIMU2]")?;
         writeln!(output, "Mean: {mean:.4}")?;
         writeln!(output, "Standard Deviation: {deviation:.4}")?;
+        writeln!(output, "Standard Error: {error:.4}")?;
+        writeln!(
+            output,
+            "95% Confidence Interval: {ci_low:.4} - {ci_high:.4}"
+        )?;
+        if self.scores.len() < MIN_RELIABLE_SAMPLES {
+            writeln!(
+                output,
+                "Warning: only {} scores (< {MIN_RELIABLE_SAMPLES}), the mean is unreliable",
+                self.scores.len()
+            )?;
+        }
         writeln!(output, "Mode: {:.4}, count: {:.4}", mode.value, mode.count)?;
 
         // write!(output, "Median: ")?;
@@ -286,17 +389,37 @@ impl ScoreList {
         Ok(output)
     }
 
+    pub fn to_stats_json(&self) -> Result<StatsJson> {
+        let min = min(&self.scores)?
+            .scores
+            .first()
+            .copied()
+            .ok_or_eyre("Error getting min score")?;
+
+        Ok(StatsJson {
+            mean: mean(&self.scores),
+            standard_deviation: standard_deviation(&self.scores),
+            standard_error: standard_error(&self.scores),
+            confidence_interval_95: confidence_interval_95(&self.scores),
+            samples: self.scores.len(),
+            mode: mode(self)?,
+            percentiles: percentiles(&self.scores)?.percentiles,
+            min,
+        })
+    }
+
     pub fn write_to_csv(&self, output_path: &Path) -> eyre::Result<()> {
-        let mut file = File::create(output_path)?;
+        let mut csv = Vec::new();
 
         // Write CSV header
-        writeln!(file, "frame,score")?;
+        writeln!(csv, "frame,score")?;
 
         // Write each frame score
         for frame_score in &self.scores {
-            writeln!(file, "{},{:.4}", frame_score.frame, frame_score.value)?;
+            writeln!(csv, "{},{:.4}", frame_score.frame, frame_score.value)?;
         }
 
+        write_atomic(output_path, csv)?;
         Ok(())
     }
 
@@ -1,12 +1,21 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
+    fmt,
     fs::{self},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 
 use clap::ValueEnum;
 use eyre::{Ok, OptionExt, Result, eyre};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use vapoursynth4_rs::{
+    core::Core,
+    frame::Frame,
+    map::KeyStr,
+    node::{Node, VideoNode},
+};
 
 pub fn get_scene_file<'a>(
     scene_vpy_file: &'a Path,
@@ -59,11 +68,15 @@ pub fn get_scene_file<'a>(
     println!("{}", args.join(" "));
     println!();
 
-    Command::new("av1an")
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()?;
+    let mut command = av1an_command();
+    command.args(args);
+    run_av1an(command, &temp_folder.join("scene.log"))?;
+    if !scenes_path.exists() {
+        return Err(eyre!(
+            "av1an finished without writing the scene file {}",
+            scenes_path.display()
+        ));
+    }
 
     Ok(scenes_path)
 }
@@ -110,11 +123,15 @@ pub fn get_scene_file_with_zones<'a>(
 
     println!("{}", args.join(" "));
 
-    Command::new("av1an")
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()?;
+    let mut command = av1an_command();
+    command.args(args);
+    run_av1an(command, &scenes_zones_path.with_extension("log"))?;
+    if !scenes_zones_path.exists() {
+        return Err(eyre!(
+            "av1an finished without writing the scene file {}",
+            scenes_zones_path.display()
+        ));
+    }
 
     println!("Scene file obtained\n");
     Ok(scenes_zones_path)
@@ -123,11 +140,20 @@ pub fn get_scene_file_with_zones<'a>(
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    binaries::{av1an_command, ffprobe_command, run_av1an},
     chapters::ZoneChapters,
+    crf::CRF_RANGE,
     dampen::dampen_loop::SceneSizeList,
     math::{self, FrameScore, ScoreList},
+    vapoursynth::{GRAIN_PROP, MOTION_PROP, ToCString, grain_stats, motion_stats, write_atomic},
 };
 
+/// Frames measured per scene by [`SceneList::assign_grain`]
+const GRAIN_SAMPLES: u32 = 5;
+/// ISO range of the per scene photon noise, av1an takes strengths 1-64 (ISO / 100)
+const MIN_GRAIN_ISO: u32 = 100;
+const MAX_GRAIN_ISO: u32 = 6400;
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct Scene {
     #[serde(skip_serializing, skip_deserializing)]
@@ -141,6 +167,15 @@ pub struct Scene {
     pub frame_scores: Vec<FrameScore>,
     #[serde(skip_serializing, skip_deserializing)]
     pub zoned: bool,
+    /// Target quality of the chapter the scene is in, replaces the global one
+    #[serde(skip_serializing, skip_deserializing)]
+    pub target_quality: Option<f64>,
+    /// Target percentile of the chapter the scene is in, replaces the global one
+    #[serde(skip_serializing, skip_deserializing)]
+    pub target_percentile: Option<u8>,
+    /// Hardcut prediction of the cut starting the scene, set by scene detection
+    #[serde(skip_serializing, skip_deserializing)]
+    pub cut_confidence: f32,
 }
 
 impl Scene {
@@ -155,6 +190,13 @@ impl Scene {
             overrides.update_encoder_params(encoder_params);
         }
     }
+
+    /// CRF in the video params of the zone overrides, if any
+    pub fn zoned_crf(&self) -> Option<f64> {
+        let params = self.zone_overrides.as_ref()?.video_params.as_ref()?;
+        let (flag, value) = find_crf_value_in_params(params)?;
+        Some(flag.to_crf(value.parse().ok()?))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -184,7 +226,7 @@ impl ZoneOverrides {
         let mut av1an_tokens = av1an_params.split_whitespace().peekable();
         while let Some(token) = av1an_tokens.next() {
             match token {
-                "--encoder" => {
+                "--encoder" | "-e" => {
                     if let Some(value) = av1an_tokens.next() {
                         let value = if value == "svt-av1" { "svt_av1" } else { value };
                         encoder = Some(value.to_string());
@@ -231,8 +273,7 @@ impl ZoneOverrides {
             .split_whitespace()
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
-        video_params_vec.push("--crf".to_string());
-        video_params_vec.push(format!("{crf}"));
+        QualityFlag::from_encoder(encoder.as_deref()).push(&mut video_params_vec, crf);
 
         ZoneOverrides {
             encoder,
@@ -247,7 +288,7 @@ impl ZoneOverrides {
         }
     }
 
-    pub fn update_from_params(&mut self, av1an_params: &str, encoder_params: &str, crf: u8) {
+    pub fn update_from_params(&mut self, av1an_params: &str, encoder_params: &str, crf: f64) {
         let mut encoder = None;
         let mut passes = None;
         let mut photon_noise = None;
@@ -260,7 +301,7 @@ impl ZoneOverrides {
         let mut av1an_tokens = av1an_params.split_whitespace().peekable();
         while let Some(token) = av1an_tokens.next() {
             match token {
-                "--encoder" => {
+                "--encoder" | "-e" => {
                     if let Some(value) = av1an_tokens.next() {
                         let value = if value == "svt-av1" { "svt_av1" } else { value };
                         encoder = Some(value.to_string());
@@ -307,8 +348,7 @@ impl ZoneOverrides {
             .split_whitespace()
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
-        video_params_vec.push("--crf".to_string());
-        video_params_vec.push(format!("{crf}"));
+        QualityFlag::from_encoder(encoder.as_deref()).push(&mut video_params_vec, crf);
 
         self.encoder = encoder;
         self.passes = passes.or(Some(1));
@@ -321,25 +361,16 @@ impl ZoneOverrides {
         self.min_scene_len = min_scene_len.or(Some(24));
     }
 
-    /// Update only the CRF value in `video_params`
+    /// Update only the CRF value in `video_params`, using the quality flag of the encoder
     pub fn update_crf(&mut self, crf: f64) {
-        if let Some(ref mut params) = self.video_params {
-            let mut found = false;
-
-            for i in 0..params.len() {
-                if params[i] == "--crf" && i + 1 < params.len() {
-                    params[i + 1] = crf.to_string();
-                    found = true;
-                    break;
-                }
-            }
-
-            if !found {
-                params.push("--crf".to_string());
-                params.push(crf.to_string());
+        let flag = QualityFlag::from_encoder(self.encoder.as_deref());
+        match self.video_params {
+            Some(ref mut params) => flag.set(params, crf),
+            None => {
+                let mut params = Vec::new();
+                flag.push(&mut params, crf);
+                self.video_params = Some(params);
             }
-        } else {
-            self.video_params = Some(vec!["--crf".to_string(), crf.to_string()]);
         }
     }
 
@@ -350,6 +381,181 @@ impl ZoneOverrides {
             .collect::<Vec<String>>();
         self.video_params = Some(video_params_vec)
     }
+
+    /// Points the encoder at a film grain table instead of av1an's `--photon-noise`,
+    /// replacing the table of previous calls
+    pub fn set_grain_table(&mut self, table: &Path) -> Result<()> {
+        let flag = match self.encoder.as_deref() {
+            None | Some("svt_av1") => "--fgs-table",
+            Some("aom") => "--film-grain-table",
+            Some(other) => Err(eyre!(
+                "Grain tables aren't supported with the {other} encoder"
+            ))?,
+        };
+        let table = table
+            .to_str()
+            .ok_or_eyre("Invalid UTF-8 in grain table path")?;
+
+        let video_params = self.video_params.get_or_insert_default();
+        if let Some(i) = video_params.iter().position(|param| param == flag) {
+            video_params.drain(i..(i + 2).min(video_params.len()));
+        }
+        video_params.push(flag.to_owned());
+        video_params.push(table.to_owned());
+
+        self.photon_noise = None;
+        self.photon_noise_width = None;
+        self.photon_noise_height = None;
+        self.chroma_noise = false;
+        Ok(())
+    }
+}
+
+/// Flag an encoder takes its constant quality value with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityFlag {
+    /// svt-av1, x264, x265: `--crf 30`
+    Crf,
+    /// rav1e: `--quantizer 30`
+    Quantizer,
+    /// aomenc: `--cq-level=30`
+    CqLevel,
+}
+
+impl QualityFlag {
+    /// Maps an av1an `--encoder` name to its quality flag, `--crf` if unknown
+    pub fn from_encoder(encoder: Option<&str>) -> QualityFlag {
+        match encoder {
+            Some("rav1e") => QualityFlag::Quantizer,
+            Some("aom") => QualityFlag::CqLevel,
+            _ => QualityFlag::Crf,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityFlag::Crf => "--crf",
+            QualityFlag::Quantizer => "--quantizer",
+            QualityFlag::CqLevel => "--cq-level",
+        }
+    }
+
+    /// Values the encoder takes for the flag
+    pub fn range(&self) -> RangeInclusive<f64> {
+        match self {
+            QualityFlag::Crf => CRF_RANGE,
+            QualityFlag::Quantizer => 0.0..=255.0,
+            QualityFlag::CqLevel => 0.0..=63.0,
+        }
+    }
+
+    /// Value of the flag for a CRF of the ladder. The ladder is on the 0-63 scale of SVT-AV1
+    /// and aomenc, rav1e's quantizer is the 0-255 AV1 quantizer index, 4 per CRF step
+    pub fn from_crf(&self, crf: f64) -> f64 {
+        match self {
+            QualityFlag::Quantizer => crf * 4.0,
+            _ => crf,
+        }
+    }
+
+    /// CRF of the ladder for a value of the flag, the inverse of [`QualityFlag::from_crf`]
+    pub fn to_crf(&self, value: f64) -> f64 {
+        match self {
+            QualityFlag::Quantizer => value / 4.0,
+            _ => value,
+        }
+    }
+
+    /// Smallest CRF step the encoder can express: rav1e and aomenc only take whole values
+    pub fn crf_step(&self) -> f64 {
+        match self {
+            QualityFlag::Crf => 0.25,
+            QualityFlag::Quantizer => 0.25,
+            QualityFlag::CqLevel => 1.0,
+        }
+    }
+
+    /// Checks that every CRF of the ladder maps to a value the encoder takes
+    pub fn check_ladder(&self, crfs: &[f64]) -> Result<()> {
+        if *self == QualityFlag::Crf {
+            return Ok(());
+        }
+        for &crf in crfs {
+            let value = self.from_crf(crf);
+            if value.fract() != 0.0 || !self.range().contains(&value) {
+                return Err(eyre!(
+                    "CRF {crf} is {} {value}, which takes whole values from {} to {}",
+                    self.as_str(),
+                    self.range().start(),
+                    self.range().end()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of the flag for `crf`, as the encoder parses it
+    fn format(&self, crf: f64) -> String {
+        match self {
+            QualityFlag::Crf => crf.to_string(),
+            _ => self.from_crf(crf).round().to_string(),
+        }
+    }
+
+    /// Appends the flag and its value for `crf` in the form the encoder expects
+    pub fn push(&self, params: &mut Vec<String>, crf: f64) {
+        let value = self.format(crf);
+        match self {
+            QualityFlag::CqLevel => params.push(format!("{}={value}", self.as_str())),
+            _ => {
+                params.push(self.as_str().to_string());
+                params.push(value);
+            }
+        }
+    }
+
+    /// Replaces the value of the flag with the one of `crf`, appending it if missing
+    pub fn set(&self, params: &mut Vec<String>, crf: f64) {
+        let flag = self.as_str();
+        let joined = format!("{flag}=");
+        let value = self.format(crf);
+        let position = params
+            .iter()
+            .position(|param| param == flag || param.starts_with(&joined));
+
+        match position {
+            Some(i) if params[i].starts_with(&joined) => params[i] = format!("{joined}{value}"),
+            Some(i) if i + 1 < params.len() => params[i + 1] = value,
+            Some(_) => params.push(value),
+            None => self.push(params, crf),
+        }
+    }
+}
+
+/// x264/x265 named presets, from the slowest to the fastest
+const X26X_PRESETS: [&str; 10] = [
+    "placebo",
+    "veryslow",
+    "slower",
+    "slow",
+    "medium",
+    "fast",
+    "faster",
+    "veryfast",
+    "superfast",
+    "ultrafast",
+];
+
+/// Preset value of an SVT-AV1 style numeric preset (-1 slowest to 13 fastest) for an av1an
+/// `--encoder`: x264 and x265 take named presets, spread over the same range
+pub fn encoder_preset(encoder: Option<&str>, preset: i32) -> String {
+    match encoder {
+        Some("x264" | "x265") => {
+            let step = (preset.clamp(-1, 13) + 1) as usize * (X26X_PRESETS.len() - 1) / 14;
+            X26X_PRESETS[step].to_owned()
+        }
+        _ => preset.to_string(),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -378,6 +584,9 @@ impl SceneList {
                 crf: scene.crf,
                 index: scene.index,
                 zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
             });
         }
 
@@ -388,6 +597,26 @@ impl SceneList {
         }
     }
 
+    /// Selects every frame of each scene, to measure a full encode
+    pub fn with_all_frames(&self) -> SceneList {
+        let scenes: Vec<Scene> = self
+            .split_scenes
+            .iter()
+            .map(|scene| Scene {
+                frame_scores: (scene.start_frame..scene.end_frame)
+                    .map(FrameScore::from)
+                    .collect(),
+                ..scene.clone()
+            })
+            .collect();
+
+        SceneList {
+            frames: self.frames,
+            scenes: scenes.clone(),
+            split_scenes: scenes,
+        }
+    }
+
     pub fn with_contiguous_frames(&self) -> SceneList {
         let mut scenes = Vec::with_capacity(self.split_scenes.len());
         let mut global_counter = 0;
@@ -446,6 +675,9 @@ impl SceneList {
                 crf: scene.crf,
                 index: scene.index,
                 zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
             });
         }
 
@@ -494,6 +726,9 @@ impl SceneList {
                 crf: scene.crf,
                 index: scene.index,
                 zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
             });
         }
 
@@ -514,7 +749,7 @@ impl SceneList {
         for scene in &self.split_scenes {
             let start = scene.start_frame;
             let end = scene.end_frame.saturating_sub(1); // end is inclusive
-            let total_frames = end - start + 1;
+            let total_frames = scene.end_frame.saturating_sub(start);
 
             let frame_values = if n_frames == 0 || total_frames == 0 {
                 vec![]
@@ -556,6 +791,118 @@ impl SceneList {
                 crf: scene.crf,
                 index: scene.index,
                 zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
+            });
+        }
+
+        SceneList {
+            frames: self.frames,
+            scenes: scenes.clone(),
+            split_scenes: scenes,
+        }
+    }
+
+    /// Samples the first and last `n_frames / 2` frames of each scene, skipping the
+    /// middle. An odd frame goes to the start.
+    pub fn with_start_end_frames(&self, n_frames: u32) -> SceneList {
+        if n_frames <= 1 {
+            return self.with_middle_frames();
+        }
+
+        let mut scenes = Vec::with_capacity(self.split_scenes.len());
+
+        for scene in &self.split_scenes {
+            let start = scene.start_frame;
+            let end = scene.end_frame.saturating_sub(1); // end is inclusive
+            let total_frames = scene.end_frame.saturating_sub(start);
+
+            let frame_values = if total_frames == 0 {
+                vec![]
+            } else {
+                let end_frames = n_frames / 2;
+                let start_frames = n_frames - end_frames;
+
+                // Start segment (first `start_frames` frames, clamped to total_frames)
+                let start_seg: Vec<_> = (start..start + start_frames)
+                    .take_while(|&f| f <= end)
+                    .collect();
+
+                // End segment (last `end_frames` frames, clamped to total_frames)
+                let end_seg: Vec<_> = (end.saturating_sub(end_frames - 1)..=end)
+                    .filter(|&f| f >= start) // Avoid underflow if `end_frames > total_frames`
+                    .collect();
+
+                // Combine segments, deduplicate, and truncate
+                let mut all_frames = start_seg;
+                all_frames.extend(end_seg);
+                all_frames.sort_unstable();
+                all_frames.dedup(); // Short scenes overlap
+                all_frames.truncate(n_frames as usize);
+                all_frames
+            };
+
+            scenes.push(Scene {
+                start_frame: scene.start_frame,
+                end_frame: scene.end_frame,
+                zone_overrides: scene.zone_overrides.clone(),
+                frame_scores: frame_values.into_iter().map(FrameScore::from).collect(),
+                crf: scene.crf,
+                index: scene.index,
+                zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
+            });
+        }
+
+        SceneList {
+            frames: self.frames,
+            scenes: scenes.clone(),
+            split_scenes: scenes,
+        }
+    }
+
+    /// Samples `n_frames` distinct frames uniformly at random within each scene.
+    /// The same `seed` always picks the same frames. Scenes shorter than `n_frames`
+    /// use all their frames.
+    pub fn with_random_frames(&self, n_frames: u32, seed: u64) -> SceneList {
+        let mut scenes = Vec::with_capacity(self.split_scenes.len());
+
+        for scene in &self.split_scenes {
+            let start = scene.start_frame;
+            let total_frames = scene.end_frame.saturating_sub(start);
+
+            let frame_values: Vec<u32> = if n_frames >= total_frames {
+                (start..scene.end_frame).collect()
+            } else {
+                // Each scene gets its own stream so results don't depend on the scene order
+                let mut rng =
+                    SplitMix64::new(seed ^ (scene.index as u64).wrapping_mul(GOLDEN_GAMMA));
+
+                // Floyd's algorithm: n distinct offsets in 0..total_frames
+                let mut offsets = BTreeSet::new();
+                for j in (total_frames - n_frames)..total_frames {
+                    let t = rng.below(j as u64 + 1) as u32;
+                    if !offsets.insert(t) {
+                        offsets.insert(j);
+                    }
+                }
+                offsets.into_iter().map(|offset| start + offset).collect()
+            };
+
+            scenes.push(Scene {
+                start_frame: scene.start_frame,
+                end_frame: scene.end_frame,
+                zone_overrides: scene.zone_overrides.clone(),
+                frame_scores: frame_values.into_iter().map(FrameScore::from).collect(),
+                crf: scene.crf,
+                index: scene.index,
+                zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
             });
         }
 
@@ -566,21 +913,174 @@ impl SceneList {
         }
     }
 
+    /// Sets the av1an photon noise of every scene from its measured grain: the scene with
+    /// the median grain gets `base_iso`, the others scale with their grain. The grain is
+    /// the high frequency luma of `GRAIN_SAMPLES` frames per scene, see [`grain_stats`]
+    pub fn assign_grain(&mut self, core: &Core, clip: &VideoNode, base_iso: u32) -> Result<()> {
+        let grain_clip = grain_stats(core, clip)?;
+        let grain_key = format!("{GRAIN_PROP}Diff");
+
+        let pb = ProgressBar::new(self.split_scenes.len() as u64);
+        pb.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {prefix} {wide_bar} {pos}/{len} {msg}",
+            )
+            .unwrap(),
+        );
+        pb.set_prefix("Grain");
+
+        let grain = self
+            .split_scenes
+            .par_iter()
+            .map(|scene| {
+                let scene_len = scene.end_frame.saturating_sub(scene.start_frame);
+                let samples = GRAIN_SAMPLES.min(scene_len).max(1);
+                let mut total = 0.0;
+                for i in 0..samples {
+                    // Center of each of the `samples` equal segments of the scene
+                    let n = scene.start_frame + (2 * i + 1) * scene_len / (2 * samples);
+                    let frame = grain_clip
+                        .get_frame(n as i32)
+                        .map_err(|e| eyre!(e.to_string_lossy().to_string()))?;
+                    let props = frame
+                        .properties()
+                        .ok_or_eyre("Frame properties not found")?;
+                    total +=
+                        props.get_float(KeyStr::from_cstr(&grain_key.as_str().to_cstring()), 0)?;
+                }
+                pb.inc(1);
+                Ok(total / samples as f64)
+            })
+            .collect::<Result<Vec<f64>>>()?;
+        pb.finish();
+
+        let mut sorted = grain.clone();
+        sorted.sort_by(f64::total_cmp);
+        let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+        if median <= 0.0 {
+            return Err(eyre!(
+                "No grain measured, can't scale the photon noise per scene"
+            ));
+        }
+
+        println!("\nPhoton noise per scene (median grain {median:.5} = ISO {base_iso})");
+        for (scene, grain) in self.split_scenes.iter_mut().zip(grain) {
+            let iso = (base_iso as f64 * grain / median)
+                .round()
+                .clamp(MIN_GRAIN_ISO as f64, MAX_GRAIN_ISO as f64) as u32;
+            // av1an takes the strength, ISO / 100
+            let strength = iso.div_ceil(100);
+            scene.zone_overrides.get_or_insert_default().photon_noise = Some(strength);
+            println!(
+                "Scene {}-{}: grain {grain:.5}, ISO {iso} (--photon-noise {strength})",
+                scene.start_frame, scene.end_frame
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Samples `n_frames` per scene, denser where there is more motion. Frames are
+    /// taken at evenly spaced percentiles of the cumulative motion of the scene, so
+    /// static stretches still get some frames. When one frame holds most of the
+    /// motion (e.g. a flash), the missing frames are the next highest motion ones.
+    pub fn with_motion_weighted_frames(
+        &self,
+        core: &Core,
+        clip: &VideoNode,
+        n_frames: u32,
+    ) -> Result<SceneList> {
+        let motion_clip = motion_stats(core, clip)?;
+        let motion_key = format!("{MOTION_PROP}Diff");
+
+        let total_frames: u64 = self
+            .split_scenes
+            .iter()
+            .map(|scene| (scene.end_frame - scene.start_frame) as u64)
+            .sum();
+        let pb = ProgressBar::new(total_frames);
+        pb.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {prefix} {wide_bar} {pos}/{len} {msg}",
+            )
+            .unwrap(),
+        );
+        pb.set_prefix("Motion");
+
+        let mut scenes = Vec::with_capacity(self.split_scenes.len());
+
+        for scene in &self.split_scenes {
+            let start = scene.start_frame;
+            let scene_len = scene.end_frame.saturating_sub(start);
+
+            let frame_values: Vec<u32> = if n_frames >= scene_len {
+                pb.inc(scene_len as u64);
+                (start..scene.end_frame).collect()
+            } else {
+                let motion = (start..scene.end_frame)
+                    .into_par_iter()
+                    .map(|n| {
+                        let frame = motion_clip
+                            .get_frame(n as i32)
+                            .map_err(|e| eyre!(e.to_string_lossy().to_string()))?;
+                        let props = frame
+                            .properties()
+                            .ok_or_eyre("Frame properties not found")?;
+                        let diff = props
+                            .get_float(KeyStr::from_cstr(&motion_key.as_str().to_cstring()), 0)?;
+                        pb.inc(1);
+                        Ok(diff)
+                    })
+                    .collect::<Result<Vec<f64>>>()?;
+
+                select_motion_weighted(&motion, n_frames as usize)
+                    .into_iter()
+                    .map(|offset| start + offset as u32)
+                    .collect()
+            };
+
+            scenes.push(Scene {
+                start_frame: scene.start_frame,
+                end_frame: scene.end_frame,
+                zone_overrides: scene.zone_overrides.clone(),
+                frame_scores: frame_values.into_iter().map(FrameScore::from).collect(),
+                crf: scene.crf,
+                index: scene.index,
+                zoned: scene.zoned,
+                target_quality: scene.target_quality,
+                target_percentile: scene.target_percentile,
+                cut_confidence: scene.cut_confidence,
+            });
+        }
+
+        pb.finish();
+
+        Ok(SceneList {
+            frames: self.frames,
+            scenes: scenes.clone(),
+            split_scenes: scenes,
+        })
+    }
+
     pub fn update_preset(&mut self, new_preset: i32) {
         for scene in &mut self.split_scenes {
             if let Some(ref mut overrides) = scene.zone_overrides
                 && let Some(ref mut params) = overrides.video_params
             {
+                let new_preset = encoder_preset(overrides.encoder.as_deref(), new_preset);
                 let mut found = false;
                 for i in 0..params.len() {
                     if params[i] == "--preset" && i + 1 < params.len() {
-                        params[i + 1] = new_preset.to_string();
+                        params[i + 1] = new_preset.clone();
+                        found = true;
+                    } else if params[i].starts_with("--preset=") {
+                        params[i] = format!("--preset={new_preset}");
                         found = true;
                     }
                 }
                 if !found {
                     params.push("--preset".to_string());
-                    params.push(new_preset.to_string());
+                    params.push(new_preset);
                 }
             }
         }
@@ -594,6 +1094,17 @@ impl SceneList {
         }
     }
 
+    /// Sets the film grain table of every scene, see [`ZoneOverrides::set_grain_table`]
+    pub fn set_grain_table(&mut self, table: &Path) -> Result<()> {
+        for scene in &mut self.split_scenes {
+            scene
+                .zone_overrides
+                .get_or_insert_default()
+                .set_grain_table(table)?;
+        }
+        Ok(())
+    }
+
     pub fn update_encoder_params(&mut self, encoder_params: &str) {
         // Process each split scene
         for scene in &mut self.split_scenes {
@@ -632,11 +1143,21 @@ impl SceneList {
         min_target_quality: f64,
         new_crf: f64,
         percentile: u8,
+        aggregation: AggregationMode,
+        score_targets: &[ScoreTarget],
     ) {
         self.split_scenes.retain_mut(|scene| {
-            let percentile = math::percentile(&scene.frame_scores, percentile);
-            let min_score = math::min_score(&scene.frame_scores);
-            if (percentile < target_quality) || (min_score < min_target_quality) {
+            let target_quality = scene.target_quality.unwrap_or(target_quality);
+            let percentile = scene.target_percentile.unwrap_or(percentile);
+            if aggregation.below_target(
+                &scene.frame_scores,
+                target_quality,
+                min_target_quality,
+                percentile,
+            ) || score_targets
+                .iter()
+                .any(|target| target.below_target(&scene.frame_scores))
+            {
                 scene.update_crf(new_crf);
                 true
             } else {
@@ -693,6 +1214,20 @@ impl SceneList {
         }
     }
 
+    /// Prints the split scenes from the least to the most confident cut
+    pub fn print_cut_confidences(&self) {
+        let mut scenes: Vec<&Scene> = self.split_scenes.iter().collect();
+        scenes.sort_by(|a, b| a.cut_confidence.total_cmp(&b.cut_confidence));
+
+        println!("\nCut confidence (lowest first):");
+        for scene in scenes {
+            println!(
+                "Scene {:>4} | frames {:>7}-{:<7} | confidence {:.4}",
+                scene.index, scene.start_frame, scene.end_frame, scene.cut_confidence
+            );
+        }
+    }
+
     pub fn print_stats(&self) -> Result<()> {
         let score_list = self.to_score_list();
         let stats = score_list.get_stats()?;
@@ -759,7 +1294,7 @@ impl SceneList {
             }
 
             // Write everything at once
-            std::fs::write(crf_data_file, &output)?;
+            write_atomic(crf_data_file, &output)?;
 
             // println!(
             //     "CRF data successfully written to {}",
@@ -773,6 +1308,33 @@ impl SceneList {
         Ok(())
     }
 
+    /// Writes the CRF of every scene with its frame score stats as CSV
+    /// (`scene,crf,start,end,frames,mean,p5,min`), the stats are empty for unmeasured scenes
+    pub fn write_crf_data_csv(&self, path: &Path) -> Result<()> {
+        let mut output = String::from("scene,crf,start,end,frames,mean,p5,min\n");
+        for (i, scene) in self.split_scenes.iter().enumerate() {
+            let stats = if scene.frame_scores.is_empty() {
+                ",,".to_owned()
+            } else {
+                format!(
+                    "{:.4},{:.4},{:.4}",
+                    math::mean(&scene.frame_scores),
+                    math::percentile(&scene.frame_scores, 5),
+                    math::min_score(&scene.frame_scores),
+                )
+            };
+            output.push_str(&format!(
+                "{i},{},{},{},{},{stats}\n",
+                scene.crf,
+                scene.start_frame,
+                scene.end_frame,
+                scene.end_frame - scene.start_frame,
+            ));
+        }
+
+        write_atomic(path, output.as_bytes())
+    }
+
     pub fn all_frames(&self) -> Vec<u32> {
         let mut frames: Vec<u32> = self
             .split_scenes
@@ -811,6 +1373,22 @@ impl SceneList {
         }
     }
 
+    /// Copies the chapter target quality and percentile of the reference scenes (by index)
+    pub fn sync_targets_by_index(&mut self, reference: &SceneList) {
+        let targets: HashMap<u32, (Option<f64>, Option<u8>)> = reference
+            .split_scenes
+            .iter()
+            .map(|scene| (scene.index, (scene.target_quality, scene.target_percentile)))
+            .collect();
+
+        for scene in &mut self.split_scenes {
+            if let Some(&(target_quality, target_percentile)) = targets.get(&scene.index) {
+                scene.target_quality = target_quality;
+                scene.target_percentile = target_percentile;
+            }
+        }
+    }
+
     /// Updates CRF values based on reference scene list (by index)
     pub fn sync_crf_by_index(&mut self, reference: &SceneList) {
         use std::collections::HashMap;
@@ -868,6 +1446,44 @@ impl SceneList {
         }
     }
 
+    /// Sets the frame scores from a `frame,score` CSV (as written by `ScoreList::write_to_csv`),
+    /// the frames are the source frame numbers. Every selected frame has to be in the CSV
+    pub fn apply_scores_from_csv(&mut self, csv: &str) -> Result<()> {
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+        for (line_index, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (line_index == 0 && line.starts_with("frame")) {
+                continue;
+            }
+            let (frame, score) = line
+                .split_once(',')
+                .ok_or_else(|| eyre!("Invalid score line {}: {line}", line_index + 1))?;
+            let frame: u32 = frame
+                .trim()
+                .parse()
+                .map_err(|e| eyre!("Invalid frame on line {}: {e}", line_index + 1))?;
+            let score: f64 = score
+                .trim()
+                .parse()
+                .map_err(|e| eyre!("Invalid score on line {}: {e}", line_index + 1))?;
+            scores.insert(frame, score);
+        }
+
+        for scene in &mut self.split_scenes {
+            for frame_score in &mut scene.frame_scores {
+                frame_score.value = *scores.get(&frame_score.frame).ok_or_else(|| {
+                    eyre!(
+                        "No score for frame {} (scene {}) in the scores CSV",
+                        frame_score.frame,
+                        scene.index
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Prints a summary of all scenes including index, CRF, frame range, and mean score
     pub fn print_updated_data(&self, percentile: u8, crf: f64) {
         println!();
@@ -905,30 +1521,243 @@ impl SceneList {
         self.scenes = self.split_scenes.clone();
     }
 
-    pub fn sync_crf_from_zone_overrides(&mut self) -> Result<(), eyre::Report> {
-        for (idx, scene) in self.split_scenes.iter_mut().enumerate() {
-            let overrides = scene
-                .zone_overrides
-                .as_ref()
-                .ok_or_else(|| eyre::eyre!("Missing zone_overrides in scene {}", idx))?;
+    /// Replaces the ladder CRF of every scene with the CRF where its scores cross the targets,
+    /// linearly interpolated between the cached scores at its CRF (passing) and at the next
+    /// higher measured CRF (failing), and rounded down to a multiple of `crf_step`, the
+    /// smallest step the encoder takes (see [`QualityFlag::crf_step`]). With several targets
+    /// the lowest CRF wins. Scenes without both measurements and zoned scenes keep their CRF.
+    /// Returns how many scenes were changed
+    pub fn interpolate_crf(
+        &mut self,
+        metrics_cache: &MetricsCache,
+        crf_step: f64,
+        score_targets: impl Fn(&Scene) -> Vec<ScoreTarget>,
+    ) -> usize {
+        let mut changed = 0;
+        for scene in &mut self.split_scenes {
+            if scene.zoned {
+                continue;
+            }
+            let targets = score_targets(scene);
+            let Some(passing) = metrics_cache.get_by_index(scene.index, scene.crf) else {
+                continue;
+            };
+            if targets
+                .iter()
+                .any(|target| target.below_target(&passing.frame_scores))
+            {
+                continue;
+            }
+            let Some(failing) = metrics_cache
+                .scene_metrics
+                .iter()
+                .filter(|cached_scene| {
+                    cached_scene.index == scene.index && cached_scene.crf > scene.crf
+                })
+                .min_by(|a, b| a.crf.total_cmp(&b.crf))
+            else {
+                continue;
+            };
 
-            let params = overrides.video_params.as_ref().ok_or_else(|| {
-                eyre::eyre!("Missing video_params in zone_overrides for scene {}", idx)
-            })?;
+            let (crf_pass, crf_fail) = (passing.crf, failing.crf);
+            let crf = targets
+                .iter()
+                .map(|target| {
+                    let score_pass = target.score(&passing.frame_scores);
+                    let score_fail = target.score(&failing.frame_scores);
+                    if score_fail >= target.target() {
+                        crf_fail
+                    } else if score_pass <= score_fail {
+                        crf_pass
+                    } else {
+                        crf_fail
+                            + (target.target() - score_fail) * (crf_pass - crf_fail)
+                                / (score_pass - score_fail)
+                    }
+                })
+                .fold(crf_fail, f64::min);
+            let crf = ((crf / crf_step).floor() * crf_step).clamp(crf_pass, crf_fail);
 
-            let crf_str = find_crf_value_in_params(params)
-                .ok_or_else(|| eyre::eyre!("Missing --crf in video_params for scene {}", idx))?;
+            if crf != scene.crf {
+                scene.update_crf(crf);
+                changed += 1;
+            }
+        }
 
-            let crf = crf_str.parse::<f64>().map_err(|_| {
-                eyre::eyre!(
-                    "Failed to parse '{}' as u8 for CRF in scene {}",
-                    crf_str,
-                    idx
-                )
-            })?;
+        changed
+    }
 
-            scene.crf = crf;
-        }
+    /// Gives each scene of `skipped` the CRF of the nearest scene of `measured`, the lower CRF
+    /// when two are as near. Used when only a subset of the scenes went through the boost loop.
+    /// Returns how many scenes were filled
+    pub fn fill_crf_from_nearest(
+        &mut self,
+        measured: &BTreeSet<u32>,
+        skipped: &BTreeSet<u32>,
+    ) -> usize {
+        let measured_crfs: Vec<(usize, f64)> = self
+            .split_scenes
+            .iter()
+            .enumerate()
+            .filter(|(_, scene)| measured.contains(&scene.index))
+            .map(|(position, scene)| (position, scene.crf))
+            .collect();
+
+        let mut filled = 0;
+        for (position, scene) in self.split_scenes.iter_mut().enumerate() {
+            if !skipped.contains(&scene.index) {
+                continue;
+            }
+            let Some(&(_, crf)) = measured_crfs.iter().min_by(|a, b| {
+                a.0.abs_diff(position)
+                    .cmp(&b.0.abs_diff(position))
+                    .then(a.1.total_cmp(&b.1))
+            }) else {
+                continue;
+            };
+            scene.update_crf(crf);
+            filled += 1;
+        }
+
+        filled
+    }
+
+    /// Replaces the CRF of every scene with the mean or median of the `window` scenes centered
+    /// on it, rounded to the nearest CRF of `ladder`. The result is capped at the scene's own
+    /// CRF, the higher ladder values may have failed its target. Zoned scenes are left out.
+    /// Returns how many scenes were lowered
+    pub fn smooth_crf(&mut self, window: usize, mode: CrfSmoothing, ladder: &[f64]) -> usize {
+        let half = window / 2;
+        let crfs: Vec<Option<f64>> = self
+            .split_scenes
+            .iter()
+            .map(|scene| (!scene.zoned).then_some(scene.crf))
+            .collect();
+
+        let mut lowered = 0;
+        for (i, scene) in self.split_scenes.iter_mut().enumerate() {
+            if scene.zoned {
+                continue;
+            }
+            let end = (i + half + 1).min(crfs.len());
+            let mut neighbors: Vec<f64> = crfs[i.saturating_sub(half)..end]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            let smoothed = match mode {
+                CrfSmoothing::Mean => neighbors.iter().sum::<f64>() / neighbors.len() as f64,
+                CrfSmoothing::Median => {
+                    neighbors.sort_by(f64::total_cmp);
+                    neighbors[neighbors.len() / 2]
+                }
+            };
+            let nearest = ladder
+                .iter()
+                .copied()
+                .min_by(|a, b| (a - smoothed).abs().total_cmp(&(b - smoothed).abs()))
+                .unwrap_or(smoothed);
+
+            if nearest < scene.crf {
+                scene.update_crf(nearest);
+                lowered += 1;
+            }
+        }
+
+        lowered
+    }
+
+    /// Lowers CRFs until consecutive scenes differ by at most `max_delta`, to avoid quality
+    /// pumping at the cuts. CRFs only go down, so no scene ends below the quality it reached,
+    /// and zoned scenes are kept as they are. Returns how many scenes were lowered
+    pub fn clamp_crf_delta(&mut self, max_delta: f64) -> usize {
+        let max_delta = max_delta.max(0.0);
+        let mut lowered = BTreeSet::new();
+
+        // Lowering one scene can break the step with its other neighbor, repeat until stable
+        loop {
+            let mut changed = false;
+            for i in 1..self.split_scenes.len() {
+                let previous = self.split_scenes[i - 1].crf;
+                let current = self.split_scenes[i].crf;
+                let (higher, limit) = if current > previous + max_delta {
+                    (i, previous + max_delta)
+                } else if previous > current + max_delta {
+                    (i - 1, current + max_delta)
+                } else {
+                    continue;
+                };
+                if self.split_scenes[higher].zoned {
+                    continue;
+                }
+                self.split_scenes[higher].update_crf(limit);
+                lowered.insert(higher);
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        lowered.len()
+    }
+
+    /// Coalesces consecutive scenes that ended on the same CRF into a single scene.
+    /// Scenes with different `zoned` flags or encoder params are never merged.
+    pub fn merge_same_crf_scenes(&mut self) {
+        let mut merged: Vec<Scene> = Vec::with_capacity(self.split_scenes.len());
+
+        for scene in self.split_scenes.drain(..) {
+            if let Some(last) = merged.last_mut()
+                && last.crf == scene.crf
+                && last.zoned == scene.zoned
+                && last.end_frame == scene.start_frame
+                && same_encoder_params(last, &scene)
+            {
+                last.end_frame = scene.end_frame;
+                last.frame_scores.extend(scene.frame_scores);
+                continue;
+            }
+            merged.push(scene);
+        }
+
+        for scene in merged.iter_mut() {
+            let crf = scene.crf;
+            scene.update_crf(crf);
+        }
+
+        self.split_scenes = merged;
+        self.assign_indexes();
+    }
+
+    pub fn sync_crf_from_zone_overrides(&mut self) -> Result<(), eyre::Report> {
+        for (idx, scene) in self.split_scenes.iter_mut().enumerate() {
+            let overrides = scene
+                .zone_overrides
+                .as_ref()
+                .ok_or_else(|| eyre::eyre!("Missing zone_overrides in scene {}", idx))?;
+
+            let params = overrides.video_params.as_ref().ok_or_else(|| {
+                eyre::eyre!("Missing video_params in zone_overrides for scene {}", idx)
+            })?;
+
+            let (flag, crf_str) = find_crf_value_in_params(params)
+                .ok_or_else(|| eyre::eyre!("Missing --crf in video_params for scene {}", idx))?;
+
+            let crf = crf_str.parse::<f64>().map_err(|_| {
+                eyre::eyre!("Failed to parse '{}' as a CRF in scene {}", crf_str, idx)
+            })?;
+            if !flag.range().contains(&crf) {
+                return Err(eyre!(
+                    "Invalid {} {crf} in scene {idx}: it has to be between {}-{}",
+                    flag.as_str(),
+                    flag.range().start(),
+                    flag.range().end()
+                ));
+            }
+
+            scene.crf = flag.to_crf(crf);
+        }
         Ok(())
     }
 
@@ -944,6 +1773,34 @@ impl SceneList {
         Ok(())
     }
 
+    /// Splits the scenes that straddle the boundary of a zoned chapter, so each scene
+    /// belongs to a single chapter. Indexes have to be reassigned afterwards
+    pub fn split_at_chapter_boundaries(&mut self, zone_chapters: &ZoneChapters) {
+        let boundaries = zone_chapters.zone_boundaries();
+
+        let mut split_scenes = Vec::with_capacity(self.split_scenes.len());
+        for scene in self.split_scenes.drain(..) {
+            let mut start_frame = scene.start_frame;
+            for &boundary in boundaries
+                .iter()
+                .filter(|&&boundary| boundary > scene.start_frame && boundary < scene.end_frame)
+            {
+                split_scenes.push(Scene {
+                    start_frame,
+                    end_frame: boundary,
+                    ..scene.clone()
+                });
+                start_frame = boundary;
+            }
+            split_scenes.push(Scene {
+                start_frame,
+                ..scene
+            });
+        }
+
+        self.split_scenes = split_scenes;
+    }
+
     /// Applies CRF values from ZoneChapters to scenes that fall mostly (≥80%) within chapter ranges
     pub fn apply_zone_chapters(
         &mut self,
@@ -958,7 +1815,10 @@ impl SceneList {
             }
 
             for zone_chapter in &zone_chapters.chapters {
-                if zone_chapter.crf.is_nan() {
+                if zone_chapter.crf.is_nan()
+                    && zone_chapter.target_quality.is_none()
+                    && zone_chapter.target_percentile.is_none()
+                {
                     continue;
                 }
 
@@ -973,9 +1833,17 @@ impl SceneList {
                 let overlap_len = overlap_end - overlap_start;
 
                 // Check if overlap covers at least 80% of scene
-                if (overlap_len as f64) / (scene_len as f64) >= overlap_percentage
-                    && scene.crf > zone_chapter.crf
-                {
+                let overlaps = (overlap_len as f64) / (scene_len as f64) >= overlap_percentage;
+
+                // A chapter target quality keeps the scene in the boost loop
+                if overlaps && scene.target_quality.is_none() {
+                    scene.target_quality = zone_chapter.target_quality;
+                }
+                if overlaps && scene.target_percentile.is_none() {
+                    scene.target_percentile = zone_chapter.target_percentile;
+                }
+
+                if overlaps && scene.crf > zone_chapter.crf {
                     if !zoning_params.is_empty() {
                         scene.update_encoder_params(zoning_params);
                     }
@@ -1026,17 +1894,298 @@ impl SceneList {
         Ok(())
     }
 
+    /// Fills the scores of the scenes already measured at their current CRF and returns
+    /// the scenes that still need to be encoded and measured.
+    pub fn split_by_metrics_cache(&mut self, cache: &MetricsCache) -> SceneList {
+        let mut pending = Vec::new();
+
+        for scene in &mut self.split_scenes {
+            match cache.get(scene, scene.crf) {
+                Some(cached_scene) => scene.frame_scores = cached_scene.frame_scores.clone(),
+                None => pending.push(scene.clone()),
+            }
+        }
+
+        SceneList {
+            frames: pending
+                .iter()
+                .map(|scene| scene.frame_scores.len() as u32)
+                .sum(),
+            scenes: pending.clone(),
+            split_scenes: pending,
+        }
+    }
+
+    /// Compares the split scenes with the ones of `other`: boundaries only `other` has are added,
+    /// boundaries only `self` has are removed, and scenes present in both with a different CRF
+    /// in their zone overrides are reported as CRF changes
+    pub fn diff(&self, other: &SceneList) -> SceneDiff {
+        let boundaries = |scene_list: &SceneList| -> BTreeSet<u32> {
+            scene_list
+                .split_scenes
+                .iter()
+                .map(|scene| scene.start_frame)
+                .collect()
+        };
+        let (old_boundaries, new_boundaries) = (boundaries(self), boundaries(other));
+
+        let old_crfs: HashMap<(u32, u32), f64> = self
+            .split_scenes
+            .iter()
+            .filter_map(|scene| Some(((scene.start_frame, scene.end_frame), scene.zoned_crf()?)))
+            .collect();
+        let crf_changes = other
+            .split_scenes
+            .iter()
+            .filter_map(|scene| {
+                let old_crf = *old_crfs.get(&(scene.start_frame, scene.end_frame))?;
+                let new_crf = scene.zoned_crf()?;
+                (old_crf != new_crf).then_some(CrfChange {
+                    start_frame: scene.start_frame,
+                    end_frame: scene.end_frame,
+                    old_crf,
+                    new_crf,
+                })
+            })
+            .collect();
+
+        SceneDiff {
+            added: new_boundaries
+                .difference(&old_boundaries)
+                .copied()
+                .collect(),
+            removed: old_boundaries
+                .difference(&new_boundaries)
+                .copied()
+                .collect(),
+            crf_changes,
+        }
+    }
+
     pub fn parse_scene_file(json_path: &Path) -> Result<SceneList> {
         let json_data = fs::read_to_string(json_path)?;
         let scene_list: SceneList = serde_json::from_str(&json_data)?;
         Ok(scene_list)
     }
 
+    /// Moves the start of every scene (but the first) back to the nearest keyframe at or before
+    /// it, so each scene begins on a keyframe of the source. A start never moves back past one
+    /// of the sorted `boundaries` (see [`ZoneChapters::zone_boundaries`]), so each scene stays
+    /// in a single chapter. `keyframes` has to be sorted.
+    /// Scenes left empty are dropped, indexes have to be reassigned afterwards.
+    /// Returns how many scenes were moved
+    pub fn snap_to_keyframes(&mut self, keyframes: &[u32], boundaries: &[u32]) -> usize {
+        let Some(first_start) = self.split_scenes.first().map(|scene| scene.start_frame) else {
+            return 0;
+        };
+        let last_end = self.split_scenes.last().map_or(0, |scene| scene.end_frame);
+
+        let mut moved = 0;
+        let starts: Vec<u32> = self
+            .split_scenes
+            .iter()
+            .enumerate()
+            .map(|(i, scene)| {
+                if i == 0 {
+                    return scene.start_frame;
+                }
+                let previous = keyframes.partition_point(|&keyframe| keyframe <= scene.start_frame);
+                let boundary = boundaries
+                    .partition_point(|&boundary| boundary <= scene.start_frame)
+                    .checked_sub(1)
+                    .map_or(first_start, |b| boundaries[b].max(first_start));
+                let snapped = previous
+                    .checked_sub(1)
+                    .map_or(first_start, |k| keyframes[k].max(first_start))
+                    .max(boundary);
+                if snapped != scene.start_frame {
+                    moved += 1;
+                }
+                snapped
+            })
+            .collect();
+
+        for (i, scene) in self.split_scenes.iter_mut().enumerate() {
+            scene.start_frame = starts[i];
+            scene.end_frame = starts.get(i + 1).copied().unwrap_or(last_end);
+        }
+        self.split_scenes
+            .retain(|scene| scene.end_frame > scene.start_frame);
+
+        moved
+    }
+
+    /// Appends the scenes of `other`, which has to start at the frame where this list ends.
+    /// The scenes are reindexed and `frames` becomes the end of the last scene
+    pub fn concat(&mut self, other: SceneList) -> Result<()> {
+        let end = self.split_scenes.last().map_or(0, |scene| scene.end_frame);
+        if let Some(first) = other.split_scenes.first()
+            && first.start_frame != end
+        {
+            return Err(eyre!(
+                "Scene lists aren't contiguous: the first ends at frame {end}, the next starts at frame {}",
+                first.start_frame
+            ));
+        }
+        for scenes in [&self.split_scenes, &other.split_scenes] {
+            if let Some(pair) = scenes
+                .windows(2)
+                .find(|pair| pair[0].end_frame != pair[1].start_frame)
+            {
+                return Err(eyre!(
+                    "Scene {}-{} isn't followed by a contiguous scene (next starts at frame {})",
+                    pair[0].start_frame,
+                    pair[0].end_frame,
+                    pair[1].start_frame
+                ));
+            }
+        }
+
+        self.split_scenes.extend(other.split_scenes);
+        self.scenes.extend(other.scenes);
+        self.frames = self.split_scenes.last().map_or(0, |scene| scene.end_frame);
+        self.assign_indexes();
+        Ok(())
+    }
+
     pub fn write_scene_list_to_file<'a>(&self, path: &'a Path) -> Result<&'a Path> {
         let json = serde_json::to_string_pretty(&self)?; // pretty format for readability
-        fs::write(path, json)?;
+        write_atomic(path, json)?;
+        Ok(path)
+    }
+
+    /// Writes the start frame of each scene, one per line
+    pub fn write_keyframes_txt<'a>(&self, path: &'a Path) -> Result<&'a Path> {
+        let keyframes: String = self
+            .split_scenes
+            .iter()
+            .map(|scene| format!("{}\n", scene.start_frame))
+            .collect();
+        write_atomic(path, keyframes)?;
+        Ok(path)
+    }
+
+    /// Writes an x264/x265 qpfile forcing an IDR frame (`K`) at the start of each scene
+    pub fn write_qpfile<'a>(&self, path: &'a Path) -> Result<&'a Path> {
+        let qpfile: String = self
+            .split_scenes
+            .iter()
+            .map(|scene| format!("{} K\n", scene.start_frame))
+            .collect();
+        write_atomic(path, qpfile)?;
+        Ok(path)
+    }
+
+    /// Writes an av1an `--zones` file, one `start end encoder [reset] params` line per scene.
+    /// Scenes with zone overrides reset to their own encoder params, the rest only set the CRF
+    /// with the quality flag of `encoder`, the av1an `--encoder` of the encode (SVT-AV1 if none)
+    pub fn write_zones_file<'a>(&self, path: &'a Path, encoder: Option<&str>) -> Result<&'a Path> {
+        let encoder = encoder.unwrap_or("svt-av1");
+        // The zone overrides keep the scene file spelling of SVT-AV1, the zones take av1an's
+        let zone_encoder = |name: &str| {
+            if name == "svt_av1" {
+                "svt-av1".to_owned()
+            } else {
+                name.to_owned()
+            }
+        };
+        let zones: String = self
+            .split_scenes
+            .iter()
+            .map(|scene| {
+                let mut zone = vec![scene.start_frame.to_string(), scene.end_frame.to_string()];
+                match &scene.zone_overrides {
+                    Some(overrides) if overrides.video_params.is_some() => {
+                        zone.push(zone_encoder(
+                            overrides.encoder.as_deref().unwrap_or(encoder),
+                        ));
+                        zone.push("reset".to_owned());
+                        if let Some(passes) = overrides.passes {
+                            zone.push(format!("--passes {passes}"));
+                        }
+                        if let Some(photon_noise) = overrides.photon_noise {
+                            zone.push(format!("--photon-noise {photon_noise}"));
+                        }
+                        zone.extend(overrides.video_params.iter().flatten().cloned());
+                    }
+                    _ => {
+                        zone.push(zone_encoder(encoder));
+                        QualityFlag::from_encoder(Some(encoder)).push(&mut zone, scene.crf);
+                    }
+                }
+                format!("{}\n", zone.join(" "))
+            })
+            .collect();
+        write_atomic(path, zones)?;
         Ok(path)
     }
+
+    pub fn write_scene_list_with_format<'a>(
+        &self,
+        path: &'a Path,
+        format: SceneFileFormat,
+    ) -> Result<&'a Path> {
+        match format {
+            SceneFileFormat::Av1anJson => self.write_scene_list_to_file(path),
+            SceneFileFormat::Keyframes => self.write_keyframes_txt(path),
+            SceneFileFormat::Qpfile => self.write_qpfile(path),
+        }
+    }
+}
+
+/// Differences between two scene lists, see [`SceneList::diff`]
+#[derive(Debug, Default)]
+pub struct SceneDiff {
+    pub added: Vec<u32>,
+    pub removed: Vec<u32>,
+    pub crf_changes: Vec<CrfChange>,
+}
+
+#[derive(Debug)]
+pub struct CrfChange {
+    pub start_frame: u32,
+    pub end_frame: u32,
+    pub old_crf: f64,
+    pub new_crf: f64,
+}
+
+impl SceneDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.crf_changes.is_empty()
+    }
+}
+
+impl fmt::Display for SceneDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Scene diff: {} added, {} removed, {} CRF changes",
+            self.added.len(),
+            self.removed.len(),
+            self.crf_changes.len()
+        )?;
+
+        let mut boundaries: Vec<(u32, char)> = self
+            .added
+            .iter()
+            .map(|&frame| (frame, '+'))
+            .chain(self.removed.iter().map(|&frame| (frame, '-')))
+            .collect();
+        boundaries.sort_unstable();
+        for (frame, change) in boundaries {
+            writeln!(f, "  {change} cut at frame {frame:>7}")?;
+        }
+
+        for change in &self.crf_changes {
+            writeln!(
+                f,
+                "  ~ frames {:>7}-{:<7} | CRF {:>6.2} -> {:>6.2}",
+                change.start_frame, change.end_frame, change.old_crf, change.new_crf
+            )?;
+        }
+
+        fmt::Result::Ok(())
+    }
 }
 
 #[derive(ValueEnum, Clone, Debug, Copy)]
@@ -1044,6 +2193,175 @@ pub enum FramesDistribution {
     Center,
     Evenly,
     StartMiddleEnd,
+    StartEnd,
+    Random,
+    MotionWeighted,
+}
+
+/// How the scores of a scene are reduced to decide if it still needs a lower CRF
+#[derive(ValueEnum, Clone, Debug, Copy, Default)]
+pub enum AggregationMode {
+    /// Percentile below target-quality or min below min-q
+    #[default]
+    PercentileMin,
+    /// Percentile below target-quality
+    Percentile,
+    /// Mean below target-quality
+    Mean,
+    /// Harmonic mean below target-quality
+    HarmonicMean,
+}
+
+impl AggregationMode {
+    /// The targets of the mode as separate constraints, all of them have to hold
+    pub fn score_targets(
+        &self,
+        target_quality: f64,
+        min_target_quality: f64,
+        percentile: u8,
+    ) -> Vec<ScoreTarget> {
+        match self {
+            AggregationMode::PercentileMin => vec![
+                ScoreTarget::Percentile(percentile, target_quality),
+                ScoreTarget::Min(min_target_quality),
+            ],
+            AggregationMode::Percentile => {
+                vec![ScoreTarget::Percentile(percentile, target_quality)]
+            }
+            AggregationMode::Mean => vec![ScoreTarget::Mean(target_quality)],
+            AggregationMode::HarmonicMean => vec![ScoreTarget::HarmonicMean(target_quality)],
+        }
+    }
+
+    /// Whether `scores` miss the targets
+    pub fn below_target(
+        &self,
+        scores: &[FrameScore],
+        target_quality: f64,
+        min_target_quality: f64,
+        percentile: u8,
+    ) -> bool {
+        match self {
+            AggregationMode::PercentileMin => {
+                math::percentile(scores, percentile) < target_quality
+                    || math::min_score(scores) < min_target_quality
+            }
+            AggregationMode::Percentile => math::percentile(scores, percentile) < target_quality,
+            AggregationMode::Mean => math::mean(scores) < target_quality,
+            AggregationMode::HarmonicMean => math::harmonic_mean(scores) < target_quality,
+        }
+    }
+}
+
+/// Average used by [`SceneList::smooth_crf`]
+#[derive(ValueEnum, Clone, Debug, Copy, Default)]
+pub enum CrfSmoothing {
+    #[default]
+    Mean,
+    Median,
+}
+
+/// Extra constraint checked on top of the aggregation, a scene keeps getting a lower CRF
+/// until all of them hold
+#[derive(Clone, Debug, Copy)]
+pub enum ScoreTarget {
+    /// Mean at least the score
+    Mean(f64),
+    /// Percentile (0-100) at least the score
+    Percentile(u8, f64),
+    /// Harmonic mean at least the score
+    HarmonicMean(f64),
+    /// Every frame at least the score
+    Min(f64),
+}
+
+impl ScoreTarget {
+    /// Score of `scores` compared with the target
+    pub fn score(&self, scores: &[FrameScore]) -> f64 {
+        match *self {
+            ScoreTarget::Mean(_) => math::mean(scores),
+            ScoreTarget::Percentile(percentile, _) => math::percentile(scores, percentile),
+            ScoreTarget::HarmonicMean(_) => math::harmonic_mean(scores),
+            ScoreTarget::Min(_) => math::min_score(scores),
+        }
+    }
+
+    pub fn target(&self) -> f64 {
+        match *self {
+            ScoreTarget::Mean(target)
+            | ScoreTarget::Percentile(_, target)
+            | ScoreTarget::HarmonicMean(target)
+            | ScoreTarget::Min(target) => target,
+        }
+    }
+
+    /// Whether `scores` miss the target
+    pub fn below_target(&self, scores: &[FrameScore]) -> bool {
+        self.score(scores) < self.target()
+    }
+}
+
+/// Picks `n` offsets at evenly spaced percentiles of the cumulative `motion`,
+/// topped up with the highest motion frames left when percentiles collide
+fn select_motion_weighted(motion: &[f64], n: usize) -> Vec<usize> {
+    // A small floor so static frames still count towards the percentiles
+    let weights: Vec<f64> = motion.iter().map(|m| m.max(0.0) + 1e-6).collect();
+    let total: f64 = weights.iter().sum();
+
+    let mut selected = BTreeSet::new();
+    let mut cumulative = 0.0;
+    let mut offset = 0;
+    for i in 0..n {
+        let target = total * (i as f64 + 0.5) / n as f64;
+        while offset + 1 < weights.len() && cumulative + weights[offset] < target {
+            cumulative += weights[offset];
+            offset += 1;
+        }
+        selected.insert(offset);
+    }
+
+    if selected.len() < n {
+        let mut by_motion: Vec<usize> = (0..motion.len())
+            .filter(|offset| !selected.contains(offset))
+            .collect();
+        by_motion.sort_by(|a, b| motion[*b].total_cmp(&motion[*a]));
+        selected.extend(by_motion.into_iter().take(n - selected.len()));
+    }
+
+    selected.into_iter().collect()
+}
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Small seeded PRNG (SplitMix64), enough for reproducible frame sampling
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..bound`
+    fn below(&mut self, bound: u64) -> u64 {
+        // Rejection sampling to avoid modulo bias
+        let zone = u64::MAX - (u64::MAX % bound);
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
 }
 
 // New struct definition
@@ -1056,7 +2374,7 @@ pub struct FrameSelection {
 
 #[derive(Debug)]
 pub struct CrfPercentage {
-    pub crf: u8,
+    pub crf: f64,
     pub percentage: f64,
 }
 
@@ -1066,17 +2384,135 @@ pub enum SceneDetectionMethod {
     TransnetV2,
 }
 
-/// Helper function to extract the CRF value following `--crf` in a parameter list.
-pub fn find_crf_value_in_params(params: &[String]) -> Option<&str> {
+#[derive(ValueEnum, Clone, Debug, Copy)]
+pub enum SceneFileFormat {
+    /// av1an scenes JSON
+    Av1anJson,
+    /// Start frame of each scene, one per line
+    Keyframes,
+    /// x264/x265 qpfile with a `K` frame at each scene start
+    Qpfile,
+}
+
+impl SceneFileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SceneFileFormat::Av1anJson => "json",
+            SceneFileFormat::Keyframes => "txt",
+            SceneFileFormat::Qpfile => "qpfile",
+        }
+    }
+}
+
+/// Compares the zone overrides of two scenes ignoring the quality value
+fn same_encoder_params(a: &Scene, b: &Scene) -> bool {
+    let params_without_crf = |scene: &Scene| -> Option<Vec<String>> {
+        let params = scene.zone_overrides.as_ref()?.video_params.as_ref()?;
+        let mut params = params.iter();
+        let mut filtered = Vec::new();
+        let flag = QualityFlag::from_encoder(scene.zone_overrides.as_ref()?.encoder.as_deref());
+        let joined = format!("{}=", flag.as_str());
+        while let Some(param) = params.next() {
+            if param == flag.as_str() {
+                params.next();
+            } else if !param.starts_with(&joined) {
+                filtered.push(param.clone());
+            }
+        }
+        Some(filtered)
+    };
+    params_without_crf(a) == params_without_crf(b)
+}
+
+/// Helper function to extract the quality value in a parameter list, with the flag it was
+/// given with: `--crf`, `--quantizer` (rav1e) or `--cq-level` (aomenc).
+pub fn find_crf_value_in_params(params: &[String]) -> Option<(QualityFlag, &str)> {
+    let flags = [
+        QualityFlag::Crf,
+        QualityFlag::Quantizer,
+        QualityFlag::CqLevel,
+    ];
     let mut iter = params.iter();
     while let Some(arg) = iter.next() {
-        if arg == "--crf" {
-            return iter.next().map(|s| s.as_str());
+        for flag in flags {
+            if arg == flag.as_str() {
+                return iter.next().map(|s| (flag, s.as_str()));
+            }
+            if let Some(value) = arg
+                .strip_prefix(flag.as_str())
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                return Some((flag, value));
+            }
         }
     }
     None
 }
 
+/// Keyframe positions (presentation order frame numbers) of the first video stream of `input`,
+/// read from the packet flags with ffprobe, without decoding
+pub fn probe_keyframes(input: &Path) -> Result<Vec<u32>> {
+    let output = ffprobe_command()
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "packet=pts,flags",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(input)
+        .output()
+        .map_err(|e| eyre!("Failed to run ffprobe: {e}"))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "ffprobe failed on {}: {}",
+            input.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    keyframes_from_packets(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| eyre!("ffprobe found no video packets in {}", input.display()))
+}
+
+/// Keyframe positions from the `pts,flags` lines of ffprobe, in decode order. The frame number
+/// is the rank of the pts. A packet without pts (`N/A`) still takes a frame, right after the
+/// packet decoded before it, but is never a keyframe since its position is a guess.
+/// `None` if there are no packets
+fn keyframes_from_packets(packets: &str) -> Option<Vec<u32>> {
+    let mut last_pts = i64::MIN;
+    let mut packets: Vec<(i64, bool)> = packets
+        .lines()
+        .filter_map(|line| {
+            let (pts, flags) = line.trim().split_once(',')?;
+            Some(match pts.parse().ok() {
+                Some(pts) => {
+                    last_pts = pts;
+                    (pts, flags.contains('K'))
+                }
+                None => (last_pts, false),
+            })
+        })
+        .collect();
+    if packets.is_empty() {
+        return None;
+    }
+    // Stable, so a packet without pts stays after the one it took its pts from
+    packets.sort_by_key(|&(pts, _)| pts);
+
+    Some(
+        packets
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, key))| *key)
+            .map(|(frame, _)| frame as u32)
+            .collect(),
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct MetricsCache {
     pub frames: u32,
@@ -1092,14 +2528,117 @@ impl MetricsCache {
 
     pub fn write_metrics_cache<'a>(&self, path: &'a Path) -> Result<&'a Path> {
         let json = serde_json::to_string_pretty(&self)?;
-        fs::write(path, json)?;
+        write_atomic(path, json)?;
+        Ok(path)
+    }
+
+    /// Cached metrics of `scene` at `crf`. The measured frames must match, otherwise
+    /// the scene was sampled differently and the cache entry is ignored.
+    pub fn get(&self, scene: &Scene, crf: f64) -> Option<&SceneMetrics> {
+        self.scene_metrics.iter().find(|cached_scene| {
+            cached_scene.index == scene.index
+                && cached_scene.crf == crf
+                && cached_scene
+                    .frame_scores
+                    .iter()
+                    .map(|frame_score| frame_score.frame)
+                    .eq(scene
+                        .frame_scores
+                        .iter()
+                        .map(|frame_score| frame_score.frame))
+        })
+    }
+
+    /// Writes the CRF-vs-quality curve of every scene of `scene_list` from the cached
+    /// metrics, with the `percentile` (or the one of the scene's chapter) and min score of each
+    /// measured CRF
+    pub fn write_curves<'a>(
+        &self,
+        scene_list: &SceneList,
+        percentile: u8,
+        path: &'a Path,
+    ) -> Result<&'a Path> {
+        let scenes = scene_list
+            .split_scenes
+            .iter()
+            .map(|scene| {
+                let percentile = scene.target_percentile.unwrap_or(percentile);
+                let mut points: Vec<CurvePoint> = self
+                    .scene_metrics
+                    .iter()
+                    .filter(|cached_scene| cached_scene.index == scene.index)
+                    .map(|cached_scene| CurvePoint {
+                        crf: cached_scene.crf,
+                        percentile_score: math::percentile(&cached_scene.frame_scores, percentile),
+                        min_score: math::min_score(&cached_scene.frame_scores),
+                    })
+                    .collect();
+                points.sort_by(|a, b| a.crf.total_cmp(&b.crf));
+                SceneCurve {
+                    index: scene.index,
+                    start_frame: scene.start_frame,
+                    end_frame: scene.end_frame,
+                    final_crf: scene.crf,
+                    percentile,
+                    points,
+                }
+            })
+            .collect();
+
+        let json = serde_json::to_string_pretty(&SceneCurves { percentile, scenes })?;
+        write_atomic(path, json)?;
         Ok(path)
     }
+
+    /// Cached metrics of the scene with `index` at `crf`, whatever frames were measured
+    pub fn get_by_index(&self, index: u32, crf: f64) -> Option<&SceneMetrics> {
+        self.scene_metrics
+            .iter()
+            .find(|cached_scene| cached_scene.index == index && cached_scene.crf == crf)
+    }
+
+    /// Adds the metrics of every scene, replacing older entries for the same scene and CRF
+    pub fn insert_scenes(&mut self, scene_list: &SceneList) {
+        for scene in &scene_list.split_scenes {
+            self.scene_metrics.retain(|cached_scene| {
+                cached_scene.index != scene.index || cached_scene.crf != scene.crf
+            });
+            self.scene_metrics.push(SceneMetrics::from(scene));
+        }
+    }
+}
+
+/// Rate-quality curves written by [`MetricsCache::write_curves`]
+#[derive(Debug, Serialize)]
+pub struct SceneCurves {
+    pub percentile: u8,
+    pub scenes: Vec<SceneCurve>,
+}
+
+/// Scores of a scene at every CRF it was measured at, sorted by CRF
+#[derive(Debug, Serialize)]
+pub struct SceneCurve {
+    pub index: u32,
+    pub start_frame: u32,
+    pub end_frame: u32,
+    pub final_crf: f64,
+    /// Percentile of `percentile_score`, the one of the scene's chapter if it has its own
+    pub percentile: u8,
+    pub points: Vec<CurvePoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurvePoint {
+    pub crf: f64,
+    pub percentile_score: f64,
+    pub min_score: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct SceneMetrics {
     pub index: u32,
+    #[serde(default)]
+    pub crf: f64,
     pub start_frame: u32,
     pub end_frame: u32,
     pub frame_scores: Vec<FrameScore>,
@@ -1109,9 +2648,250 @@ impl From<&Scene> for SceneMetrics {
     fn from(scene: &Scene) -> Self {
         Self {
             index: scene.index,
+            crf: scene.crf,
             start_frame: scene.start_frame,
             end_frame: scene.end_frame,
             frame_scores: scene.frame_scores.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video_params(overrides: &ZoneOverrides) -> Vec<&str> {
+        overrides
+            .video_params
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn zone_overrides_svt_av1() {
+        let mut overrides =
+            ZoneOverrides::from_params("--encoder svt-av1 --passes 1", "--preset 4", 27.25);
+        assert_eq!(overrides.encoder.as_deref(), Some("svt_av1"));
+        assert_eq!(
+            video_params(&overrides),
+            ["--preset", "4", "--crf", "27.25"]
+        );
+
+        overrides.update_crf(30.0);
+        assert_eq!(video_params(&overrides), ["--preset", "4", "--crf", "30"]);
+    }
+
+    #[test]
+    fn zone_overrides_rav1e() {
+        let mut overrides = ZoneOverrides::from_params("--encoder rav1e", "--speed 4", 30.0);
+        assert_eq!(
+            video_params(&overrides),
+            ["--speed", "4", "--quantizer", "120"]
+        );
+
+        overrides.update_crf(27.25);
+        assert_eq!(
+            video_params(&overrides),
+            ["--speed", "4", "--quantizer", "109"]
+        );
+
+        let scene = Scene {
+            zone_overrides: Some(overrides),
+            ..Default::default()
+        };
+        assert_eq!(scene.zoned_crf(), Some(27.25));
+    }
+
+    #[test]
+    fn zone_overrides_aomenc() {
+        let mut overrides = ZoneOverrides::from_params("--encoder aom", "--cpu-used=4", 30.0);
+        assert_eq!(video_params(&overrides), ["--cpu-used=4", "--cq-level=30"]);
+
+        overrides.update_crf(27.4);
+        assert_eq!(video_params(&overrides), ["--cpu-used=4", "--cq-level=27"]);
+    }
+
+    #[test]
+    fn scene_file_quality_ranges() {
+        let scene_list = |params: &[&str]| SceneList {
+            split_scenes: vec![Scene {
+                zone_overrides: Some(ZoneOverrides {
+                    video_params: Some(params.iter().map(|s| s.to_string()).collect()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut rav1e = scene_list(&["--quantizer", "120"]);
+        rav1e.sync_crf_from_zone_overrides().unwrap();
+        assert_eq!(rav1e.split_scenes[0].crf, 30.0);
+
+        assert!(
+            scene_list(&["--crf", "99"])
+                .sync_crf_from_zone_overrides()
+                .is_err()
+        );
+        assert!(
+            scene_list(&["--crf", "0"])
+                .sync_crf_from_zone_overrides()
+                .is_err()
+        );
+        assert!(
+            scene_list(&["--quantizer", "256"])
+                .sync_crf_from_zone_overrides()
+                .is_err()
+        );
+        assert!(
+            scene_list(&["--cq-level=64"])
+                .sync_crf_from_zone_overrides()
+                .is_err()
+        );
+        assert!(
+            scene_list(&["--cq-level=0"])
+                .sync_crf_from_zone_overrides()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn zones_file_encoders() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zones.txt");
+        let mut scene_list = SceneList {
+            split_scenes: vec![
+                Scene {
+                    start_frame: 0,
+                    end_frame: 10,
+                    zone_overrides: Some(ZoneOverrides::from_params(
+                        "--encoder svt-av1",
+                        "--preset 4",
+                        30.0,
+                    )),
+                    ..Default::default()
+                },
+                Scene {
+                    start_frame: 10,
+                    end_frame: 20,
+                    crf: 25.0,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        scene_list.write_zones_file(&path, None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "0 10 svt-av1 reset --passes 1 --preset 4 --crf 30\n10 20 svt-av1 --crf 25\n"
+        );
+
+        scene_list.split_scenes.remove(0);
+        scene_list.write_zones_file(&path, Some("aom")).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "10 20 aom --cq-level=25\n"
+        );
+        scene_list.write_zones_file(&path, Some("rav1e")).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "10 20 rav1e --quantizer 100\n"
+        );
+    }
+
+    #[test]
+    fn snap_to_keyframes_within_chapters() {
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            ..Default::default()
+        };
+        let mut scene_list = SceneList {
+            split_scenes: vec![
+                scene(0, 40),
+                scene(40, 100),
+                scene(100, 130),
+                scene(130, 200),
+            ],
+            ..Default::default()
+        };
+
+        // The chapter starting at 100 stops the scene start from moving back to 90
+        let moved = scene_list.snap_to_keyframes(&[0, 30, 90, 150], &[100]);
+        assert_eq!(moved, 2);
+        let ranges: Vec<(u32, u32)> = scene_list
+            .split_scenes
+            .iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect();
+        assert_eq!(ranges, [(0, 30), (30, 100), (100, 200)]);
+    }
+
+    #[test]
+    fn x26x_presets() {
+        assert_eq!(encoder_preset(Some("x265"), -1), "placebo");
+        assert_eq!(encoder_preset(Some("x265"), 4), "slow");
+        assert_eq!(encoder_preset(Some("x265"), 8), "fast");
+        assert_eq!(encoder_preset(Some("x265"), 13), "ultrafast");
+        assert_eq!(encoder_preset(Some("x265"), 20), "ultrafast");
+        assert_eq!(encoder_preset(Some("x264"), 4), "slow");
+        assert_eq!(encoder_preset(Some("svt-av1"), 4), "4");
+        assert_eq!(encoder_preset(None, 4), "4");
+    }
+
+    #[test]
+    fn quality_flag_ladders() {
+        assert!(QualityFlag::Crf.check_ladder(&[35.0, 27.5, 1.0]).is_ok());
+        assert!(
+            QualityFlag::CqLevel
+                .check_ladder(&[63.0, 30.0, 0.0])
+                .is_ok()
+        );
+        assert!(QualityFlag::CqLevel.check_ladder(&[30.5]).is_err());
+        assert!(QualityFlag::CqLevel.check_ladder(&[64.0]).is_err());
+        assert!(QualityFlag::Quantizer.check_ladder(&[63.75, 27.25]).is_ok());
+        assert!(QualityFlag::Quantizer.check_ladder(&[27.1]).is_err());
+        assert!(QualityFlag::Quantizer.check_ladder(&[64.0]).is_err());
+    }
+
+    #[test]
+    fn start_end_frames_of_empty_scenes() {
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            ..Default::default()
+        };
+        let scene_list = SceneList {
+            split_scenes: vec![scene(0, 0), scene(0, 10), scene(10, 10), scene(10, 12)],
+            ..Default::default()
+        };
+
+        let frames: Vec<Vec<u32>> = scene_list
+            .with_start_end_frames(4)
+            .split_scenes
+            .iter()
+            .map(|scene| scene.frame_scores.iter().map(|score| score.frame).collect())
+            .collect();
+        assert_eq!(frames, [vec![], vec![0, 1, 8, 9], vec![], vec![10, 11]]);
+
+        let middle_end = scene_list.with_start_middle_end_frames(3);
+        assert!(middle_end.split_scenes[0].frame_scores.is_empty());
+        assert!(middle_end.split_scenes[2].frame_scores.is_empty());
+    }
+
+    #[test]
+    fn keyframes_from_packets_without_pts() {
+        // Decode order with B-frames: I P B B, then a second GOP
+        let packets = "0,K__\n3,___\n1,___\n2,___\n4,K__\n5,___\n";
+        assert_eq!(keyframes_from_packets(packets), Some(vec![0, 4]));
+
+        // A packet without pts still counts, the keyframes after it don't shift
+        let packets = "0,K__\nN/A,___\n2,___\n3,K__\nN/A,K__\n5,___\n6,K__\n";
+        assert_eq!(keyframes_from_packets(packets), Some(vec![0, 3, 6]));
+
+        assert_eq!(keyframes_from_packets(""), None);
+    }
+}
@@ -1,18 +1,20 @@
 use crate::{
-    math::{self, FrameScore, ScoreList},
+    math::{self, FrameScore, MultiScoreList, ScoreList},
     scenes::SceneList,
     vapoursynth::{
-        SourcePlugin, ToCString, TrimComplex, bestsource_invoke, downscale_resolution,
-        ffms2_invoke, inverse_telecine, lsmash_invoke, resize_resolution, select_frames,
-        set_color_metadata, set_output, synchronize_clips, to_crop, trim_clip, vszip_metrics,
+        DownscaleKernel, Ivtc, SourceFormat, SourcePlugin, ToCString, TrimComplex, VszipMetric,
+        bestsource_invoke, downscale_resolution, ffms2_invoke, imwri_invoke, inverse_telecine,
+        lsmash_invoke, luma_only, resize_resolution, select_frames, set_color_metadata, set_output,
+        synchronize_clips, to_crop, trim_clip, vszip_metric, vszip_metrics,
     },
 };
 
 use eyre::{Ok, OptionExt, Result, eyre};
 use indicatif::{ProgressBar, ProgressStyle};
 use quill::*;
-use rayon::iter::{ParallelBridge, ParallelIterator};
-use std::path::Path;
+use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
+use resvg::{tiny_skia, usvg};
+use std::{fs, path::Path};
 use vapoursynth4_rs::{
     core::Core,
     frame::Frame,
@@ -31,10 +33,12 @@ pub fn prepare_clips(
     color_metadata: &str,
     crop: Option<&str>,
     downscale: f64,
+    downscale_kernel: DownscaleKernel,
     resize: Option<&str>,
-    detelecine: bool,
+    detelecine: Option<Ivtc>,
     trim: Option<&str>,
     trim_complex: Option<TrimComplex>,
+    luma_only_metric: bool,
 ) -> Result<(VideoNode, VideoNode)> {
     let (mut reference, mut distorted) = match importer_plugin {
         SourcePlugin::Lsmash => (
@@ -49,6 +53,10 @@ pub fn prepare_clips(
             ffms2_invoke(core, reference_path, temp_dir)?,
             ffms2_invoke(core, distorted_path, temp_dir)?,
         ),
+        SourcePlugin::Imwri => (
+            imwri_invoke(core, reference_path)?,
+            imwri_invoke(core, distorted_path)?,
+        ),
     };
 
     if verbose {
@@ -59,11 +67,19 @@ pub fn prepare_clips(
         );
     }
 
+    let source_format = SourceFormat::of(&reference);
     reference = set_color_metadata(core, &reference, color_metadata)?;
     distorted = set_color_metadata(core, &distorted, color_metadata)?;
 
-    if detelecine {
-        reference = inverse_telecine(core, &reference)?;
+    if let Some(ivtc) = detelecine {
+        reference = inverse_telecine(
+            core,
+            &reference,
+            ivtc.field_order,
+            ivtc.vfm_mode,
+            ivtc.decimate_cycle,
+            ivtc.decimate_dupthresh,
+        )?;
     }
 
     if let Some(trim) = trim.filter(|s| !s.is_empty()) {
@@ -75,19 +91,30 @@ pub fn prepare_clips(
     }
 
     if downscale < 1.0 {
-        reference = downscale_resolution(core, &reference, downscale)?;
-        reference = set_output(core, &reference, color_metadata)?;
+        reference = downscale_resolution(
+            core,
+            &reference,
+            downscale,
+            downscale_kernel.kernel.as_str(),
+            downscale_kernel.taps,
+        )?;
+        reference = set_output(core, &reference, color_metadata, source_format)?;
     }
 
     if let Some(resize) = resize.filter(|s| !s.is_empty()) {
         reference = resize_resolution(core, &reference, resize)?;
-        reference = set_output(core, &reference, color_metadata)?;
+        reference = set_output(core, &reference, color_metadata, source_format)?;
     }
 
     if let Some(trim) = trim_complex {
         (reference, distorted) = synchronize_clips(core, &reference, &distorted, &trim)?;
     }
 
+    if luma_only_metric {
+        reference = luma_only(core, &reference)?;
+        distorted = luma_only(core, &distorted)?;
+    }
+
     if verbose {
         println!(
             "Preprocessed\nReference: {:?}\nDistorted: {:?}\n",
@@ -99,6 +126,59 @@ pub fn prepare_clips(
     Ok((reference, distorted))
 }
 
+/// Checks that the preprocessed clips line up before measuring them, with a hint of the
+/// option that most likely desynchronized them. The hints don't name flags, several tools
+/// call this
+pub fn check_clip_sync(
+    reference: &VideoNode,
+    distorted: &VideoNode,
+    detelecine: Option<Ivtc>,
+    trim: Option<&str>,
+) -> Result<()> {
+    let ref_info = reference.info();
+    let dist_info = distorted.info();
+    if ref_info.num_frames == dist_info.num_frames
+        && ref_info.width == dist_info.width
+        && ref_info.height == dist_info.height
+    {
+        return Ok(());
+    }
+
+    let ref_frames = ref_info.num_frames as f64;
+    let dist_frames = dist_info.num_frames as f64;
+    // Frames left once VDecimate drops one of every `cycle`
+    let decimated = |frames: f64, cycle: i64| frames * (cycle - 1) as f64 / cycle as f64;
+    let close = |a: f64, b: f64| (a - b).abs() <= (a.max(b) * 0.01).max(2.0);
+
+    let hint = if ref_info.width != dist_info.width || ref_info.height != dist_info.height {
+        "The resolutions differ: check the crop, downscale and resize options, they only apply to the reference"
+    } else if let Some(ivtc) = detelecine
+        && close(decimated(dist_frames, ivtc.decimate_cycle), ref_frames)
+    {
+        "Detelecining is only applied to the reference, but the distorted clip wasn't detelecined. Disable detelecining"
+    } else if detelecine.is_none() && close(decimated(ref_frames, 5), dist_frames) {
+        "The distorted clip looks detelecined while the reference isn't. Enable detelecining"
+    } else if trim.is_some_and(|trim| !trim.is_empty()) {
+        "The trim is only applied to the reference: the distorted clip has to be an encode of the trimmed range"
+    } else {
+        "The frame counts differ: trim the extra frames of one of the clips"
+    };
+
+    Err(eyre!(
+        "Reference and distorted clips don't match after preprocessing\nReference: {}x{}, {} frames\nDistorted: {}x{}, {} frames\n{hint}",
+        ref_info.width,
+        ref_info.height,
+        ref_info.num_frames,
+        dist_info.width,
+        dist_info.height,
+        dist_info.num_frames
+    ))
+}
+
+/// Progress of the metric passes, with the throughput and the time left
+const METRIC_PROGRESS_TEMPLATE: &str =
+    "[{elapsed_precise}] {prefix} {wide_bar} {pos}/{len} {per_sec} ETA {eta_precise} {msg}";
+
 #[allow(clippy::too_many_arguments)]
 pub fn ssimu2_frames_selected(
     core: &Core,
@@ -111,10 +191,19 @@ pub fn ssimu2_frames_selected(
     color_metadata: &str,
     crop: Option<&str>,
     downscale: f64,
+    downscale_kernel: DownscaleKernel,
     resize: Option<&str>,
-    detelecine: bool,
+    detelecine: Option<Ivtc>,
     trim: Option<&str>,
+    luma_only: bool,
+    metric_threads: usize,
+    progress_prefix: &str,
 ) -> Result<()> {
+    // Scoped pool, so the metrics don't take the cores of the av1an workers
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(metric_threads)
+        .build()?;
+
     let (reference, distorted) = prepare_clips(
         core,
         reference,
@@ -125,14 +214,17 @@ pub fn ssimu2_frames_selected(
         color_metadata,
         crop,
         downscale,
+        downscale_kernel,
         resize,
         detelecine,
         trim,
         None,
+        luma_only,
     )?;
 
     let all_frames: Vec<u32> = scene_list.all_frames();
     let reference = select_frames(core, &reference, &all_frames)?;
+    check_clip_sync(&reference, &distorted, detelecine, trim)?;
 
     if verbose {
         println!(
@@ -144,70 +236,79 @@ pub fn ssimu2_frames_selected(
 
     let ssimu2 = vszip_metrics(core, &reference, &distorted)?;
 
-    // Calculate total frames to process for progress bar
-    let total_frames = scene_list.all_frames().len();
+    // Every measured frame of every scene as (scene index, position in the scene, position in
+    // the selected clip, original frame number), measured in one flat pass so many short scenes
+    // don't serialize the work
+    let work = metric_positions(scene_list, &all_frames)?;
 
     println!("Calculating Metrics");
-    let pb = ProgressBar::new(total_frames.try_into().unwrap());
-    pb.set_style(
-        ProgressStyle::with_template("[{elapsed_precise}] {prefix} {wide_bar} {pos}/{len} {msg}")
-            .unwrap(),
-    );
-    pb.set_prefix("SSIMU2");
+    let pb = ProgressBar::new(work.len() as u64);
+    pb.set_style(ProgressStyle::with_template(METRIC_PROGRESS_TEMPLATE).unwrap());
+    pb.set_prefix(progress_prefix.to_owned());
 
-    scene_list
-        .split_scenes
-        .iter_mut()
-        .enumerate()
-        .try_for_each(|(scene_index, scene)| {
-            let mut updated_scores: Vec<FrameScore> = (scene.start_frame..scene.end_frame)
-                .par_bridge()
-                .map(|frame_index| {
-                    // Get the FrameScore for this position
-                    let frame_score = scene
-                        .frame_scores
-                        .get((frame_index - scene.start_frame) as usize)
-                        .ok_or_eyre(format!(
-                            "Frame index {frame_index} out of bounds in scene {scene_index}"
-                        ))?;
-
-                    // Get metrics using the frame index (not the frame number)
-                    let frame = ssimu2
-                        .get_frame(frame_index as i32)
-                        .map_err(|e| eyre!(e.to_string_lossy().to_string()))?;
-
-                    let props = frame
-                        .properties()
-                        .ok_or_eyre("Frame properties not found")?;
-                    let value =
-                        props.get_float(KeyStr::from_cstr(&"SSIMULACRA2".to_cstring()), 0)?;
-
-                    if verbose {
-                        println!(
-                            "Scene: {:3}, Frame: {:6}, Score: {:6.2}",
-                            scene_index, frame_score.frame, value
-                        );
-                    }
-
-                    pb.inc(1); // increment progress bar safely from multiple threads
-
-                    Ok(FrameScore {
-                        frame: frame_score.frame, // Keep original frame number
-                        value,
-                    })
-                })
-                .collect::<Result<_>>()?;
+    let scores: Vec<(usize, usize, FrameScore)> = pool.install(|| {
+        work.par_iter()
+            .map(|&(scene_index, slot, frame_index, frame)| {
+                // Get metrics using the frame index (not the frame number)
+                let metric_frame = ssimu2
+                    .get_frame(frame_index as i32)
+                    .map_err(|e| eyre!(e.to_string_lossy().to_string()))?;
+
+                let props = metric_frame
+                    .properties()
+                    .ok_or_eyre("Frame properties not found")?;
+                let value = props.get_float(KeyStr::from_cstr(&"SSIMULACRA2".to_cstring()), 0)?;
+
+                if verbose {
+                    println!(
+                        "Scene: {:3}, Frame: {:6}, Score: {:6.2}",
+                        scene_index, frame, value
+                    );
+                }
 
-            updated_scores.sort_by_key(|fs| fs.frame);
-            scene.frame_scores = updated_scores;
-            Ok(())
-        })?;
+                pb.inc(1); // increment progress bar safely from multiple threads
+
+                Ok((scene_index, slot, FrameScore { frame, value }))
+            })
+            .collect::<Result<_>>()
+    })?;
+
+    // Scatter the scores back into their scenes
+    for (scene_index, slot, score) in scores {
+        scene_list.split_scenes[scene_index].frame_scores[slot] = score;
+    }
+    for scene in &mut scene_list.split_scenes {
+        scene.frame_scores.sort_by_key(|fs| fs.frame);
+    }
 
     pb.finish_with_message("DONE");
     println!();
     Ok(())
 }
 
+/// Maps the frame scores of each scene to their position in the clip built by
+/// `select_frames(all_frames)`. The clip holds the selected frames sorted by frame number,
+/// so the position comes from the frame number itself, not from the scene range: the
+/// selected frames of a scene don't have to be contiguous nor stored in order
+fn metric_positions(
+    scene_list: &SceneList,
+    all_frames: &[u32],
+) -> Result<Vec<(usize, usize, u32, u32)>> {
+    let mut work = Vec::with_capacity(all_frames.len());
+    for (scene_index, scene) in scene_list.split_scenes.iter().enumerate() {
+        for (slot, frame_score) in scene.frame_scores.iter().enumerate() {
+            let position = all_frames.binary_search(&frame_score.frame).map_err(|_| {
+                eyre!(
+                    "Frame {} of scene {scene_index} is not in the selected frames",
+                    frame_score.frame
+                )
+            })?;
+            work.push((scene_index, slot, position as u32, frame_score.frame));
+        }
+    }
+    Ok(work)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn ssimu2(
     core: &Core,
@@ -222,9 +323,12 @@ pub fn ssimu2(
     color_metadata: &str,
     crop: Option<&str>,
     downscale: f64,
+    downscale_kernel: DownscaleKernel,
     resize: Option<&str>,
-    detelecine: bool,
-) -> Result<ScoreList> {
+    detelecine: Option<Ivtc>,
+    with_psnr_ssim: bool,
+    luma_only: bool,
+) -> Result<MultiScoreList> {
     let (reference_node, distorted_node) = prepare_clips(
         core,
         reference,
@@ -235,26 +339,31 @@ pub fn ssimu2(
         color_metadata,
         crop,
         downscale,
+        downscale_kernel,
         resize,
         detelecine,
         trim,
         trim_complex,
+        luma_only,
     )?;
+    check_clip_sync(&reference_node, &distorted_node, detelecine, trim)?;
 
     let ssimu2 = vszip_metrics(core, &reference_node, &distorted_node)?;
+    let psnr_ssim = if with_psnr_ssim {
+        Some(psnr_ssim_metrics(core, &reference_node, &distorted_node)?)
+    } else {
+        None
+    };
     let num_frames = ssimu2.info().num_frames;
 
     let frames_to_process: Vec<u32> = (0..num_frames.try_into().unwrap())
         .step_by(step)
         .collect::<Vec<_>>();
     let pb = ProgressBar::new(frames_to_process.len() as u64);
-    pb.set_style(
-        ProgressStyle::with_template("[{elapsed_precise}] {prefix} {wide_bar} {pos}/{len} {msg}")
-            .unwrap(),
-    );
+    pb.set_style(ProgressStyle::with_template(METRIC_PROGRESS_TEMPLATE).unwrap());
     pb.set_prefix("SSIMU2");
 
-    let mut scores: Vec<FrameScore> = frames_to_process
+    let mut scores: Vec<(FrameScore, Option<(f64, f64)>)> = frames_to_process
         .iter()
         .par_bridge()
         .map(|&i| {
@@ -264,85 +373,237 @@ pub fn ssimu2(
             let props = frame.properties().ok_or_eyre("Props not found")?;
             let score = props.get_float(KeyStr::from_cstr(&"SSIMULACRA2".to_cstring()), 0)?;
 
+            let psnr_ssim_values = match &psnr_ssim {
+                Some(psnr_ssim) => Some(psnr_ssim.frame_values(i)?),
+                None => None,
+            };
+
             if verbose {
-                println!("Frame: {i:6}, Score: {score:6.2}");
+                match psnr_ssim_values {
+                    Some((psnr, ssim)) => println!(
+                        "Frame: {i:6}, Score: {score:6.2}, PSNR: {psnr:6.2}, SSIM: {ssim:6.4}"
+                    ),
+                    None => println!("Frame: {i:6}, Score: {score:6.2}"),
+                }
             }
 
             pb.inc(1); // increment progress bar safely from multiple threads
 
-            Ok(FrameScore {
-                frame: i,
-                value: score,
-            })
+            Ok((
+                FrameScore {
+                    frame: i,
+                    value: score,
+                },
+                psnr_ssim_values,
+            ))
         })
         .collect::<Result<_>>()?;
 
     pb.finish_with_message("DONE");
 
-    scores.sort_by_key(|s| s.frame);
+    scores.sort_by_key(|(s, _)| s.frame);
+
+    let (psnr, ssim) = if with_psnr_ssim {
+        let (psnr, ssim) = scores
+            .iter()
+            .filter_map(|(score, values)| {
+                values.map(|(psnr, ssim)| {
+                    (
+                        FrameScore {
+                            frame: score.frame,
+                            value: psnr,
+                        },
+                        FrameScore {
+                            frame: score.frame,
+                            value: ssim,
+                        },
+                    )
+                })
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        (Some(ScoreList::from(psnr)), Some(ScoreList::from(ssim)))
+    } else {
+        (None, None)
+    };
+
+    Ok(MultiScoreList {
+        ssimu2: ScoreList {
+            scores: scores.into_iter().map(|(score, _)| score).collect(),
+        },
+        psnr,
+        ssim,
+    })
+}
+
+/// PSNR and SSIM of the same clips measured by SSIMU2
+pub struct PsnrSsim {
+    pub psnr: VideoNode,
+    pub ssim: VideoNode,
+}
+
+impl PsnrSsim {
+    /// PSNR and SSIM of frame `n`
+    pub fn frame_values(&self, n: u32) -> Result<(f64, f64)> {
+        let read = |node: &VideoNode, metric: VszipMetric| -> Result<f64> {
+            let frame = node
+                .get_frame(n as i32)
+                .map_err(|e| eyre!(e.to_string_lossy().to_string()))?;
+            let props = frame.properties().ok_or_eyre("Props not found")?;
+            let value = props.get_float(KeyStr::from_cstr(&metric.prop().to_cstring()), 0)?;
+            Ok(value)
+        };
+
+        Ok((
+            read(&self.psnr, VszipMetric::Psnr)?,
+            read(&self.ssim, VszipMetric::Ssim)?,
+        ))
+    }
+}
+
+/// Builds the PSNR and SSIM nodes (vszip Metrics) of prepared clips
+pub fn psnr_ssim_metrics(
+    core: &Core,
+    reference: &VideoNode,
+    distorted: &VideoNode,
+) -> Result<PsnrSsim> {
+    Ok(PsnrSsim {
+        psnr: vszip_metric(core, reference, distorted, VszipMetric::Psnr)?,
+        ssim: vszip_metric(core, reference, distorted, VszipMetric::Ssim)?,
+    })
+}
+
+/// Output format of the plot, picked from the extension of the plot file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotFormat {
+    Svg,
+    Png,
+}
+
+impl PlotFormat {
+    pub fn from_path(path: &Path) -> Result<PlotFormat> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        match extension.as_deref() {
+            Some("svg") => Ok(PlotFormat::Svg),
+            Some("png") => Ok(PlotFormat::Png),
+            _ => Err(eyre!(
+                "Unsupported plot file '{}': extension must be .svg or .png",
+                path.display()
+            )),
+        }
+    }
+}
 
-    Ok(ScoreList { scores })
+/// Per-encode data drawn on the SSIMU2 plot
+struct DistortedSeries {
+    name: String,
+    legend: String,
+    frames: Vec<(u32, f64)>,
+    mean_frames: Vec<(u32, f64)>,
+    deviation_plus_frames: Vec<(u32, f64)>,
+    deviation_minus_frames: Vec<(u32, f64)>,
+    five_percentile_frames: Vec<(u32, f64)>,
+    min_frames: Vec<(u32, f64)>,
+    mean_text: String,
+    deviation_plus_text: String,
+    deviation_minus_text: String,
+    five_percentile_text: String,
+    min_text: String,
+    min_value: f64,
 }
 
+impl DistortedSeries {
+    fn new(distorted: &Path, score_list: &ScoreList) -> Result<Self> {
+        let score_list = &score_list.scores;
+        let frames: Vec<(u32, f64)> = score_list
+            .iter()
+            .map(|frame_score| (frame_score.frame, frame_score.value))
+            .collect();
+        let mean = math::mean(score_list);
+        let deviation = math::standard_deviation(score_list);
+        let deviation_plus = mean + deviation;
+        let deviation_minus = mean - deviation;
+        let percentile_list = math::percentiles(score_list)?;
+        let five_percentile = &percentile_list.percentiles[1];
+        let min = math::min(score_list)?;
+        let min_frames: Vec<(u32, f64)> = min
+            .scores
+            .iter()
+            .map(|frame_score| (frame_score.frame, frame_score.value))
+            .collect();
+        let min_value = min.scores[0].value;
+
+        let constant_line = |value: f64| -> Vec<(u32, f64)> {
+            score_list
+                .iter()
+                .map(|frame_score| (frame_score.frame, value))
+                .collect()
+        };
+
+        let name = distorted
+            .file_name()
+            .ok_or_eyre("Input path has no filename")?
+            .to_str()
+            .ok_or_eyre("Filename not UTF-8")?
+            .to_owned();
+
+        Ok(DistortedSeries {
+            legend: format!("Distorted: {name}"),
+            name,
+            mean_frames: constant_line(mean),
+            deviation_plus_frames: constant_line(deviation_plus),
+            deviation_minus_frames: constant_line(deviation_minus),
+            five_percentile_frames: constant_line(five_percentile.score.value),
+            mean_text: format!("Mean: {mean:.2}"),
+            deviation_plus_text: format!(
+                "Mean + 1 Deviation: {mean:.2} + {deviation:.2} = {deviation_plus:.2}"
+            ),
+            deviation_minus_text: format!(
+                "Mean - 1 Deviation: {mean:.2} - {deviation:.2} = {deviation_minus:.2}"
+            ),
+            five_percentile_text: format!("5th Percentile: {:.2}", five_percentile.score.value),
+            min_text: format!(
+                "Min: Frame {}, Score {:.2}",
+                min_frames[0].0, min_frames[0].1
+            ),
+            frames,
+            min_frames,
+            min_value,
+        })
+    }
+}
+
+/// Plots the SSIMU2 scores of one or more encodes of the same reference.
+/// A single encode gets the full set of stat lines, several encodes are overlaid
+/// with their own color and mean line.
 pub fn create_plot(
-    svg_path: &Path,
-    score_list: &ScoreList,
+    plot_path: &Path,
+    distorted_scores: Vec<(&Path, &ScoreList)>,
     reference: &Path,
-    distorted: &Path,
     scenes: Option<&Path>,
     steps: u32,
+    dpi: f32,
 ) -> Result<()> {
-    let score_list = &score_list.scores;
-    // let frame_scores = score_list.scores;
-    let frames: Vec<(u32, f64)> = score_list
-        .iter()
-        .map(|frame_score| (frame_score.frame, frame_score.value))
-        .collect();
-    let mean = math::mean(score_list);
-    let deviation = math::standard_deviation(score_list);
-    let deviation_plus = mean + deviation;
-    let deviation_minus = mean - deviation;
-    let percentile_list = math::percentiles(score_list)?;
-    let five_percentile = &percentile_list.percentiles[1];
-    let min = math::min(score_list)?;
-    let min_frames: Vec<(u32, f64)> = min
-        .scores
-        .iter()
-        .map(|frame_score| (frame_score.frame, frame_score.value))
-        .collect();
-    let min_value = min.scores[0].value;
+    let plot_format = PlotFormat::from_path(plot_path)?;
 
-    let mean_frames: Vec<(u32, f64)> = score_list
-        .iter()
-        .map(|frame_score| (frame_score.frame, mean))
-        .collect();
-    let deviation_plus_frames: Vec<(u32, f64)> = score_list
-        .iter()
-        .map(|frame_score| (frame_score.frame, deviation_plus))
-        .collect();
-    let deviation_minus_frames: Vec<(u32, f64)> = score_list
-        .iter()
-        .map(|frame_score| (frame_score.frame, deviation_minus))
-        .collect();
-    let five_percentile_frames: Vec<(u32, f64)> = score_list
+    if distorted_scores.is_empty() {
+        return Err(eyre!("No distorted scores to plot"));
+    }
+
+    let series = distorted_scores
         .iter()
-        .map(|frame_score| (frame_score.frame, five_percentile.score.value))
-        .collect();
+        .map(|(distorted, score_list)| DistortedSeries::new(distorted, score_list))
+        .collect::<Result<Vec<_>>>()?;
+    let overlay = series.len() > 1;
 
-    let mean_text = format!("Mean: {mean:.2}");
-    let deviation_plus_text = format!(
-        "Mean + 1 Deviation: {mean:.2} + {deviation:.2} = {:.2}",
-        mean + deviation
-    );
-    let deviation_minus_text = format!(
-        "Mean - 1 Deviation: {mean:.2} - {deviation:.2} = {:.2}",
-        mean - deviation
-    );
-    let five_percentile_text = format!("5th Percentile: {:.2}", five_percentile.score.value);
-    let min_text = format!(
-        "Min: Frame {}, Score {:.2}",
-        min_frames[0].0, min_frames[0].1
-    );
+    let min_value = series
+        .iter()
+        .map(|series| series.min_value)
+        .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .ok_or_eyre("No distorted scores to plot")?;
 
     let reference_name = reference
         .file_name()
@@ -350,19 +611,15 @@ pub fn create_plot(
         .to_str()
         .ok_or_eyre("Filename not UTF-8")?;
     let reference_legend = format!("Reference: {reference_name}");
-    let distorted_name = distorted
-        .file_name()
-        .ok_or_eyre("Input path has no filename")?
-        .to_str()
-        .ok_or_eyre("Filename not UTF-8")?;
-    let distorted_legend = format!("Distorted: {distorted_name}");
 
     let blue = Color::hex("#89b4fa");
     let orange = Color::hex("#fab387");
-    // let pink = Color::hex("#f5c2e7");
+    let pink = Color::hex("#f5c2e7");
     let yellow = Color::hex("#f9e2af");
     let green = Color::hex("#a6e3a1");
     let red = Color::hex("#f38ba8");
+    let mauve = Color::hex("#cba6f7");
+    let teal = Color::hex("#94e2d5");
     let text_color = Color::hex("#cdd6f4");
     let background_color = Color::hex("#1e1e2e");
     let light_gray = Color::hex("#bac2de");
@@ -371,69 +628,125 @@ pub fn create_plot(
     let surface = Color::hex("#45475a");
 
     let scores_title = format!("SSIMU2 Scores (Steps: {steps})");
-    let mut plot_data: Vec<Series<'_, u32, f64>> = vec![
-        Series::builder()
-            .name(&scores_title)
-            .color(green.clone())
-            .data(frames)
-            .marker(Marker::None)
-            .line(Line::Solid)
-            .interpolation(Interpolation::Linear)
-            .line_width(2.0)
-            .build(),
-        Series::builder()
-            .name(&mean_text)
-            .color(blue.clone())
-            .data(mean_frames)
-            .marker(Marker::None)
-            .line(Line::Dotted)
-            .line_width(4.0)
-            .build(),
-        Series::builder()
-            .name(&deviation_plus_text)
-            .color(orange.clone())
-            .data(deviation_plus_frames)
-            .marker(Marker::None)
-            .line(Line::Dotted)
-            .line_width(4.0)
-            .build(),
-        Series::builder()
-            .name(&deviation_minus_text)
-            .color(orange.clone())
-            .data(deviation_minus_frames)
-            .marker(Marker::None)
-            .line(Line::Dotted)
-            .line_width(4.0)
-            .build(),
-        Series::builder()
-            .name(&five_percentile_text)
-            .color(red.clone())
-            .data(five_percentile_frames)
-            .marker(Marker::None)
-            .line(Line::Dotted)
-            .line_width(4.0)
-            .build(),
-        Series::builder()
-            .name(&min_text)
-            .color(yellow.clone())
-            .data(min_frames)
-            .marker(Marker::Cross)
-            .marker_size(20.0)
-            .line(Line::None)
-            .build(),
-        Series::builder()
-            .name(&reference_legend)
-            .data(vec![])
-            .line(Line::None)
-            .color(background_color.clone())
-            .build(),
-        Series::builder()
-            .name(&distorted_legend)
-            .data(vec![])
-            .line(Line::None)
-            .color(background_color.clone())
-            .build(),
-    ];
+    let mut plot_data: Vec<Series<'_, u32, f64>> = Vec::new();
+
+    if overlay {
+        let palette = [
+            green.clone(),
+            blue.clone(),
+            mauve,
+            orange.clone(),
+            teal,
+            pink,
+            yellow.clone(),
+            red.clone(),
+        ];
+
+        for (i, distorted) in series.iter().enumerate() {
+            let color = palette[i % palette.len()].clone();
+            plot_data.extend([
+                Series::builder()
+                    .name(&distorted.legend)
+                    .color(color.clone())
+                    .data(distorted.frames.clone())
+                    .marker(Marker::None)
+                    .line(Line::Solid)
+                    .interpolation(Interpolation::Linear)
+                    .line_width(2.0)
+                    .build(),
+                Series::builder()
+                    .name(&distorted.mean_text)
+                    .color(color.clone())
+                    .data(distorted.mean_frames.clone())
+                    .marker(Marker::None)
+                    .line(Line::Dotted)
+                    .line_width(4.0)
+                    .build(),
+                Series::builder()
+                    .name(&distorted.min_text)
+                    .color(color)
+                    .data(distorted.min_frames.clone())
+                    .marker(Marker::Cross)
+                    .marker_size(20.0)
+                    .line(Line::None)
+                    .build(),
+            ]);
+        }
+
+        plot_data.push(
+            Series::builder()
+                .name(&reference_legend)
+                .data(vec![])
+                .line(Line::None)
+                .color(background_color.clone())
+                .build(),
+        );
+    } else {
+        let distorted = &series[0];
+        plot_data.extend([
+            Series::builder()
+                .name(&scores_title)
+                .color(green.clone())
+                .data(distorted.frames.clone())
+                .marker(Marker::None)
+                .line(Line::Solid)
+                .interpolation(Interpolation::Linear)
+                .line_width(2.0)
+                .build(),
+            Series::builder()
+                .name(&distorted.mean_text)
+                .color(blue.clone())
+                .data(distorted.mean_frames.clone())
+                .marker(Marker::None)
+                .line(Line::Dotted)
+                .line_width(4.0)
+                .build(),
+            Series::builder()
+                .name(&distorted.deviation_plus_text)
+                .color(orange.clone())
+                .data(distorted.deviation_plus_frames.clone())
+                .marker(Marker::None)
+                .line(Line::Dotted)
+                .line_width(4.0)
+                .build(),
+            Series::builder()
+                .name(&distorted.deviation_minus_text)
+                .color(orange.clone())
+                .data(distorted.deviation_minus_frames.clone())
+                .marker(Marker::None)
+                .line(Line::Dotted)
+                .line_width(4.0)
+                .build(),
+            Series::builder()
+                .name(&distorted.five_percentile_text)
+                .color(red.clone())
+                .data(distorted.five_percentile_frames.clone())
+                .marker(Marker::None)
+                .line(Line::Dotted)
+                .line_width(4.0)
+                .build(),
+            Series::builder()
+                .name(&distorted.min_text)
+                .color(yellow.clone())
+                .data(distorted.min_frames.clone())
+                .marker(Marker::Cross)
+                .marker_size(20.0)
+                .line(Line::None)
+                .build(),
+            Series::builder()
+                .name(&reference_legend)
+                .data(vec![])
+                .line(Line::None)
+                .color(background_color.clone())
+                .build(),
+            Series::builder()
+                .name(&distorted.legend)
+                .data(vec![])
+                .line(Line::None)
+                .color(background_color.clone())
+                .build(),
+        ]);
+    }
 
     if let Some(scene_path) = scenes {
         let scenes = SceneList::parse_scene_file(scene_path)?;
@@ -455,7 +768,11 @@ pub fn create_plot(
         }
     }
 
-    let title = format!("SSIMU2 - {distorted_name}");
+    let title = if overlay {
+        format!("SSIMU2 - {reference_name}")
+    } else {
+        format!("SSIMU2 - {}", series[0].name)
+    };
 
     let plot = Plot::builder()
         .dimensions((4200, 1800))
@@ -532,7 +849,268 @@ pub fn create_plot(
         .data(plot_data)
         .build();
 
-    plot.to_svg(svg_path.to_str().ok_or_eyre("Filename not UTF-8")?)?;
+    match plot_format {
+        PlotFormat::Svg => plot.to_svg(plot_path.to_str().ok_or_eyre("Filename not UTF-8")?)?,
+        PlotFormat::Png => {
+            // Render the same plot to a temporary SVG and rasterize it
+            let svg_file = tempfile::Builder::new().suffix(".svg").tempfile()?;
+            plot.to_svg(svg_file.path().to_str().ok_or_eyre("Filename not UTF-8")?)?;
+            svg_to_png(svg_file.path(), plot_path, dpi)?;
+        }
+    }
 
     Ok(())
 }
+
+/// Plots the distribution of SSIMU2 scores as an SVG histogram with 1-point bins.
+/// The mean and 5th percentile are drawn as vertical marker lines.
+pub fn create_histogram(
+    histogram_path: &Path,
+    score_list: &ScoreList,
+    distorted: &Path,
+    steps: u32,
+) -> Result<()> {
+    let scores = &score_list.scores;
+    let min_value = math::min(scores)?.scores[0].value;
+    let first_bin = min_value.floor() as i64;
+    let last_bin = 100;
+
+    let mut counts = vec![0u32; (last_bin - first_bin + 1) as usize];
+    for frame_score in scores {
+        let bin = (frame_score.value.floor() as i64).clamp(first_bin, last_bin);
+        counts[(bin - first_bin) as usize] += 1;
+    }
+    let max_count = counts.iter().copied().max().unwrap_or(0) as f64;
+
+    // Draw each bin as a step so the bars read as a single outline
+    let mut bins: Vec<(f64, f64)> = Vec::with_capacity(counts.len() * 2 + 2);
+    bins.push((first_bin as f64, 0.0));
+    for (i, count) in counts.iter().enumerate() {
+        let start = (first_bin + i as i64) as f64;
+        bins.push((start, *count as f64));
+        bins.push((start + 1.0, *count as f64));
+    }
+    bins.push(((last_bin + 1) as f64, 0.0));
+
+    let mean = math::mean(scores);
+    let percentile_list = math::percentiles(scores)?;
+    let five_percentile = percentile_list.percentiles[1].score.value;
+
+    let distorted_name = distorted
+        .file_name()
+        .ok_or_eyre("Input path has no filename")?
+        .to_str()
+        .ok_or_eyre("Filename not UTF-8")?;
+
+    let blue = Color::hex("#89b4fa");
+    let green = Color::hex("#a6e3a1");
+    let red = Color::hex("#f38ba8");
+    let text_color = Color::hex("#cdd6f4");
+    let background_color = Color::hex("#1e1e2e");
+    let light_gray = Color::hex("#bac2de");
+    let surface = Color::hex("#45475a");
+
+    let bins_title = format!("Frames per SSIMU2 point (Steps: {steps})");
+    let mean_text = format!("Mean: {mean:.2}");
+    let five_percentile_text = format!("5th Percentile: {five_percentile:.2}");
+    let distorted_legend = format!("Distorted: {distorted_name}");
+
+    let plot_data: Vec<Series<'_, f64, f64>> = vec![
+        Series::builder()
+            .name(&bins_title)
+            .color(green.clone())
+            .data(bins)
+            .marker(Marker::None)
+            .line(Line::Solid)
+            .interpolation(Interpolation::Linear)
+            .line_width(2.0)
+            .build(),
+        Series::builder()
+            .name(&mean_text)
+            .color(blue.clone())
+            .data(vec![(mean, 0.0), (mean, max_count)])
+            .marker(Marker::None)
+            .line(Line::Dotted)
+            .line_width(4.0)
+            .build(),
+        Series::builder()
+            .name(&five_percentile_text)
+            .color(red.clone())
+            .data(vec![(five_percentile, 0.0), (five_percentile, max_count)])
+            .marker(Marker::None)
+            .line(Line::Dotted)
+            .line_width(4.0)
+            .build(),
+        Series::builder()
+            .name(&distorted_legend)
+            .data(vec![])
+            .line(Line::None)
+            .color(background_color.clone())
+            .build(),
+    ];
+
+    let title = format!("SSIMU2 Histogram - {distorted_name}");
+
+    let plot = Plot::builder()
+        .dimensions((2400, 1350))
+        .title(&title)
+        .title_config(TitleConfig {
+            font_size: 40.0,
+            color: text_color.clone(),
+        })
+        .background_color(background_color.clone())
+        .axis_config(AxisConfig {
+            color: light_gray.clone(),
+            ..Default::default()
+        })
+        .grid_config(GridConfig {
+            line_width: 1.0,
+            minor_line_width: 0.6,
+            show_x_grid: false,
+            x_color: light_gray.clone(),
+            y_color: light_gray.clone(),
+            minor_x_color: light_gray.clone(),
+            minor_y_color: light_gray.clone(),
+            ..Default::default()
+        })
+        .x_label("Scores")
+        .x_label_config(LabelConfig {
+            font_size: 32.0,
+            color: text_color.clone(),
+        })
+        .y_label("Frames")
+        .y_label_config(LabelConfig {
+            font_size: 32.0,
+            color: text_color.clone(),
+        })
+        .x_range(Range::Manual {
+            min: first_bin as f64,
+            max: (last_bin + 1) as f64,
+        })
+        .y_range(Range::Manual {
+            min: 0.0,
+            max: (max_count * 1.1).max(1.0),
+        })
+        .legend(Legend::TopLeftInside)
+        .legend_config(LegendConfig {
+            font_size: 32.0,
+            padding: 20.0,
+            item_height: 40.0,
+            color_swatch_width: 32.0,
+            text_offset: 10.0,
+            text_color: text_color.clone(),
+            border_color: text_color.clone(),
+            background_color: surface.clone(),
+        })
+        .grid(Grid::Dotted)
+        .tick_config(TickConfig {
+            density_x: 100.0,
+            density_y: 100.0,
+            length: 10.0,
+            minor_tick_length: 6.0,
+            font_size: 25.0,
+            text_padding: 6.0,
+            line_color: text_color.clone(),
+            label_color: text_color.clone(),
+            minor_tick_color: text_color.clone(),
+            show_x_decimals: false,
+            ..Default::default()
+        })
+        .margin(Margin {
+            top: 120.0,
+            bottom: 150.0,
+            left: 180.0,
+            right: 60.0,
+        })
+        .font("Fredoka")
+        .data(plot_data)
+        .build();
+
+    plot.to_svg(histogram_path.to_str().ok_or_eyre("Filename not UTF-8")?)?;
+
+    Ok(())
+}
+
+/// Rasterizes an SVG file into a PNG. `dpi` is relative to the SVG's 96 DPI user units.
+pub fn svg_to_png(svg_path: &Path, png_path: &Path, dpi: f32) -> Result<()> {
+    if dpi <= 0.0 {
+        return Err(eyre!("Plot DPI must be positive (got {dpi})"));
+    }
+
+    let svg_data = fs::read(svg_path)?;
+
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_data(&svg_data, &options)?;
+
+    let scale = dpi / 96.0;
+    let size = tree
+        .size()
+        .to_int_size()
+        .scale_by(scale)
+        .ok_or_eyre("Invalid PNG dimensions")?;
+
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_eyre("Failed to allocate PNG buffer")?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    pixmap.save_png(png_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenes::Scene;
+
+    fn scene(start_frame: u32, end_frame: u32, frames: &[u32]) -> Scene {
+        Scene {
+            start_frame,
+            end_frame,
+            frame_scores: frames
+                .iter()
+                .map(|&frame| FrameScore::from(frame))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn metric_positions_gapped_selections() {
+        let scene_list = SceneList {
+            frames: 300,
+            split_scenes: vec![
+                scene(0, 100, &[0, 40, 99]),
+                // Stored out of order, e.g. after middle frames were added
+                scene(100, 180, &[150, 100, 120]),
+                scene(180, 181, &[180]),
+                scene(181, 300, &[290, 200]),
+            ],
+            ..Default::default()
+        };
+        let all_frames = scene_list.all_frames();
+        assert_eq!(all_frames, [0, 40, 99, 100, 120, 150, 180, 200, 290]);
+
+        assert_eq!(
+            metric_positions(&scene_list, &all_frames).unwrap(),
+            [
+                (0, 0, 0, 0),
+                (0, 1, 1, 40),
+                (0, 2, 2, 99),
+                (1, 0, 5, 150),
+                (1, 1, 3, 100),
+                (1, 2, 4, 120),
+                (2, 0, 6, 180),
+                (3, 0, 8, 290),
+                (3, 1, 7, 200),
+            ]
+        );
+
+        // A frame that wasn't selected can't be measured
+        assert!(metric_positions(&scene_list, &[0, 40, 99, 100, 120, 150, 180, 200]).is_err());
+    }
+}
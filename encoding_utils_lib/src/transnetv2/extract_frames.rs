@@ -2,9 +2,9 @@ use eyre::Result;
 use eyre::eyre;
 use indicatif::{ProgressBar, ProgressStyle};
 use iter_chunks::IterChunks;
-use ndarray::{Array4, ArrayView2, ArrayView4, Axis, ShapeBuilder, s};
-// use rayon::iter::ParallelBridge;
-// use rayon::iter::ParallelIterator;
+use ndarray::{Array3, Array4, ArrayView2, ArrayView4, Axis, CowArray, Ix4, ShapeBuilder, s};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::collections::VecDeque;
 use vapoursynth4_rs::{
     frame::VideoFrame,
     node::{Node, VideoNode},
@@ -56,18 +56,24 @@ impl From<FrameShape> for (i64, i64, i64) {
 }
 
 impl VideoConfig {
-    pub fn get_frames(&self) -> Result<Vec<VideoFrame>> {
+    /// Fetches every frame as `(height, width, channels)` pixels, requesting them from several
+    /// threads. VapourSynth requests on a node are thread-safe, but the frames are copied to
+    /// owned arrays inside the worker so no frame reference leaves the thread that requested
+    /// it. How much this helps over serial requests depends on the source filter and hasn't
+    /// been measured
+    pub fn get_frames(&self, height: usize, width: usize) -> Result<Vec<Array3<u8>>> {
         let pb = self.create_progress_bar("Extracting frames");
 
-        let mut frames: Vec<(usize, VideoFrame)> = (0..self.total_frames)
-            // .par_bridge()
+        let mut frames: Vec<(usize, Array3<u8>)> = (0..self.total_frames)
+            .par_bridge()
             .map(|n| {
                 let frame = self
                     .src
                     .get_frame(n.try_into().unwrap())
                     .map_err(|e| eyre!("Failed to load frame {}: {}", n, e.to_string_lossy()))?;
+                let pixels = Self::frame_to_array(&frame, height, width);
                 pb.inc(1);
-                Ok((n, frame))
+                Ok((n, pixels))
             })
             .collect::<Result<Vec<_>>>()?;
 
@@ -81,6 +87,22 @@ impl VideoConfig {
         Ok(frames.into_iter().map(|(_, frame)| frame).collect())
     }
 
+    /// Copies the 3 planes of an RGB frame to a `(height, width, channels)` array
+    fn frame_to_array(frame: &VideoFrame, height: usize, width: usize) -> Array3<u8> {
+        let mut pixels = Array3::<u8>::zeros((height, width, 3));
+        for c in 0..3 {
+            let plane_ptr = frame.plane(c);
+            let stride = frame.stride(c) as usize;
+
+            unsafe {
+                let plane_view =
+                    ArrayView2::from_shape_ptr((height, width).strides((stride, 1)), plane_ptr);
+                pixels.slice_mut(s![.., .., c]).assign(&plane_view);
+            }
+        }
+        pixels
+    }
+
     pub fn frame_batches(&self) -> impl Iterator<Item = Result<Vec<VideoFrame>>> + '_ {
         // Create the frame iterator
         let frame_iter = (0..self.total_frames).map(move |n| {
@@ -96,14 +118,30 @@ impl VideoConfig {
         std::iter::from_fn(move || chunks.next().map(|chunk| chunk.collect()))
     }
 
-    /// Main processing pipeline
-    pub fn process_frames(&self) -> Result<Array4<f32>> {
+    /// Main processing pipeline. `pad_start` and `pad_end` are the frames repeated before
+    /// the first and after the last frame, see `InferenceWindow::padding`.
+    pub fn process_frames(&self, pad_start: usize, pad_end: usize) -> Result<Array4<f32>> {
         let (height, width, channels) = self.validate_dimensions()?;
 
         let all_frames = self.modify_frames(height, width, channels)?;
 
         let frames_f32 = self.concatenate_and_convert(all_frames)?;
-        self.create_padded_frames(frames_f32, height, width, channels)
+        self.create_padded_frames(frames_f32, height, width, channels, pad_start, pad_end)
+    }
+
+    /// Same frames as `process_frames`, fetched on demand instead of all at once
+    pub fn stream_frames(&self, pad_start: usize, pad_end: usize) -> Result<FrameStream<'_>> {
+        let (height, width, _) = self.validate_dimensions()?;
+
+        Ok(FrameStream {
+            config: self,
+            height,
+            width,
+            pad_start,
+            len: pad_start + self.total_frames + pad_end,
+            first: 0,
+            frames: VecDeque::new(),
+        })
     }
 
     /// Creates progress bar with consistent styling
@@ -119,12 +157,23 @@ impl VideoConfig {
         pb
     }
 
-    /// Validates frame dimensions
+    /// Validates frame dimensions, the clip must already be resized to `frame_shape`
     pub fn validate_dimensions(&self) -> Result<(usize, usize, usize)> {
         let (h, w, c) = self.frame_shape.as_tuple();
+        if self.total_frames == 0 {
+            return Err(eyre!("The clip has no frames"));
+        }
         if c != 3 {
             return Err(eyre!("Expected 3 channels (RGB), got {}", c));
         }
+        let info = self.src.info();
+        if info.width as usize != w || info.height as usize != h {
+            return Err(eyre!(
+                "Frames are {}x{} but the inference size is {w}x{h}, resize the clip first",
+                info.width,
+                info.height
+            ));
+        }
         Ok((h, w, c))
     }
 
@@ -136,13 +185,13 @@ impl VideoConfig {
         channels: usize,
     ) -> Result<Vec<Array4<u8>>> {
         let mut batch_frames = Vec::new();
-        let all_frames = self.get_frames()?;
+        let all_frames = self.get_frames(height, width)?;
 
         for batch in all_frames.chunks(self.batch.try_into().unwrap()) {
             let batch_size = batch.len();
             let mut batch_arr = Array4::<u8>::zeros((batch_size, height, width, channels));
 
-            self.process_batch(batch, &mut batch_arr, height, width)?;
+            self.process_batch(batch, &mut batch_arr)?;
             batch_frames.push(batch_arr);
         }
 
@@ -150,24 +199,9 @@ impl VideoConfig {
     }
 
     /// Processes a single batch of frames
-    pub fn process_batch(
-        &self,
-        frames: &[VideoFrame],
-        batch_arr: &mut Array4<u8>,
-        height: usize,
-        width: usize,
-    ) -> Result<()> {
+    pub fn process_batch(&self, frames: &[Array3<u8>], batch_arr: &mut Array4<u8>) -> Result<()> {
         for (i, frame) in frames.iter().enumerate() {
-            for c in 0..3 {
-                let plane_ptr = frame.plane(c);
-                let stride = frame.stride(c) as usize;
-
-                unsafe {
-                    let plane_view =
-                        ArrayView2::from_shape_ptr((height, width).strides((stride, 1)), plane_ptr);
-                    batch_arr.slice_mut(s![i, .., .., c]).assign(&plane_view);
-                }
-            }
+            batch_arr.slice_mut(s![i, .., .., ..]).assign(frame);
         }
         Ok(())
     }
@@ -181,26 +215,28 @@ impl VideoConfig {
         Ok(concatenated.mapv(|x| x as f32))
     }
 
-    /// Creates padded frames
+    /// Creates padded frames, repeating the first frame `pad_start` times and the last one
+    /// `pad_end` times
     pub fn create_padded_frames(
         &self,
         frames_f32: Array4<f32>,
         height: usize,
         width: usize,
         channels: usize,
+        pad_start: usize,
+        pad_end: usize,
     ) -> Result<Array4<f32>> {
         let pad_start = self.create_padding(
             frames_f32.slice(s![0..1, .., .., ..]),
-            25,
+            pad_start,
             height,
             width,
             channels,
         )?;
 
-        let pad_size = 25 + (50 - (self.total_frames % 50).min(50));
         let pad_end = self.create_padding(
             frames_f32.slice(s![-1.., .., .., ..]),
-            pad_size,
+            pad_end,
             height,
             width,
             channels,
@@ -228,3 +264,87 @@ impl VideoConfig {
             .ok_or_else(|| eyre!("Broadcast failed for padding"))
     }
 }
+
+/// Padded frames of a clip, either all in memory or streamed from VapourSynth
+pub enum PaddedFrames<'a> {
+    Full(Array4<f32>),
+    Stream(FrameStream<'a>),
+}
+
+impl PaddedFrames<'_> {
+    /// Number of padded frames
+    pub fn len(&self) -> usize {
+        match self {
+            PaddedFrames::Full(frames) => frames.shape()[0],
+            PaddedFrames::Stream(stream) => stream.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Padded frames `start..end`. Requests must not go back before the start of the
+    /// previous one when streaming
+    pub fn range(&mut self, start: usize, end: usize) -> Result<CowArray<'_, f32, Ix4>> {
+        match self {
+            PaddedFrames::Full(frames) => Ok(frames.slice(s![start..end, .., .., ..]).into()),
+            PaddedFrames::Stream(stream) => Ok(stream.range(start, end)?.into()),
+        }
+    }
+}
+
+/// Padded frames fetched as they are needed, only the frames of the last requested range
+/// are kept in memory
+pub struct FrameStream<'a> {
+    config: &'a VideoConfig,
+    height: usize,
+    width: usize,
+    pad_start: usize,
+    len: usize,
+    /// Padded index of the first frame in `frames`
+    first: usize,
+    frames: VecDeque<Array3<f32>>,
+}
+
+impl FrameStream<'_> {
+    pub fn range(&mut self, start: usize, end: usize) -> Result<Array4<f32>> {
+        if start < self.first || end > self.len {
+            return Err(eyre!(
+                "Frame range {start}..{end} is outside of the stream ({}..{})",
+                self.first,
+                self.len
+            ));
+        }
+
+        // Drop the frames before the range
+        while self.first < start && !self.frames.is_empty() {
+            self.frames.pop_front();
+            self.first += 1;
+        }
+        if self.frames.is_empty() {
+            self.first = start;
+        }
+
+        // Padding repeats the first and last frames of the clip
+        let last_frame = self.config.total_frames - 1;
+        while self.first + self.frames.len() < end {
+            let padded_index = self.first + self.frames.len();
+            let n = padded_index.saturating_sub(self.pad_start).min(last_frame);
+            let frame = self
+                .config
+                .src
+                .get_frame(n.try_into().unwrap())
+                .map_err(|e| eyre!("Failed to load frame {}: {}", n, e.to_string_lossy()))?;
+            let pixels = VideoConfig::frame_to_array(&frame, self.height, self.width);
+            self.frames.push_back(pixels.mapv(|x| x as f32));
+        }
+
+        let views: Vec<_> = self
+            .frames
+            .range(start - self.first..end - self.first)
+            .map(|frame| frame.view())
+            .collect();
+        ndarray::stack(Axis(0), &views).map_err(|e| eyre!("Stacking frames failed: {}", e))
+    }
+}
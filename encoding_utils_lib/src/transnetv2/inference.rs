@@ -1,14 +1,109 @@
-use std::{fs::File, path::Path};
+use std::{fs::File, io::BufWriter, path::Path};
 
 use crate::{
     scenes::{Scene, SceneList},
-    transnetv2::extract_frames::VideoConfig,
+    transnetv2::extract_frames::{PaddedFrames, VideoConfig},
+    vapoursynth::write_atomic,
 };
-use eyre::Result;
+use clap::ValueEnum;
+use eyre::{Result, eyre};
 use ndarray::{Array3, Axis, s};
 use ort::{session::Session, value::Tensor};
+use serde::Serialize;
 use std::io::Write;
 
+/// Neighbour a scene shorter than the minimum scene length is merged into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MergeDirection {
+    /// Into the next scene
+    #[default]
+    Forward,
+    /// Into the previous scene
+    Backward,
+    /// Into the shorter of both neighbours
+    Nearest,
+}
+
+/// Temporal window the model is run on. Only the frames in `center_start..center_end`
+/// of every window are kept, and consecutive windows are `stride` frames apart.
+#[derive(Debug, Clone, Copy)]
+pub struct InferenceWindow {
+    pub window_size: usize,
+    pub stride: usize,
+    pub center_start: usize,
+    pub center_end: usize,
+}
+
+impl Default for InferenceWindow {
+    fn default() -> Self {
+        Self {
+            window_size: 100,
+            stride: 50,
+            center_start: 25,
+            center_end: 75,
+        }
+    }
+}
+
+impl InferenceWindow {
+    /// Builds the window from CLI values. `center_range` format: START:END (e.g. 25:75)
+    pub fn new(window_size: usize, stride: usize, center_range: &str) -> Result<Self> {
+        let (start_str, end_str) = center_range
+            .split_once(':')
+            .ok_or_else(|| eyre!("Invalid center range '{center_range}', expected START:END"))?;
+        let center_start: usize = start_str
+            .trim()
+            .parse()
+            .map_err(|_| eyre!("Invalid center range start: '{start_str}'"))?;
+        let center_end: usize = end_str
+            .trim()
+            .parse()
+            .map_err(|_| eyre!("Invalid center range end: '{end_str}'"))?;
+
+        if stride == 0 || stride > window_size {
+            return Err(eyre!(
+                "Stride ({stride}) must be between 1 and the window size ({window_size})"
+            ));
+        }
+        if center_start >= center_end || center_end > window_size {
+            return Err(eyre!(
+                "Center range {center_start}:{center_end} must be inside the window (0:{window_size})"
+            ));
+        }
+        if center_end - center_start != stride {
+            return Err(eyre!(
+                "Center range {center_start}:{center_end} must be exactly one stride ({stride}) long"
+            ));
+        }
+
+        Ok(Self {
+            window_size,
+            stride,
+            center_start,
+            center_end,
+        })
+    }
+
+    /// Frames repeated before and after a clip of `total_frames` frames. The start gets the
+    /// context before the first center, the end the context after the last one plus up to a
+    /// whole number of strides, so even a clip shorter than a window fills one
+    pub fn padding(&self, total_frames: usize) -> (usize, usize) {
+        let pad_start = self.center_start;
+        let pad_end = self.window_size - self.center_end + self.stride - total_frames % self.stride;
+        (pad_start, pad_end)
+    }
+
+    /// Start of every window over the padded clip, their centers cover every frame
+    pub fn window_starts(&self, total_frames: usize) -> Vec<usize> {
+        let (pad_start, pad_end) = self.padding(total_frames);
+        let padded_len = pad_start + total_frames + pad_end;
+        (0..)
+            .map(|i| i * self.stride)
+            .take_while(|ptr| ptr + self.window_size <= padded_len)
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct SceneDetector {
     // Predictions
@@ -25,13 +120,22 @@ pub struct SceneDetector {
     pub fade_threshold_low: f32,
     pub min_fade_len: usize,
     pub merge_gap: usize,
-    // pub fade_threshold_high: f32,
+    /// A fade only starts if some frame reaches it, then lasts while above `fade_threshold_low`
+    pub fade_threshold_high: f32,
 
     // Windowing parameters
     pub window_size: usize,
     pub stride: usize,
     pub center_start: usize,
     pub center_end: usize,
+    /// Number of windows sent to the session in a single run
+    pub batch_size: usize,
+    /// Fetch frames for every batch of windows instead of loading the whole clip first
+    pub low_memory: bool,
+    /// Where scenes shorter than `min_scene_len` are merged
+    pub short_scene_merge: MergeDirection,
+    /// Split scenes longer than `extra_split` at their strongest sub-cut instead of the midpoint
+    pub split_at_sub_cuts: bool,
 }
 
 impl Default for SceneDetector {
@@ -43,7 +147,7 @@ impl Default for SceneDetector {
             min_scene_len: 24,      // ~1 second at 24fps
             extra_split: 240,       // ~10 seconds at 24fps
             extra_split_fades: 120, // ~5 seconds at 24fps
-            // fade_threshold_high: 1.0,
+            fade_threshold_high: 0.05,
             fade_threshold_low: 0.05,
             min_fade_len: 5,
             merge_gap: 4,
@@ -51,6 +155,10 @@ impl Default for SceneDetector {
             stride: 50,
             center_start: 25,
             center_end: 75,
+            batch_size: 1,
+            low_memory: false,
+            short_scene_merge: MergeDirection::default(),
+            split_at_sub_cuts: false,
         }
     }
 }
@@ -60,6 +168,7 @@ impl SceneDetector {
         Self::default()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn with_params(
         threshold: f32,
         min_scene_len: usize,
@@ -68,7 +177,8 @@ impl SceneDetector {
         fade_threshold_low: f32,
         min_fade_len: usize,
         merge_gap: usize,
-        // fade_threshold_high: f32,
+        fade_threshold_high: f32,
+        window: InferenceWindow,
     ) -> Self {
         if extra_split > 0 {
             assert!(
@@ -76,6 +186,19 @@ impl SceneDetector {
                 "min_scene_len ({min_scene_len}) cannot be greater than extra_split ({extra_split})"
             );
         }
+        assert!(
+            window.stride <= window.window_size,
+            "stride ({}) cannot be greater than window_size ({})",
+            window.stride,
+            window.window_size
+        );
+        assert!(
+            window.center_end - window.center_start == window.stride,
+            "center range ({}..{}) must span exactly one stride ({})",
+            window.center_start,
+            window.center_end,
+            window.stride
+        );
 
         Self {
             threshold,
@@ -85,11 +208,24 @@ impl SceneDetector {
             fade_threshold_low,
             min_fade_len,
             merge_gap,
-            // fade_threshold_high,
+            fade_threshold_high,
+            window_size: window.window_size,
+            stride: window.stride,
+            center_start: window.center_start,
+            center_end: window.center_end,
             ..Default::default()
         }
     }
 
+    fn window(&self) -> InferenceWindow {
+        InferenceWindow {
+            window_size: self.window_size,
+            stride: self.stride,
+            center_start: self.center_start,
+            center_end: self.center_end,
+        }
+    }
+
     pub fn predictions(
         &mut self,
         mut session: Session,
@@ -99,29 +235,119 @@ impl SceneDetector {
         let inputs = session.inputs();
         let input_name = inputs[0].name().to_owned();
 
+        // Input shape is [batch, frames, H, W, C], -1 for dynamic dimensions
+        let mut batch_size = self.batch_size;
+        // Models exported with a fixed batch only accept exactly that many windows per run
+        let mut fixed_batch = false;
+        if let Some(shape) = inputs[0].dtype().tensor_shape() {
+            if let Some(&frames) = shape.get(1)
+                && frames > 0
+                && frames as usize != self.window_size
+            {
+                return Err(eyre!(
+                    "The ONNX model expects windows of {frames} frames but the window size is {}",
+                    self.window_size
+                ));
+            }
+            let (height, width, channels) = video_config.frame_shape.as_tuple();
+            if let [_, _, model_height, model_width, model_channels] = shape[..]
+                && [
+                    (model_height, height),
+                    (model_width, width),
+                    (model_channels, channels),
+                ]
+                .iter()
+                .any(|&(model, frames)| model > 0 && model as usize != frames)
+            {
+                return Err(eyre!(
+                    "The ONNX model expects {model_width}x{model_height}x{model_channels} frames \
+                     but they are {width}x{height}x{channels}, set the inference size to match"
+                ));
+            }
+            if let Some(&batch) = shape.first()
+                && batch > 0
+            {
+                if batch as usize != self.batch_size {
+                    eprintln!(
+                        "Warning: the ONNX model has a fixed batch size of {batch}, using it instead of {}",
+                        self.batch_size
+                    );
+                }
+                batch_size = batch as usize;
+                fixed_batch = true;
+            }
+        }
+
         let output_names = {
             let outputs = session.outputs(); // This borrows session temporarily
-            (outputs[0].name().to_string(), outputs[1].name().to_string())
+            let names: Vec<String> = outputs.iter().map(|o| o.name().to_string()).collect();
+            let single_frame = find_output(&names, SINGLE_FRAME_OUTPUT, 0)?;
+            let all_frames = find_output(&names, ALL_FRAMES_OUTPUT, 1)?;
+            if single_frame == all_frames {
+                return Err(eyre!(
+                    "Can't tell the {SINGLE_FRAME_OUTPUT} and {ALL_FRAMES_OUTPUT} outputs apart, model outputs: {names:?}"
+                ));
+            }
+            (single_frame, all_frames)
         };
 
         // Now session is no longer borrowed immutably
-        let padded_frames = video_config.process_frames()?;
         let total_frames = video_config.total_frames;
+        let window = self.window();
+        let (pad_start, pad_end) = window.padding(total_frames);
+        let mut padded_frames = if self.low_memory {
+            PaddedFrames::Stream(video_config.stream_frames(pad_start, pad_end)?)
+        } else {
+            PaddedFrames::Full(video_config.process_frames(pad_start, pad_end)?)
+        };
 
         // Initialize both prediction vectors
         let mut hardcut_predictions: Vec<f32> = Vec::with_capacity(total_frames);
         let mut fade_predictions: Vec<f32> = Vec::with_capacity(total_frames);
-        let mut ptr = 0;
-
         let pb = video_config.create_progress_bar("Inferring scenes");
 
-        while ptr + self.window_size <= padded_frames.shape()[0] {
-            // Get a window of shape [1, window_size, H, W, C]
-            let window = padded_frames
-                .slice(s![ptr..ptr + self.window_size, .., .., ..])
-                .insert_axis(Axis(0));
+        // Rows are written and flushed after every batch so a crash keeps them
+        let mut predictions_file = match path_predictions {
+            Some(path) => {
+                let mut file = BufWriter::new(File::create(path)?);
+                writeln!(file, "frame,hardcut,fade")?;
+                Some(file)
+            }
+            None => None,
+        };
+        let mut rows_written = 0;
+
+        let window_starts = window.window_starts(total_frames);
+        if window_starts.is_empty() {
+            return Err(eyre!(
+                "{} padded frames can't fill a window of {} frames",
+                padded_frames.len(),
+                self.window_size
+            ));
+        }
+
+        for batch in window_starts.chunks(batch_size.max(1)) {
+            // A short last batch is filled up with copies of its last window for a fixed
+            // batch model, their predictions are dropped
+            let mut run_windows = batch.to_vec();
+            if fixed_batch {
+                run_windows.resize(batch_size, batch[batch.len() - 1]);
+            }
+
+            // Stack the windows into a tensor of shape [N, window_size, H, W, C]
+            // Frames of every window in the batch, the windows overlap
+            let (first, last) = (batch[0], batch[batch.len() - 1] + self.window_size);
+            let frames = padded_frames.range(first, last)?;
+            let windows: Vec<_> = run_windows
+                .iter()
+                .map(|&ptr| {
+                    let ptr = ptr - first;
+                    frames.slice(s![ptr..ptr + self.window_size, .., .., ..])
+                })
+                .collect();
+            let batch_frames = ndarray::stack(Axis(0), &windows)?;
 
-            let input_tensor = Tensor::from_array(window.to_owned())?;
+            let input_tensor = Tensor::from_array(batch_frames)?;
             let outputs = session.run(vec![(&input_name, input_tensor)])?;
 
             // Process single_frame predictions
@@ -129,25 +355,43 @@ impl SceneDetector {
                 .get(&output_names.0)
                 .ok_or_else(|| eyre::eyre!("Single frame output not found"))?
                 .try_extract_tensor::<f32>()?;
-            let single_array =
-                Array3::from_shape_vec((1, self.window_size, 1), single_logits.1.to_vec())?;
-            let single_center = single_array.slice(s![0, self.center_start..self.center_end, 0]);
-            hardcut_predictions.extend(single_center.iter().copied());
+            let single_array = Array3::from_shape_vec(
+                (run_windows.len(), self.window_size, 1),
+                single_logits.1.to_vec(),
+            )?;
 
             // Process all_frames predictions
             let all_logits = outputs
                 .get(&output_names.1)
                 .ok_or_else(|| eyre::eyre!("All frames output not found"))?
                 .try_extract_tensor::<f32>()?;
-            let all_array =
-                Array3::from_shape_vec((1, self.window_size, 1), all_logits.1.to_vec())?;
-            let all_center = all_array.slice(s![0, self.center_start..self.center_end, 0]);
-            fade_predictions.extend(all_center.iter().copied());
-
-            // Progress update
-            let frames_done = self.stride.min(total_frames - ptr);
-            pb.inc(frames_done as u64);
-            ptr += self.stride;
+            let all_array = Array3::from_shape_vec(
+                (run_windows.len(), self.window_size, 1),
+                all_logits.1.to_vec(),
+            )?;
+
+            // Keep only the center of every window, in window order
+            for (i, &ptr) in batch.iter().enumerate() {
+                let single_center =
+                    single_array.slice(s![i, self.center_start..self.center_end, 0]);
+                hardcut_predictions.extend(single_center.iter().copied());
+
+                let all_center = all_array.slice(s![i, self.center_start..self.center_end, 0]);
+                fade_predictions.extend(all_center.iter().copied());
+
+                // Progress update
+                let frames_done = self.stride.min(total_frames.saturating_sub(ptr));
+                pb.inc(frames_done as u64);
+            }
+
+            if let Some(file) = predictions_file.as_mut() {
+                let end = hardcut_predictions.len().min(total_frames);
+                for i in rows_written..end {
+                    write_prediction_row(file, i, hardcut_predictions[i], fade_predictions[i])?;
+                }
+                rows_written = end;
+                file.flush()?;
+            }
         }
 
         pb.finish_with_message("Inference complete");
@@ -159,15 +403,11 @@ impl SceneDetector {
         self.fade_predictions =
             fade_predictions[..total_frames.min(fade_predictions.len())].to_vec();
 
-        if let Some(path) = path_predictions {
-            self.save_predictions_to_file(path)?;
-        }
-
         Ok(())
     }
 
     pub fn save_predictions_to_file(&self, filename: &Path) -> Result<()> {
-        let mut file = File::create(filename)?;
+        let mut file = Vec::new();
 
         // Ensure both predictions have the same length
         let len = std::cmp::min(self.hardcut_predictions.len(), self.fade_predictions.len());
@@ -176,13 +416,33 @@ impl SceneDetector {
         writeln!(file, "frame,hardcut,fade")?;
 
         for i in 0..len {
-            writeln!(
-                file,
-                "{}, {:.6},{:.6}",
-                i, self.hardcut_predictions[i], self.fade_predictions[i]
+            write_prediction_row(
+                &mut file,
+                i,
+                self.hardcut_predictions[i],
+                self.fade_predictions[i],
             )?;
         }
 
+        write_atomic(filename, file)?;
+        Ok(())
+    }
+
+    /// Writes the detected fade ranges as `{"fades": [{"start_frame", "end_frame"}]}`,
+    /// with the end frame exclusive like in the scene files
+    pub fn write_fades_file(&self, path: &Path) -> Result<()> {
+        let fades = FadesFile {
+            fades: self
+                .detect_fade_segments()
+                .into_iter()
+                .map(|(start, end)| FadeSegment {
+                    start_frame: start as u32,
+                    end_frame: end as u32 + 1,
+                })
+                .collect(),
+        };
+        let json = serde_json::to_string_pretty(&fades)?;
+        write_atomic(path, json)?;
         Ok(())
     }
 
@@ -215,10 +475,13 @@ impl SceneDetector {
         scene_cut_frames
     }
 
-    /// Simple threshold-based fade detection (no trend analysis)
+    /// Threshold-based fade detection with hysteresis (no trend analysis).
+    /// A run of frames above `fade_threshold_low` is a fade only if one of them reaches
+    /// `fade_threshold_high`
     pub fn detect_fade_segments(&self) -> Vec<(usize, usize)> {
         let mut fade_segments = Vec::new();
         let mut inside_fade = false;
+        let mut reached_high = false;
         let mut start_idx = 0;
 
         for (idx, &confidence) in self.fade_predictions.iter().enumerate() {
@@ -229,6 +492,11 @@ impl SceneDetector {
                 (true, false) => {
                     start_idx = idx;
                     inside_fade = true;
+                    reached_high = confidence >= self.fade_threshold_high;
+                }
+                // Staying in fade region
+                (true, true) => {
+                    reached_high |= confidence >= self.fade_threshold_high;
                 }
                 // Exiting fade region
                 (false, true) => {
@@ -236,19 +504,19 @@ impl SceneDetector {
                     inside_fade = false;
 
                     // Only keep segments that meet minimum length
-                    if end_idx - start_idx + 1 >= self.min_fade_len {
+                    if reached_high && end_idx - start_idx + 1 >= self.min_fade_len {
                         fade_segments.push((start_idx, end_idx));
                     }
                 }
-                // Already in/out of fade - no action needed
-                _ => continue,
+                // Already out of fade - no action needed
+                (false, false) => continue,
             }
         }
 
         // Handle fade at end of video
         if inside_fade {
             let end_idx = self.fade_predictions.len() - 1;
-            if end_idx - start_idx + 1 >= self.min_fade_len {
+            if reached_high && end_idx - start_idx + 1 >= self.min_fade_len {
                 fade_segments.push((start_idx, end_idx));
             }
         }
@@ -422,37 +690,38 @@ impl SceneDetector {
         )
     }
 
+    /// Merges scenes shorter than `min_scene_len` into a neighbour chosen by
+    /// `short_scene_merge`. The first and last scenes can only merge into their only neighbour
     pub fn combine_short_scenes(&self, scenes: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
-        if scenes.is_empty() {
-            return scenes;
-        }
-
-        let mut combined = Vec::with_capacity(scenes.len());
-        let mut current_start = scenes[0].0;
-        let mut current_end = scenes[0].1;
-
-        for &(start, end) in &scenes[1..] {
-            let current_length = current_end - current_start;
-
-            if current_length < self.min_scene_len {
-                // Always merge short segments forward
-                current_end = end;
-            } else {
-                // Finalize current segment
-                combined.push((current_start, current_end));
-                current_start = start;
-                current_end = end;
+        let mut combined: Vec<(usize, usize)> = Vec::with_capacity(scenes.len());
+        // Start of short scenes being merged forward
+        let mut carried_start = None;
+
+        for (i, &(start, end)) in scenes.iter().enumerate() {
+            let start = carried_start.take().unwrap_or(start);
+            if end - start >= self.min_scene_len {
+                combined.push((start, end));
+                continue;
             }
-        }
 
-        // Handle last accumulated segment
-        let last_length = current_end - current_start;
-        if last_length < self.min_scene_len && !combined.is_empty() {
-            // Merge trailing shorts with previous segment
-            let (prev_start, _) = combined.pop().unwrap();
-            combined.push((prev_start, current_end));
-        } else {
-            combined.push((current_start, current_end));
+            let previous_len = combined.last().map(|&(start, end)| end - start);
+            let next_len = scenes.get(i + 1).map(|&(start, end)| end - start);
+            let backward = match (self.short_scene_merge, previous_len, next_len) {
+                (_, None, None) => {
+                    combined.push((start, end));
+                    continue;
+                }
+                (_, Some(_), None) => true,
+                (_, None, Some(_)) => false,
+                (MergeDirection::Forward, _, _) => false,
+                (MergeDirection::Backward, _, _) => true,
+                (MergeDirection::Nearest, Some(previous), Some(next)) => previous <= next,
+            };
+
+            match combined.last_mut() {
+                Some(previous) if backward => previous.1 = end,
+                _ => carried_start = Some(start),
+            }
         }
 
         combined
@@ -472,8 +741,13 @@ impl SceneDetector {
                 // Scene is small enough, keep as-is
                 result.push((start, end));
             } else {
-                // Split into two equal parts (rounding down)
-                let mid = start + length / 2;
+                // Split into two equal parts (rounding down), or at the strongest sub-cut
+                let mid = if self.split_at_sub_cuts {
+                    self.strongest_sub_cut(start, end)
+                        .unwrap_or(start + length / 2)
+                } else {
+                    start + length / 2
+                };
 
                 // Recursively split both halves
                 let mut left = self.split_large_scenes(vec![(start, mid)]);
@@ -488,6 +762,42 @@ impl SceneDetector {
         result
     }
 
+    /// Cut with the highest hardcut or fade prediction in the middle half of `start..end`,
+    /// keeping both parts at least `min_scene_len` long. `None` if no frame there goes over
+    /// `fade_threshold_low`
+    fn strongest_sub_cut(&self, start: usize, end: usize) -> Option<usize> {
+        let margin = ((end - start) / 4).max(self.min_scene_len);
+        let (first, last) = (start + margin, end.checked_sub(margin)?);
+
+        // A cut at `frame` follows a transition predicted on `frame - 1`
+        (first..=last)
+            .filter(|&frame| frame > start && frame < end)
+            .map(|frame| {
+                let prediction = self.hardcut_predictions[frame - 1].max(
+                    self.fade_predictions
+                        .get(frame - 1)
+                        .copied()
+                        .unwrap_or_default(),
+                );
+                (frame, prediction)
+            })
+            .filter(|&(_, prediction)| prediction > self.fade_threshold_low)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(frame, _)| frame)
+    }
+
+    /// Hardcut prediction of the cut before `start_frame`, the first frame is always a cut
+    fn cut_confidence(&self, start_frame: usize) -> f32 {
+        match start_frame.checked_sub(1) {
+            Some(frame) => self
+                .hardcut_predictions
+                .get(frame)
+                .copied()
+                .unwrap_or_default(),
+            None => 1.0,
+        }
+    }
+
     pub fn predictions_to_scene_list(&self, fade_scenes: bool) -> SceneList {
         let (hardcut_scenes, final_scenes) = self.predictions_with_fades_to_scenes();
         let scenes = if fade_scenes {
@@ -509,6 +819,9 @@ impl SceneDetector {
                 zone_overrides: None,
                 frame_scores: Vec::new(),
                 zoned: false,
+                target_quality: None,
+                target_percentile: None,
+                cut_confidence: self.cut_confidence(start),
             })
             .collect();
 
@@ -523,6 +836,9 @@ impl SceneDetector {
                 zone_overrides: None,
                 frame_scores: Vec::new(),
                 zoned: false,
+                target_quality: None,
+                target_percentile: None,
+                cut_confidence: self.cut_confidence(start),
             })
             .collect();
 
@@ -549,6 +865,9 @@ impl SceneDetector {
                 zone_overrides: None,
                 frame_scores: Vec::new(),
                 zoned: false,
+                target_quality: None,
+                target_percentile: None,
+                cut_confidence: self.cut_confidence(start),
             })
             .collect();
 
@@ -559,3 +878,131 @@ impl SceneDetector {
         }
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct FadeSegment {
+    pub start_frame: u32,
+    pub end_frame: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct FadesFile {
+    fades: Vec<FadeSegment>,
+}
+
+/// Writes one `frame,hardcut,fade` row of the predictions CSV
+fn write_prediction_row(
+    file: &mut impl Write,
+    frame: usize,
+    hardcut: f32,
+    fade: f32,
+) -> Result<()> {
+    writeln!(file, "{}, {:.6},{:.6}", frame, hardcut, fade)?;
+    Ok(())
+}
+
+/// Output with the hard cut predictions
+const SINGLE_FRAME_OUTPUT: &str = "single_frame_pred";
+/// Output with the predictions of every transition, used for fades
+const ALL_FRAMES_OUTPUT: &str = "all_frames_pred";
+
+/// Finds an output by name, then by a name containing it without the `_pred` suffix,
+/// then by its position in the original TransNetV2 export
+fn find_output(names: &[String], name: &str, fallback_index: usize) -> Result<String> {
+    let stem = name.trim_end_matches("_pred");
+    names
+        .iter()
+        .find(|output| *output == name)
+        .or_else(|| names.iter().find(|output| output.contains(stem)))
+        .or_else(|| names.get(fallback_index))
+        .cloned()
+        .ok_or_else(|| eyre!("ONNX model has no {name} output, model outputs: {names:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames covered by the centers of the windows, in order
+    fn center_frames(window: &InferenceWindow, total_frames: usize) -> Vec<usize> {
+        let (pad_start, _) = window.padding(total_frames);
+        window
+            .window_starts(total_frames)
+            .iter()
+            .flat_map(|&ptr| ptr + window.center_start..ptr + window.center_end)
+            .map(|padded| padded - pad_start)
+            .collect()
+    }
+
+    #[test]
+    fn window_layout() {
+        let windows = [
+            InferenceWindow::default(),
+            InferenceWindow::new(100, 30, "35:65").unwrap(),
+            InferenceWindow::new(64, 64, "0:64").unwrap(),
+        ];
+        for window in windows {
+            for total_frames in [1, 10, 100] {
+                let (pad_start, pad_end) = window.padding(total_frames);
+                let padded_len = pad_start + total_frames + pad_end;
+                assert!(
+                    padded_len >= window.window_size,
+                    "{window:?} {total_frames}"
+                );
+                assert_eq!(
+                    padded_len % window.stride,
+                    window.window_size % window.stride
+                );
+
+                // Every frame is predicted once, in order, the rest is truncated
+                let frames = center_frames(&window, total_frames);
+                assert_eq!(
+                    frames[..total_frames],
+                    (0..total_frames).collect::<Vec<_>>()
+                );
+                assert!(frames.len() - total_frames <= window.stride);
+            }
+        }
+
+        let window = InferenceWindow::default();
+        assert_eq!(window.padding(1), (25, 74));
+        assert_eq!(window.window_starts(1), [0]);
+        assert_eq!(window.padding(10), (25, 65));
+        assert_eq!(window.window_starts(10), [0]);
+        assert_eq!(window.padding(100), (25, 75));
+        assert_eq!(window.window_starts(100), [0, 50, 100]);
+    }
+
+    #[test]
+    fn ten_frame_clip_is_one_scene() {
+        let scene_ranges = |scene_list: SceneList| -> Vec<(u32, u32)> {
+            scene_list
+                .split_scenes
+                .iter()
+                .map(|scene| (scene.start_frame, scene.end_frame))
+                .collect()
+        };
+
+        // No cut, and a cut too close to the start to make a scene of its own
+        let cut_at_5 = [0.0, 0.0, 0.0, 0.0, 0.0, 0.9, 0.0, 0.0, 0.0, 0.0];
+        for hardcuts in [[0.0; 10], cut_at_5] {
+            let detector = SceneDetector {
+                hardcut_predictions: hardcuts.to_vec(),
+                fade_predictions: vec![0.0; 10],
+                ..Default::default()
+            };
+            for fade_scenes in [false, true] {
+                assert_eq!(
+                    scene_ranges(detector.predictions_to_scene_list(fade_scenes)),
+                    [(0, 10)],
+                    "{hardcuts:?} fade_scenes: {fade_scenes}"
+                );
+            }
+        }
+
+        assert_eq!(
+            SceneDetector::default().combine_short_scenes(vec![(0, 5), (5, 10)]),
+            [(0, 10)]
+        );
+    }
+}
@@ -1,53 +1,143 @@
 // #![allow(unused_imports)]
+use clap::ValueEnum;
 use eyre::{Result, eyre};
 use ort::{
     self,
-    execution_providers::ExecutionProviderDispatch,
+    execution_providers::{
+        CUDAExecutionProvider, CoreMLExecutionProvider, DirectMLExecutionProvider,
+        ExecutionProviderDispatch, ROCmExecutionProvider, TensorRTExecutionProvider,
+    },
     session::{Session, builder::GraphOptimizationLevel},
 };
 
-#[cfg(target_os = "macos")]
-use ort::execution_providers::CoreMLExecutionProvider;
-
-#[cfg(windows)]
-use ort::execution_providers::{
-    CUDAExecutionProvider, DirectMLExecutionProvider, TensorRTExecutionProvider,
-};
+use std::{path::Path, process::Command};
+
+/// Rough GPU memory needed to infer one 100-frame window
+const VRAM_PER_WINDOW_MIB: u64 = 256;
+/// Upper bound for the automatic batch size
+const MAX_AUTO_BATCH_SIZE: usize = 32;
+
+/// ONNX Runtime execution provider used for inference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExecutionProvider {
+    /// Every GPU provider available on this platform, silently falling back to CPU
+    #[default]
+    Auto,
+    Cuda,
+    Tensorrt,
+    Directml,
+    Coreml,
+    Rocm,
+    Cpu,
+}
 
-#[cfg(all(unix, not(target_os = "macos")))]
-use ort::execution_providers::{CUDAExecutionProvider, ROCmExecutionProvider};
+impl ExecutionProvider {
+    /// Providers to register. An explicitly requested one fails the session build if it can't
+    /// be registered, so `TransNetSession::new` can warn and fall back to CPU
+    fn providers(self) -> Vec<ExecutionProviderDispatch> {
+        let provider = match self {
+            Self::Auto => return TransNetSession::preferred_execution_providers(),
+            Self::Cpu => return vec![],
+            Self::Cuda => CUDAExecutionProvider::default().build(),
+            Self::Tensorrt => TensorRTExecutionProvider::default().build(),
+            Self::Directml => DirectMLExecutionProvider::default().build(),
+            Self::Coreml => CoreMLExecutionProvider::default().build(),
+            Self::Rocm => ROCmExecutionProvider::default().build(),
+        };
+        vec![provider.error_on_failure()]
+    }
+}
 
-use std::path::Path;
+/// Name ONNX Runtime gives to its default provider
+const CPU_EXECUTION_PROVIDER: &str = "CPUExecutionProvider";
 
 #[derive(Debug)]
 pub struct TransNetSession {
     pub session: Session,
+    /// Execution provider the session actually runs on, e.g. "CUDAExecutionProvider"
+    pub execution_provider: String,
 }
 
 impl TransNetSession {
-    pub fn new(model_path: Option<impl AsRef<Path>>, use_cpu: bool) -> Result<Self> {
-        let providers = if use_cpu {
-            vec![]
-        } else {
-            Self::preferred_execution_providers()
-        };
-        let session = match model_path {
-            Some(path) => Self::init_session_from_file(path.as_ref(), &providers)?,
-            None => Self::init_session_from_embedded(&providers)?,
+    /// `intra_threads` sets the ONNX Runtime intra-op threads (default: the rayon pool size).
+    /// This mostly affects CPU inference, on GPU only the ops that fall back to CPU use them.
+    /// An explicitly requested execution provider that fails to register falls back to CPU.
+    pub fn new(
+        model_path: Option<impl AsRef<Path>>,
+        execution_provider: ExecutionProvider,
+        intra_threads: Option<usize>,
+    ) -> Result<Self> {
+        let model_path = model_path.as_ref().map(|path| path.as_ref());
+        let intra_threads = intra_threads.unwrap_or_else(rayon::current_num_threads);
+        let providers = execution_provider.providers();
+
+        let (session, active_provider) = match Self::init_session(
+            model_path,
+            &providers,
+            intra_threads,
+        ) {
+            Ok(session) => (session, Self::active_execution_provider(&providers)),
+            Err(e) if !providers.is_empty() && execution_provider != ExecutionProvider::Auto => {
+                eprintln!(
+                    "Warning: could not use the {execution_provider:?} execution provider ({e}), falling back to CPU"
+                );
+                (
+                    Self::init_session(model_path, &[], intra_threads)?,
+                    CPU_EXECUTION_PROVIDER.to_owned(),
+                )
+            }
+            Err(e) => return Err(e),
         };
 
-        Ok(Self { session })
+        Ok(Self {
+            session,
+            execution_provider: active_provider,
+        })
+    }
+
+    /// First of `providers` that registers, the one ONNX Runtime runs the session on since the
+    /// others fail silently. Each one is tried alone on a throwaway builder, CPU if none registers
+    fn active_execution_provider(providers: &[ExecutionProviderDispatch]) -> String {
+        providers
+            .iter()
+            .find(|provider| {
+                Session::builder().is_ok_and(|builder| {
+                    builder
+                        .with_execution_providers([(*provider).clone().error_on_failure()])
+                        .is_ok()
+                })
+            })
+            // The Debug output of a dispatch starts with the provider name
+            .and_then(|provider| {
+                format!("{provider:?}")
+                    .split([' ', '{'])
+                    .next()
+                    .map(str::to_owned)
+            })
+            .unwrap_or_else(|| CPU_EXECUTION_PROVIDER.to_owned())
+    }
+
+    fn init_session(
+        model_path: Option<&Path>,
+        execution_providers: &[ExecutionProviderDispatch],
+        intra_threads: usize,
+    ) -> Result<Session> {
+        match model_path {
+            Some(path) => Self::init_session_from_file(path, execution_providers, intra_threads),
+            None => Self::init_session_from_embedded(execution_providers, intra_threads),
+        }
     }
 
     pub fn init_session_from_file(
         model_path: &Path,
         execution_providers: &[ExecutionProviderDispatch],
+        intra_threads: usize,
     ) -> Result<Session> {
         let mut builder = Session::builder()
             .map_err(|e| eyre!("{}", e))?
             .with_optimization_level(GraphOptimizationLevel::Level3)
             .map_err(|e| eyre!("{}", e))?
-            .with_intra_threads(rayon::current_num_threads())
+            .with_intra_threads(intra_threads)
             .map_err(|e| eyre!("{}", e))?
             .with_execution_providers(execution_providers)
             .map_err(|e| eyre!("{}", e))?;
@@ -59,6 +149,7 @@ impl TransNetSession {
 
     pub fn init_session_from_embedded(
         execution_providers: &[ExecutionProviderDispatch],
+        intra_threads: usize,
     ) -> Result<Session> {
         // Embedded model bytes (compile-time included)
         const MODEL_BYTES: &[u8] = include_bytes!("../../../transnetv2-rs/models/transnetv2.onnx");
@@ -73,7 +164,7 @@ impl TransNetSession {
             .map_err(|e| eyre!("{}", e))?
             .with_optimization_level(GraphOptimizationLevel::Level3)
             .map_err(|e| eyre!("{}", e))?
-            .with_intra_threads(rayon::current_num_threads())
+            .with_intra_threads(intra_threads)
             .map_err(|e| eyre!("{}", e))?
             .with_execution_providers(execution_providers)
             .map_err(|e| eyre!("{}", e))?
@@ -86,6 +177,37 @@ impl TransNetSession {
         Ok(session)
     }
 
+    /// Picks how many windows to infer at once from the free VRAM reported by `nvidia-smi`,
+    /// using half of it. Falls back to 1 when the session runs on CPU or the query fails.
+    pub fn auto_batch_size(&self) -> usize {
+        if self.execution_provider == CPU_EXECUTION_PROVIDER {
+            return 1;
+        }
+
+        let Ok(output) = Command::new("nvidia-smi")
+            .args(["--query-gpu=memory.free", "--format=csv,noheader,nounits"])
+            .output()
+        else {
+            return 1;
+        };
+        if !output.status.success() {
+            return 1;
+        }
+
+        // One line per GPU, the session runs on the first one
+        let free_mib = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .and_then(|line| line.trim().parse::<u64>().ok());
+
+        match free_mib {
+            Some(free_mib) => {
+                ((free_mib / 2 / VRAM_PER_WINDOW_MIB) as usize).clamp(1, MAX_AUTO_BATCH_SIZE)
+            }
+            None => 1,
+        }
+    }
+
     pub fn preferred_execution_providers() -> Vec<ExecutionProviderDispatch> {
         let mut providers = Vec::new();
         // println!("USING PROVIDERS");
@@ -1,38 +1,74 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::{
     scenes::SceneList,
-    transnetv2::{extract_frames::VideoConfig, inference::SceneDetector, onnx::TransNetSession},
-    vapoursynth::{SourcePlugin, add_extension, prepare_clip, resize_format},
+    transnetv2::{
+        extract_frames::VideoConfig,
+        inference::{InferenceWindow, MergeDirection, SceneDetector},
+        onnx::{ExecutionProvider, TransNetSession},
+    },
+    vapoursynth::{
+        DownscaleKernel, Ivtc, SourcePlugin, add_extension, clip_fps, prepare_clip, resize_format,
+        seconds_to_frames_at,
+    },
 };
 use eyre::{OptionExt, Result};
 use vapoursynth4_rs::{core::Core, node::VideoNode};
 
+/// Frame size (width, height) the bundled TransNetV2 model was trained on
+pub const TRANSNET_INPUT_SIZE: (u32, u32) = (48, 27);
+
+/// Parses an inference size like `48x27` (width x height)
+pub fn parse_inference_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("Invalid inference size '{s}', expected WIDTHxHEIGHT"))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&value| value > 0)
+            .ok_or_else(|| format!("Invalid inference size '{s}', expected WIDTHxHEIGHT"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn run_transnetv2(
     core: &Core,
     video_path: &Path,
     model_path: Option<&Path>,
-    use_cpu: bool,
+    execution_provider: ExecutionProvider,
+    batch_size: Option<usize>,
+    ort_threads: Option<usize>,
+    low_memory: bool,
+    window: InferenceWindow,
+    inference_size: (u32, u32),
     importer_plugin: SourcePlugin,
     temp_folder: &Path,
     verbose: bool,
     color_metadata: &str,
     crop: Option<&str>,
     trim: Option<&str>,
-    detelecine: bool,
+    detelecine: Option<Ivtc>,
     extra_split_seconds: i64,
     extra_split_frames: Option<i64>,
     extra_split_seconds_fades: i64,
     extra_split_frames_fades: Option<i64>,
     min_scene_len_sec: i64,
     min_scene_len: Option<i64>,
+    short_scene_merge: MergeDirection,
+    split_at_sub_cuts: bool,
+    fps: Option<f64>,
     threshold: f32,
     fade_threshold_low: f32,
+    fade_threshold_high: Option<f32>,
     min_fade_len: i64,
     merge_gap: i64,
     enable_fade_detection: bool,
     save_predictions: bool,
+    save_fades: bool,
 ) -> Result<(SceneList, SceneList)> {
     let src = prepare_clip(
         core,
@@ -44,37 +80,39 @@ pub fn run_transnetv2(
         crop,
         trim,
         1.0,
+        DownscaleKernel::default(),
         detelecine,
     )?;
 
-    let src: VideoNode = resize_format(core, &src, 48, 27, "RGB24")?;
+    // Resized before the frames are copied, the model only sees tiny frames
+    let (width, height) = inference_size;
+    let src: VideoNode = resize_format(core, &src, width.into(), height.into(), "RGB24")?;
     let info = src.info();
     let total_frames = info.num_frames as usize;
-    let extra_split = match extra_split_frames {
-        Some(frames) => frames,
-        None => {
-            ((extra_split_seconds as f64 * info.fps_num as f64) / info.fps_den as f64).ceil() as i64
-        }
-    };
-    let extra_split_fades = match extra_split_frames_fades {
-        Some(frames) => frames,
-        None => ((extra_split_seconds_fades as f64 * info.fps_num as f64) / info.fps_den as f64)
-            .ceil() as i64,
-    };
-    let min_scene_len = match min_scene_len {
-        Some(frames) => frames,
-        None => {
-            ((min_scene_len_sec as f64 * info.fps_num as f64) / info.fps_den as f64).ceil() as i64
-        }
+    // Only needed for the lengths given in seconds, so VFR sources with every length in
+    // frames don't need --fps
+    let fps = if extra_split_frames.is_some()
+        && extra_split_frames_fades.is_some()
+        && min_scene_len.is_some()
+    {
+        0.0
+    } else {
+        clip_fps(&src, fps)?
     };
+    let to_frames = |seconds: i64| seconds_to_frames_at(seconds as f64, fps) as i64;
+    let extra_split = extra_split_frames.unwrap_or_else(|| to_frames(extra_split_seconds));
+    let extra_split_fades =
+        extra_split_frames_fades.unwrap_or_else(|| to_frames(extra_split_seconds_fades));
+    let min_scene_len = min_scene_len.unwrap_or_else(|| to_frames(min_scene_len_sec));
     let video_config = VideoConfig {
         src,
         total_frames,
-        frame_shape: (27, 48, 3).into(),
+        frame_shape: (height.into(), width.into(), 3).into(),
         batch: 100,
     };
 
-    let transnet_session = TransNetSession::new(model_path, use_cpu)?;
+    let transnet_session = TransNetSession::new(model_path, execution_provider, ort_threads)?;
+    println!("Inference device: {}", transnet_session.execution_provider);
     let mut scene_detection = SceneDetector::with_params(
         threshold,
         min_scene_len.try_into().unwrap(),
@@ -83,19 +121,18 @@ pub fn run_transnetv2(
         fade_threshold_low,
         min_fade_len as usize,
         merge_gap as usize,
+        fade_threshold_high
+            .unwrap_or(fade_threshold_low)
+            .max(fade_threshold_low),
+        window,
     );
+    scene_detection.short_scene_merge = short_scene_merge;
+    scene_detection.low_memory = low_memory;
+    scene_detection.split_at_sub_cuts = split_at_sub_cuts;
+    scene_detection.batch_size = batch_size.unwrap_or_else(|| transnet_session.auto_batch_size());
 
     let path_predictions = if save_predictions {
-        let output_name = format!(
-            "[PREDICTIONS]_{}",
-            video_path
-                .file_stem()
-                .ok_or_eyre("No file name")?
-                .to_str()
-                .ok_or_eyre("Invalid UTF-8 in input path")?
-        );
-        let path = video_path.with_file_name(output_name);
-        Some(add_extension("csv", path))
+        Some(sidecar_path(video_path, "PREDICTIONS", "csv")?)
     } else {
         None
     };
@@ -108,7 +145,27 @@ pub fn run_transnetv2(
     let scene_list = scene_detection.predictions_to_scene_list(enable_fade_detection);
     let hardcut_scene_list = scene_detection.hardcuts_to_scene_list();
 
+    if save_fades {
+        let path_fades = sidecar_path(video_path, "FADES", "json")?;
+        scene_detection.write_fades_file(&path_fades)?;
+        println!("Fades written to {}", path_fades.display());
+    }
+
     // println!("{scenes:#?}");
 
     Ok((scene_list, hardcut_scene_list))
 }
+
+/// `[<PREFIX>]_<input stem>.<extension>` next to the input
+fn sidecar_path(video_path: &Path, prefix: &str, extension: &str) -> Result<PathBuf> {
+    let output_name = format!(
+        "[{prefix}]_{}",
+        video_path
+            .file_stem()
+            .ok_or_eyre("No file name")?
+            .to_str()
+            .ok_or_eyre("Invalid UTF-8 in input path")?
+    );
+    let path = video_path.with_file_name(output_name);
+    Ok(add_extension(extension, path))
+}
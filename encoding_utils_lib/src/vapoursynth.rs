@@ -1,6 +1,9 @@
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf, absolute};
-use std::process::{Command, Stdio};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{ffi::CString, str::FromStr};
 
 use clap::ValueEnum;
@@ -9,11 +12,13 @@ use vapoursynth4_rs::api::Api;
 use vapoursynth4_rs::ffi::VSMapAppendMode::{Append, Replace};
 use vapoursynth4_rs::{
     core::Core,
+    frame::Frame,
     map::{KeyStr, Map, Value},
-    node::VideoNode,
+    node::{Node, VideoNode},
     plugin::Plugin,
 };
 
+use crate::binaries::ffmsindex_command;
 use crate::vpy_files::ColorMetadata;
 
 pub trait ToCString {
@@ -40,6 +45,8 @@ pub enum SourcePlugin {
     Lsmash,
     Bestsource,
     Ffms2,
+    /// Image sequence: a folder, a `*` wildcard (frames/*.png) or a printf pattern (frame%05d.png)
+    Imwri,
 }
 
 impl SourcePlugin {
@@ -48,6 +55,7 @@ impl SourcePlugin {
             SourcePlugin::Lsmash => "lsmash",
             SourcePlugin::Bestsource => "bestsource",
             SourcePlugin::Ffms2 => "ffms2",
+            SourcePlugin::Imwri => "imwri",
         }
     }
 }
@@ -67,6 +75,11 @@ pub fn bestsource(core: &Core) -> Result<Plugin> {
         .ok_or_eyre("Plugin [com.vapoursynth.bestsource] was not found")
 }
 
+pub fn imwri(core: &Core) -> Result<Plugin> {
+    core.get_plugin_by_id(&"com.vapoursynth.imwri".to_cstring())
+        .ok_or_eyre("Plugin [com.vapoursynth.imwri] was not found")
+}
+
 pub fn vszip(core: &Core) -> Result<Plugin> {
     core.get_plugin_by_id(&"com.julek.vszip".to_cstring())
         .ok_or_eyre("Plugin [com.julek.vszip] was not found")
@@ -92,6 +105,64 @@ pub fn vivtc(core: &Core) -> Result<Plugin> {
         .ok_or_eyre("Plugin [vivtc] was not found")
 }
 
+/// When enabled, index files are named after the content of the input instead of only
+/// its file name, see `index_cache_name`
+static CONTENT_HASH_INDEX: AtomicBool = AtomicBool::new(false);
+
+/// Names the source indexes by content hash (for every later source call)
+pub fn set_content_hash_index(enabled: bool) {
+    CONTENT_HASH_INDEX.store(enabled, Ordering::Relaxed);
+}
+
+/// Bytes read from each end of the file for the content hash
+const CONTENT_HASH_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// File name of the index of `path` (before the plugin extension).
+/// With `set_content_hash_index(true)` the name also includes a hash of the file
+/// size and its first and last 4 MiB, so an overwritten file with the same name
+/// doesn't reuse a stale index.
+pub fn index_cache_name(path: &Path) -> Result<String> {
+    let file_name = path
+        .file_name()
+        .ok_or_eyre("Input path has no filename")?
+        .to_str()
+        .ok_or_eyre("Filename not UTF-8")?;
+
+    if !CONTENT_HASH_INDEX.load(Ordering::Relaxed) || !path.is_file() {
+        return Ok(file_name.to_owned());
+    }
+
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    // FNV-1a, stable across runs and Rust versions
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+
+    feed(&size.to_le_bytes());
+    let mut buffer = Vec::with_capacity(CONTENT_HASH_CHUNK as usize);
+    (&mut file)
+        .take(CONTENT_HASH_CHUNK)
+        .read_to_end(&mut buffer)?;
+    feed(&buffer);
+    if size > CONTENT_HASH_CHUNK {
+        buffer.clear();
+        file.seek(SeekFrom::Start(
+            size.saturating_sub(CONTENT_HASH_CHUNK)
+                .max(CONTENT_HASH_CHUNK),
+        ))?;
+        file.take(CONTENT_HASH_CHUNK).read_to_end(&mut buffer)?;
+        feed(&buffer);
+    }
+
+    Ok(format!("{file_name}.{hash:016x}"))
+}
+
 pub fn lsmash_invoke(core: &Core, path: &Path, temp_dir: &Path) -> Result<VideoNode> {
     let lsmash = lsmash(core)?;
     let mut args = Map::default();
@@ -106,12 +177,7 @@ pub fn lsmash_invoke(core: &Core, path: &Path, temp_dir: &Path) -> Result<VideoN
         Replace,
     )?;
 
-    let cache_path = temp_dir.join(
-        path.file_name()
-            .ok_or_eyre("Input path has no filename")?
-            .to_str()
-            .ok_or_eyre("Filename not UTF-8")?,
-    );
+    let cache_path = temp_dir.join(index_cache_name(&path)?);
     let cache_path = add_extension("lwi", cache_path);
 
     args.set(
@@ -139,17 +205,12 @@ pub fn ffms2_invoke(core: &Core, path: &Path, temp_dir: &Path) -> Result<VideoNo
     let temp_dir = absolute(temp_dir)?;
 
     // Build index path: same filename but .ffindex
-    let cache_path = temp_dir.join(
-        path.file_name()
-            .ok_or_eyre("Input path has no filename")?
-            .to_str()
-            .ok_or_eyre("Filename not UTF-8")?,
-    );
+    let cache_path = temp_dir.join(index_cache_name(&path)?);
     let cache_path = add_extension("ffindex", cache_path);
 
     // If index doesn’t exist, run ffmsindex
     if !cache_path.exists() {
-        let status = Command::new("ffmsindex")
+        let status = ffmsindex_command()
             .arg("-f")
             .arg("-p")
             .arg(&path)
@@ -201,12 +262,7 @@ pub fn bestsource_invoke(core: &Core, path: &Path, temp_dir: &Path) -> Result<Vi
         Replace,
     )?;
 
-    let cache_path = temp_dir.join(
-        path.file_name()
-            .ok_or_eyre("Input path has no filename")?
-            .to_str()
-            .ok_or_eyre("Filename not UTF-8")?,
-    );
+    let cache_path = temp_dir.join(index_cache_name(path)?);
     // let cache_path = add_extension("bsindex", cache_path);
 
     args.set(
@@ -232,6 +288,116 @@ pub fn bestsource_invoke(core: &Core, path: &Path, temp_dir: &Path) -> Result<Vi
 
     Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
 }
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "png", "jpg", "jpeg", "tif", "tiff", "bmp", "webp", "exr", "dpx",
+];
+
+/// Expands an image sequence path into the sorted list of frames.
+/// Folders return every image inside, a `*` in the file name is matched against the
+/// folder contents, anything else (single file, printf pattern) is returned as is.
+pub fn image_sequence_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let path = absolute(path)?;
+    let file_name = path
+        .file_name()
+        .ok_or_eyre("Input path has no filename")?
+        .to_str()
+        .ok_or_eyre("Filename not UTF-8")?
+        .to_owned();
+
+    let (folder, pattern) = if path.is_dir() {
+        (path.clone(), None)
+    } else if file_name.contains('*') {
+        (
+            path.parent()
+                .ok_or_eyre("Input path has no parent folder")?
+                .to_path_buf(),
+            Some(file_name),
+        )
+    } else {
+        return Ok(vec![path]);
+    };
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&folder)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file())
+        .filter(|file| {
+            let name = file.file_name().and_then(OsStr::to_str).unwrap_or_default();
+            match &pattern {
+                Some(pattern) => wildcard_match(pattern, name),
+                None => file
+                    .extension()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str())),
+            }
+        })
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        return Err(eyre!("No images found for {}", path.display()));
+    }
+
+    Ok(files)
+}
+
+/// Matches a file name against a pattern where `*` stands for any run of characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Python call loading an image sequence, for the generated .vpy scripts
+pub fn imwri_vpy_source(path: &Path) -> Result<String> {
+    let files = image_sequence_files(path)?
+        .iter()
+        .map(|file| {
+            file.to_str()
+                .map(|file| format!("{file:?}"))
+                .ok_or_eyre("Filename not UTF-8")
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!("core.imwri.Read([{}])", files.join(", ")))
+}
+
+/// Loads an image sequence. Unlike the video sources there is no index, so the
+/// temp folder isn't used.
+pub fn imwri_invoke(core: &Core, path: &Path) -> Result<VideoNode> {
+    let imwri = imwri(core)?;
+    let mut args = Map::default();
+
+    for file in image_sequence_files(path)? {
+        args.set(
+            KeyStr::from_cstr(&"filename".to_cstring()),
+            Value::Utf8(file.to_str().ok_or_eyre("Filename not UTF-8")?),
+            Append,
+        )?;
+    }
+
+    let func = imwri.invoke(&"Read".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre::eyre!("imwri Read failed: {}", err.to_string_lossy()));
+    }
+
+    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+}
+
 pub fn vszip_metrics(
     core: &Core,
     reference: &VideoNode,
@@ -275,8 +441,258 @@ pub fn vszip_metrics(
     Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
 }
 
+/// Keeps only the luma of a YUV clip for the metrics. The chroma planes are replaced with
+/// neutral gray (the mid value of the format, 0 for float formats) instead of being dropped,
+/// so the clip keeps its format and color metadata and SSIMULACRA2 still converts it to RGB
+/// as usual, it just doesn't see any chroma difference. Gray clips are returned as they are.
+pub fn luma_only(core: &Core, clip: &VideoNode) -> Result<VideoNode> {
+    let format = clip.info().format;
+    match format.color_family as i64 {
+        COLOR_FAMILY_GRAY => return Ok(clip.to_owned()),
+        COLOR_FAMILY_YUV => {}
+        _ => {
+            return Err(eyre!(
+                "--luma-only needs a YUV or gray clip, RGB clips have no luma plane"
+            ));
+        }
+    }
+
+    let neutral = if format.sample_type as i64 == SAMPLE_TYPE_FLOAT {
+        "0".to_owned()
+    } else {
+        (1_i64 << (format.bits_per_sample - 1)).to_string()
+    };
+
+    let std = vs_std(core)?;
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clips".to_cstring()),
+        Value::VideoNode(clip.to_owned()),
+        Replace,
+    )?;
+    // "x" copies the luma, the chroma planes become a constant
+    for (i, expr) in ["x", &neutral, &neutral].into_iter().enumerate() {
+        args.set(
+            KeyStr::from_cstr(&"expr".to_cstring()),
+            Value::Utf8(expr),
+            if i == 0 { Replace } else { Append },
+        )?;
+    }
+
+    let func = std.invoke(&"Expr".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("Expr (luma only) failed: {}", err.to_string_lossy()));
+    }
+
+    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+}
+
+/// Frame property prefix of `motion_stats`, the difference is in "MotionDiff"
+pub const MOTION_PROP: &str = "Motion";
+
+/// Attaches the mean absolute luma difference against the previous frame to every
+/// frame (vszip PlaneStats), a cheap motion proxy. Frame 0 is compared with itself.
+pub fn motion_stats(core: &Core, clip: &VideoNode) -> Result<VideoNode> {
+    let std = vs_std(core)?;
+    let num_frames = clip.info().num_frames;
+
+    // Previous frames: [f0, f0, f1, ..., f(n-2)]
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clip".to_cstring()),
+        Value::VideoNode(clip.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"frames".to_cstring()),
+        Value::Int(0),
+        Replace,
+    )?;
+    let func = std.invoke(&"DuplicateFrames".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("DuplicateFrames failed: {}", err.to_string_lossy()));
+    }
+    let shifted = func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
+
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clip".to_cstring()),
+        Value::VideoNode(shifted),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"last".to_cstring()),
+        Value::Int((num_frames - 1).into()),
+        Replace,
+    )?;
+    let func = std.invoke(&"Trim".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("Trim failed: {}", err.to_string_lossy()));
+    }
+    let previous = func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
+
+    let vszip = vszip(core)?;
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clipa".to_cstring()),
+        Value::VideoNode(clip.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"clipb".to_cstring()),
+        Value::VideoNode(previous),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"prop".to_cstring()),
+        Value::Utf8(MOTION_PROP),
+        Replace,
+    )?;
+
+    let func = vszip.invoke(&"PlaneStats".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("Vszip PlaneStats failed: {}", err.to_string_lossy()));
+    }
+
+    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+}
+
+/// Frame property prefix of `grain_stats`, the difference is in "GrainDiff"
+pub const GRAIN_PROP: &str = "Grain";
+
+/// Attaches the mean absolute luma difference against a 3x3 box blur of the frame to every
+/// frame (vszip PlaneStats), a proxy of the high frequency luma energy, mostly grain and noise
+pub fn grain_stats(core: &Core, clip: &VideoNode) -> Result<VideoNode> {
+    let std = vs_std(core)?;
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clip".to_cstring()),
+        Value::VideoNode(clip.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"planes".to_cstring()),
+        Value::Int(0),
+        Replace,
+    )?;
+    let func = std.invoke(&"BoxBlur".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("BoxBlur failed: {}", err.to_string_lossy()));
+    }
+    let blurred = func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
+
+    let vszip = vszip(core)?;
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"clipa".to_cstring()),
+        Value::VideoNode(clip.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"clipb".to_cstring()),
+        Value::VideoNode(blurred),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"prop".to_cstring()),
+        Value::Utf8(GRAIN_PROP),
+        Replace,
+    )?;
+
+    let func = vszip.invoke(&"PlaneStats".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre!("Vszip PlaneStats failed: {}", err.to_string_lossy()));
+    }
+
+    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+}
+
+/// vszip Metrics mode and the frame property it sets
+#[derive(Debug, Clone, Copy)]
+pub enum VszipMetric {
+    Psnr,
+    Ssim,
+}
+
+impl VszipMetric {
+    pub fn mode(&self) -> i64 {
+        match self {
+            VszipMetric::Psnr => 0,
+            VszipMetric::Ssim => 1,
+        }
+    }
+
+    pub fn prop(&self) -> &'static str {
+        match self {
+            VszipMetric::Psnr => "_PSNR",
+            VszipMetric::Ssim => "_SSIM",
+        }
+    }
+}
+
+/// Attaches a traditional metric (PSNR or SSIM) to every frame, using vszip Metrics
+pub fn vszip_metric(
+    core: &Core,
+    reference: &VideoNode,
+    distorted: &VideoNode,
+    metric: VszipMetric,
+) -> Result<VideoNode> {
+    let vszip = vszip(core)?;
+    let mut args = Map::default();
+    args.set(
+        KeyStr::from_cstr(&"reference".to_cstring()),
+        Value::VideoNode(reference.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"distorted".to_cstring()),
+        Value::VideoNode(distorted.to_owned()),
+        Replace,
+    )?;
+    args.set(
+        KeyStr::from_cstr(&"mode".to_cstring()),
+        Value::Int(metric.mode()),
+        Replace,
+    )?;
+
+    let func = vszip.invoke(&"Metrics".to_cstring(), args);
+    if let Some(err) = func.get_error() {
+        return Err(eyre::eyre!(
+            "Vszip Metrics ({metric:?}) failed: {}",
+            err.to_string_lossy()
+        ));
+    }
+
+    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+}
+
+/// Whether the frame props of the first frame already carry `color_metadata`, so tagging the
+/// clip again would only be an extra resample. Missing props never match
+fn has_color_metadata(clip: &VideoNode, color_metadata: &ColorMetadata) -> Result<bool> {
+    let frame = clip
+        .get_frame(0)
+        .map_err(|e| eyre!("Failed to load frame 0: {}", e.to_string_lossy()))?;
+    let props = frame
+        .properties()
+        .ok_or_eyre("Frame properties not found")?;
+    let prop = |key: &str| props.get_int(KeyStr::from_cstr(&key.to_cstring()), 0).ok();
+
+    // _ColorRange is 0 for full range, the opposite of the resize plugin
+    let color_range = 1 - i64::from(color_metadata.range.min(1));
+    Ok(prop("_Matrix") == Some(color_metadata.matrix.into())
+        && prop("_Transfer") == Some(color_metadata.transfer.into())
+        && prop("_Primaries") == Some(color_metadata.primaries.into())
+        && prop("_ColorRange") == Some(color_range)
+        && prop("_ChromaLocation") == Some(color_metadata.chromaloc.into()))
+}
+
+/// Tags the clip with the color metadata of the encoder params. Clips already tagged with it
+/// are returned as they are, without going through the resizer
 pub fn set_color_metadata(core: &Core, clip: &VideoNode, color_params: &str) -> Result<VideoNode> {
     let color_metadata = ColorMetadata::from_params(color_params);
+    if has_color_metadata(clip, &color_metadata)? {
+        return Ok(clip.to_owned());
+    }
     let resize = resize(core)?;
     let mut args = Map::default();
 
@@ -324,11 +740,76 @@ pub fn set_color_metadata(core: &Core, clip: &VideoNode, color_params: &str) ->
     Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
 }
 
-pub fn set_output(core: &Core, clip: &VideoNode, color_params: &str) -> Result<VideoNode> {
+/// YUV integer format id for a bit depth and chroma subsampling, e.g. (10, 1, 1) is
+/// YUV420P10 and (12, 0, 0) is YUV444P12
+pub fn format_from_depth_and_subsampling(
+    bits_per_sample: i32,
+    sub_sampling_w: i32,
+    sub_sampling_h: i32,
+) -> Result<i64> {
+    if !(8..=16).contains(&bits_per_sample) {
+        return Err(eyre!(
+            "Unsupported bit depth {bits_per_sample}: expected an 8-16 bit integer format"
+        ));
+    }
+    if !(0..=2).contains(&sub_sampling_w) || !(0..=2).contains(&sub_sampling_h) {
+        return Err(eyre!(
+            "Unsupported chroma subsampling {sub_sampling_w}x{sub_sampling_h}"
+        ));
+    }
+
+    Ok(make_video_id(
+        COLOR_FAMILY_YUV,
+        SAMPLE_TYPE_INTEGER,
+        bits_per_sample as i64,
+        sub_sampling_w as i64,
+        sub_sampling_h as i64,
+    ))
+}
+
+/// Bit depth and chroma subsampling of the source clip, read before it's converted to RGB
+/// so the output gets them back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceFormat {
+    pub bits_per_sample: i32,
+    pub sub_sampling_w: i32,
+    pub sub_sampling_h: i32,
+}
+
+impl SourceFormat {
+    pub fn of(clip: &VideoNode) -> Self {
+        let format = clip.info().format;
+        Self {
+            bits_per_sample: format.bits_per_sample,
+            sub_sampling_w: format.sub_sampling_w,
+            sub_sampling_h: format.sub_sampling_h,
+        }
+    }
+
+    /// YUV format id of the output
+    pub fn output_format(&self) -> Result<i64> {
+        format_from_depth_and_subsampling(
+            self.bits_per_sample,
+            self.sub_sampling_w,
+            self.sub_sampling_h,
+        )
+    }
+}
+
+/// Converts the clip back to YUV with the output color metadata.
+/// The output gets the depth and subsampling of `source`, so processing done in RGB or at
+/// a higher depth (e.g. fmtconv) doesn't change the output format.
+pub fn set_output(
+    core: &Core,
+    clip: &VideoNode,
+    color_params: &str,
+    source: SourceFormat,
+) -> Result<VideoNode> {
     let color_metadata = ColorMetadata::from_params(color_params);
     let resize = resize(core)?;
     let mut args = Map::default();
 
+    let output_format = source.output_format()?;
     args.set(
         KeyStr::from_cstr(&"clip".to_cstring()),
         Value::VideoNode(clip.to_owned()),
@@ -336,7 +817,7 @@ pub fn set_output(core: &Core, clip: &VideoNode, color_params: &str) -> Result<V
     )?;
     args.set(
         KeyStr::from_cstr(&"format".to_cstring()),
-        Value::Int(805961985), // Added format
+        Value::Int(output_format),
         Replace,
     )?;
     args.set(
@@ -380,7 +861,16 @@ pub fn set_output(core: &Core, clip: &VideoNode, color_params: &str) -> Result<V
         ));
     }
 
-    Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
+    let output = func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
+    let output_bits = output.info().format.bits_per_sample;
+    if output_bits != source.bits_per_sample {
+        return Err(eyre!(
+            "Output bit depth ({output_bits}) doesn't match the source ({})",
+            source.bits_per_sample
+        ));
+    }
+
+    Ok(output)
 }
 
 pub fn set_linear_rgb(core: &Core, clip: &VideoNode) -> Result<VideoNode> {
@@ -394,7 +884,7 @@ pub fn set_linear_rgb(core: &Core, clip: &VideoNode) -> Result<VideoNode> {
     )?;
     args.set(
         KeyStr::from_cstr(&"format".to_cstring()),
-        Value::Int(555745280), // RGBS Format
+        Value::Int(RGBS),
         Replace,
     )?;
     args.set(
@@ -488,6 +978,112 @@ impl FromStr for CropParams {
     }
 }
 
+impl std::fmt::Display for CropParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.width, self.height, self.left, self.top
+        )
+    }
+}
+
+/// Frames sampled by `--crop auto`
+pub const AUTO_CROP_SAMPLES: u32 = 20;
+/// Rows/columns whose mean luma (8-bit) is at or below this are treated as black bars
+const CROP_BLACK_THRESHOLD: f64 = 24.0;
+/// Frames whose mean luma (8-bit) is at or below this are skipped (fades, black frames)
+const CROP_DARK_FRAME_THRESHOLD: f64 = 32.0;
+
+/// Detects black borders by sampling `samples` frames spread across the clip.
+/// Only the borders present in every bright enough frame are cropped, and the
+/// result is rounded so the offsets and dimensions stay mod-2.
+pub fn detect_crop(
+    core: &Core,
+    input_path: &Path,
+    importer_plugin: &SourcePlugin,
+    temp_dir: &Path,
+    samples: u32,
+) -> Result<CropParams> {
+    let src = match importer_plugin {
+        SourcePlugin::Lsmash => lsmash_invoke(core, input_path, temp_dir)?,
+        SourcePlugin::Bestsource => bestsource_invoke(core, input_path, temp_dir)?,
+        SourcePlugin::Ffms2 => ffms2_invoke(core, input_path, temp_dir)?,
+        SourcePlugin::Imwri => imwri_invoke(core, input_path)?,
+    };
+    let info = src.info();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let num_frames = info.num_frames as usize;
+
+    // Luma only, 8-bit, same size
+    let luma = resize_format(core, &src, info.width as i64, info.height as i64, "GRAY8")?;
+
+    let samples = (samples.max(1) as usize).min(num_frames);
+    let mut borders: Option<(usize, usize, usize, usize)> = None;
+
+    for i in 0..samples {
+        // Center of each of the `samples` equal segments, so the start and end aren't favored
+        let n = (2 * i + 1) * num_frames / (2 * samples);
+        let frame = luma
+            .get_frame(n as i32)
+            .map_err(|e| eyre!("Failed to load frame {}: {}", n, e.to_string_lossy()))?;
+        let stride = frame.stride(0) as usize;
+        let plane = unsafe { std::slice::from_raw_parts(frame.plane(0), stride * height) };
+        let pixel = |x: usize, y: usize| plane[y * stride + x] as f64;
+
+        let row_mean = |y: usize| (0..width).map(|x| pixel(x, y)).sum::<f64>() / width as f64;
+        let column_mean = |x: usize| (0..height).map(|y| pixel(x, y)).sum::<f64>() / height as f64;
+
+        let frame_mean = (0..height).map(row_mean).sum::<f64>() / height as f64;
+        if frame_mean <= CROP_DARK_FRAME_THRESHOLD {
+            continue;
+        }
+
+        let is_black = |mean: f64| mean <= CROP_BLACK_THRESHOLD;
+        let top = (0..height).take_while(|&y| is_black(row_mean(y))).count();
+        let bottom = (0..height)
+            .rev()
+            .take_while(|&y| is_black(row_mean(y)))
+            .count();
+        let left = (0..width).take_while(|&x| is_black(column_mean(x))).count();
+        let right = (0..width)
+            .rev()
+            .take_while(|&x| is_black(column_mean(x)))
+            .count();
+
+        borders = Some(match borders {
+            Some((t, b, l, r)) => (t.min(top), b.min(bottom), l.min(left), r.min(right)),
+            None => (top, bottom, left, right),
+        });
+    }
+
+    let (top, bottom, left, right) = borders.ok_or_eyre(
+        "Crop detection failed: every sampled frame is too dark. Pass the crop manually",
+    )?;
+
+    // Round the offsets down, then grow the size back to mod-2 by cropping less
+    let left = left - left % 2;
+    let top = top - top % 2;
+    let mut crop_width = width.saturating_sub(left + right);
+    let mut crop_height = height.saturating_sub(top + bottom);
+    if crop_width == 0 || crop_height == 0 {
+        return Err(eyre!("Crop detection failed: the whole frame looks black"));
+    }
+    if crop_width % 2 == 1 {
+        crop_width = (crop_width + 1).min(width - left);
+    }
+    if crop_height % 2 == 1 {
+        crop_height = (crop_height + 1).min(height - top);
+    }
+
+    Ok(CropParams {
+        width: crop_width as i64,
+        height: crop_height as i64,
+        left: left as i64,
+        top: top as i64,
+    })
+}
+
 pub fn to_crop(core: &Core, reference: &VideoNode, crop: &str) -> Result<VideoNode> {
     let crop_params = CropParams::from_str(crop)?;
     let ref_info = reference.info();
@@ -535,11 +1131,59 @@ pub fn to_crop(core: &Core, reference: &VideoNode, crop: &str) -> Result<VideoNo
     Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
 }
 
+/// fmtconv resampling kernels usable for downscaling
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum ResizeKernel {
+    #[default]
+    Box,
+    Bilinear,
+    Bicubic,
+    Spline16,
+    Spline36,
+    Spline64,
+    Lanczos,
+    Blackman,
+    Gauss,
+}
+
+impl ResizeKernel {
+    /// Kernel name in fmtconv
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResizeKernel::Box => "box",
+            ResizeKernel::Bilinear => "bilinear",
+            ResizeKernel::Bicubic => "bicubic",
+            ResizeKernel::Spline16 => "spline16",
+            ResizeKernel::Spline36 => "spline36",
+            ResizeKernel::Spline64 => "spline64",
+            ResizeKernel::Lanczos => "lanczos",
+            ResizeKernel::Blackman => "blackman",
+            ResizeKernel::Gauss => "gauss",
+        }
+    }
+}
+
+/// Kernel used by `downscale_resolution`, box by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownscaleKernel {
+    pub kernel: ResizeKernel,
+    /// Taps of lanczos/blackman/spline, fmtconv default when None
+    pub taps: Option<i64>,
+}
+
 pub fn downscale_resolution(
     core: &Core,
     reference: &VideoNode,
     downscale: f64,
+    kernel: &str,
+    taps: Option<i64>,
 ) -> Result<VideoNode> {
+    if !(downscale > 0.0 && downscale < 1.0) {
+        return Err(eyre!(
+            "Downscale factor must be between 0 and 1 (got {downscale})"
+        ));
+    }
+
     // Get plugin handles
     let fmtconv_plugin = fmtconv(core)?;
     let std_plugin = vs_std(core)?;
@@ -548,7 +1192,7 @@ pub fn downscale_resolution(
 
     // Check if height/2 is odd and crop if needed
     let mut working_clip = reference.clone();
-    if (ref_info.height / 2) % 2 != 0 {
+    if downscale == 0.5 && (ref_info.height / 2) % 2 != 0 {
         let mut crop_args = Map::default();
         crop_args.set(
             KeyStr::from_cstr(&"clip".to_cstring()),
@@ -575,7 +1219,14 @@ pub fn downscale_resolution(
 
     working_clip = set_linear_rgb(core, &working_clip)?;
 
-    // Box downscale (scale = 0.5)
+    // Output dimensions rounded to even, so the clip can go back to 4:2:0
+    let (width, height) = downscaled_dimensions(
+        working_clip.info().width,
+        working_clip.info().height,
+        downscale,
+    );
+
+    // Downscale (box, scale = 0.5 by default)
     let mut fmt_args = Map::default();
     fmt_args.set(
         KeyStr::from_cstr(&"clip".to_cstring()),
@@ -584,26 +1235,38 @@ pub fn downscale_resolution(
     )?;
     fmt_args.set(
         KeyStr::from_cstr(&"kernel".to_cstring()),
-        Value::Utf8("box"),
+        Value::Utf8(kernel),
         Replace,
     )?;
     fmt_args.set(
-        KeyStr::from_cstr(&"scale".to_cstring()),
-        Value::Float(downscale),
+        KeyStr::from_cstr(&"w".to_cstring()),
+        Value::Int(width),
+        Replace,
+    )?;
+    fmt_args.set(
+        KeyStr::from_cstr(&"h".to_cstring()),
+        Value::Int(height),
         Replace,
     )?;
+    if let Some(taps) = taps {
+        fmt_args.set(
+            KeyStr::from_cstr(&"taps".to_cstring()),
+            Value::Int(taps),
+            Replace,
+        )?;
+    }
 
     let resampled = fmtconv_plugin.invoke(&"resample".to_cstring(), fmt_args);
     if let Some(err) = resampled.get_error() {
         return Err(eyre::eyre!(
-            "Box resample failed: {}",
+            "Resample ({kernel}) failed: {}",
             err.to_string_lossy()
         ));
     }
 
-    let box_clip = resampled.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
+    let downscaled_clip = resampled.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?;
 
-    Ok(box_clip)
+    Ok(downscaled_clip)
 }
 
 pub fn resize_resolution(
@@ -659,6 +1322,32 @@ pub fn resize_resolution(
     Ok(resize_clip)
 }
 
+/// Width and height scaled by `downscale`, rounded to the nearest even value
+pub fn downscaled_dimensions(width: i32, height: i32, downscale: f64) -> (i64, i64) {
+    let scale_even = |value: i32| ((value as f64 * downscale / 2.0).round() as i64 * 2).max(2);
+    (scale_even(width), scale_even(height))
+}
+
+/// Parses a `--downscale` value: a factor in (0, 1], 1 disables it.
+/// "true" and "false" are kept for compatibility (0.5 and 1)
+pub fn parse_downscale(s: &str) -> Result<f64, String> {
+    let downscale = match s {
+        "true" => 0.5,
+        "false" => 1.0,
+        _ => s
+            .parse::<f64>()
+            .map_err(|e| format!("Invalid downscale factor '{s}': {e}"))?,
+    };
+
+    if downscale > 0.0 && downscale <= 1.0 {
+        Ok(downscale)
+    } else {
+        Err(format!(
+            "Downscale factor must be between 0 and 1 (got {downscale})"
+        ))
+    }
+}
+
 pub fn parse_resolution(res: &str) -> Result<(u32, u32)> {
     let parts: Vec<&str> = res.split('x').collect();
     if parts.len() != 2 {
@@ -678,7 +1367,79 @@ pub fn parse_resolution(res: &str) -> Result<(u32, u32)> {
     Ok((width, height))
 }
 
-pub fn parse_trim(res: &str) -> Result<(i32, i32)> {
+/// Frame range of a trim, `first` and `last` are inclusive.
+/// Negative values count from the end of the clip (-1 is the last frame).
+/// `None` leaves that side open: no trim at the start or no trim at the end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+}
+
+impl Trim {
+    /// Parses both ends of a trim. An empty value, a `first` of 0 or a `last` of -1 are open
+    pub fn from_bounds(first: &str, last: &str) -> Result<Self> {
+        let first = match first.trim() {
+            "" => None,
+            value => Some(
+                value
+                    .parse::<i64>()
+                    .map_err(|e| eyre!("Invalid start '{}': {}", value, e))?,
+            ),
+        };
+        let last = match last.trim() {
+            "" => None,
+            value => Some(
+                value
+                    .parse::<i64>()
+                    .map_err(|e| eyre!("Invalid end '{}': {}", value, e))?,
+            ),
+        };
+
+        Ok(Trim {
+            first: first.filter(|&first| first != 0),
+            last: last.filter(|&last| last != -1),
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.first.is_none() && self.last.is_none()
+    }
+
+    /// First and last frame (inclusive) of the trim in a clip of `num_frames` frames
+    pub fn resolve(&self, num_frames: i64) -> Result<(i64, i64)> {
+        let from_end = |frame: i64| if frame < 0 { num_frames + frame } else { frame };
+        let first = self.first.map_or(0, from_end);
+        let last = self.last.map_or(num_frames - 1, from_end);
+
+        if first < 0 || last >= num_frames || first > last {
+            return Err(eyre!(
+                "Trim {} is out of range for a clip of {} frames",
+                self,
+                num_frames
+            ));
+        }
+
+        Ok((first, last))
+    }
+}
+
+impl std::fmt::Display for Trim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.first.unwrap_or(0), self.last.unwrap_or(-1))
+    }
+}
+
+impl FromStr for Trim {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_trim(s)
+    }
+}
+
+/// Parses a "START:END" trim. Examples: 1261:5623, 0:2432, 2352:-1, 2352:, :-25
+pub fn parse_trim(res: &str) -> Result<Trim> {
     let parts: Vec<&str> = res.split(':').collect();
     if parts.len() != 2 {
         return Err(eyre!(
@@ -687,14 +1448,7 @@ pub fn parse_trim(res: &str) -> Result<(i32, i32)> {
         ));
     }
 
-    let start = parts[0]
-        .parse::<i32>()
-        .map_err(|e| eyre!("Invalid start '{}': {}", parts[0], e))?;
-    let end = parts[1]
-        .parse::<i32>()
-        .map_err(|e| eyre!("Invalid end '{}': {}", parts[1], e))?;
-
-    Ok((start, end))
+    Trim::from_bounds(parts[0], parts[1])
 }
 
 #[derive(Debug, Clone)]
@@ -705,8 +1459,7 @@ pub enum ClipTarget {
 
 #[derive(Debug, Clone)]
 pub struct TrimComplex {
-    pub first: usize,
-    pub last: usize,
+    pub trim: Trim,
     pub clip_target: ClipTarget,
 }
 
@@ -719,12 +1472,7 @@ impl FromStr for TrimComplex {
             return Err("Expected format: first,last,clip".into());
         }
 
-        let first = parts[0]
-            .parse::<usize>()
-            .map_err(|_| "Invalid first value")?;
-        let last = parts[1]
-            .parse::<usize>()
-            .map_err(|_| "Invalid last value")?;
+        let trim = Trim::from_bounds(parts[0], parts[1]).map_err(|e| e.to_string())?;
 
         let clip_target = match parts[2].to_lowercase().as_str() {
             "r" | "reference" => ClipTarget::Reference,
@@ -732,11 +1480,7 @@ impl FromStr for TrimComplex {
             other => return Err(format!("Invalid clip target: '{other}'")),
         };
 
-        Ok(TrimComplex {
-            first,
-            last,
-            clip_target,
-        })
+        Ok(TrimComplex { trim, clip_target })
     }
 }
 
@@ -754,28 +1498,38 @@ pub fn synchronize_clips(
         ClipTarget::Distorted => (distorted, reference, false),
     };
 
+    if trim.trim.is_open() {
+        return Ok((reference.clone(), distorted.clone()));
+    }
+    let (first, last) = trim.trim.resolve(target_clip.info().num_frames.into())?;
+
     args.set(
         KeyStr::from_cstr(&"clip".to_cstring()),
         Value::VideoNode(target_clip.to_owned()),
         Replace,
     )?;
-    args.set(
-        KeyStr::from_cstr(&"first".to_cstring()),
-        Value::Int(trim.first as i64),
-        Replace,
-    )?;
-    args.set(
-        KeyStr::from_cstr(&"last".to_cstring()),
-        Value::Int(trim.last as i64),
-        Replace,
-    )?;
+    // Open-ended trims only cut one boundary
+    if trim.trim.first.is_some() {
+        args.set(
+            KeyStr::from_cstr(&"first".to_cstring()),
+            Value::Int(first),
+            Replace,
+        )?;
+    }
+    if trim.trim.last.is_some() {
+        args.set(
+            KeyStr::from_cstr(&"last".to_cstring()),
+            Value::Int(last),
+            Replace,
+        )?;
+    }
 
     let func = std.invoke(&"Trim".to_cstring(), args);
     if let Some(err) = func.get_error() {
         return Err(eyre::eyre!(
             "Failed to trim selected clip ({}–{}): {}",
-            trim.first,
-            trim.last,
+            first,
+            last,
             err.to_string_lossy()
         ));
     }
@@ -794,11 +1548,11 @@ pub fn trim_clip(core: &Core, input: &VideoNode, trim: &str) -> Result<VideoNode
 
     let mut args = Map::default();
 
-    let (start, mut end) = parse_trim(trim)?;
-    let info = input.info();
-    if end == -1 {
-        end = info.num_frames - 1;
+    let trim = parse_trim(trim)?;
+    if trim.is_open() {
+        return Ok(input.to_owned());
     }
+    let (start, end) = trim.resolve(input.info().num_frames.into())?;
 
     args.set(
         KeyStr::from_cstr(&"clip".to_cstring()),
@@ -807,12 +1561,12 @@ pub fn trim_clip(core: &Core, input: &VideoNode, trim: &str) -> Result<VideoNode
     )?;
     args.set(
         KeyStr::from_cstr(&"first".to_cstring()),
-        Value::Int(start.into()),
+        Value::Int(start),
         Replace,
     )?;
     args.set(
         KeyStr::from_cstr(&"last".to_cstring()),
-        Value::Int(end.into()),
+        Value::Int(end),
         Replace,
     )?;
 
@@ -841,6 +1595,7 @@ pub fn get_dimensions(
         SourcePlugin::Lsmash => lsmash_invoke(core, input, temp_dir)?,
         SourcePlugin::Bestsource => bestsource_invoke(core, input, temp_dir)?,
         SourcePlugin::Ffms2 => ffms2_invoke(core, input, temp_dir)?,
+        SourcePlugin::Imwri => imwri_invoke(core, input)?,
     };
 
     let info = reference.info();
@@ -861,6 +1616,7 @@ pub fn get_number_of_frames(
         SourcePlugin::Lsmash => lsmash_invoke(core, input, temp_dir)?,
         SourcePlugin::Bestsource => bestsource_invoke(core, input, temp_dir)?,
         SourcePlugin::Ffms2 => ffms2_invoke(core, input, temp_dir)?,
+        SourcePlugin::Imwri => imwri_invoke(core, input)?,
     };
 
     let info = reference.info();
@@ -873,6 +1629,20 @@ pub struct Dimensions {
     pub height: i32,
 }
 
+/// Writes to a temp file in the same directory and renames it over `path`, so a crash
+/// leaves either the complete new file or the previous one
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents.as_ref())?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
+}
+
 pub fn add_extension(ext: impl AsRef<OsStr>, path: PathBuf) -> PathBuf {
     let mut os_string: OsString = path.into();
     os_string.push(".");
@@ -880,7 +1650,38 @@ pub fn add_extension(ext: impl AsRef<OsStr>, path: PathBuf) -> PathBuf {
     os_string.into()
 }
 
-pub fn inverse_telecine(core: &Core, input: &VideoNode) -> Result<VideoNode> {
+/// Inverse telecine settings (vivtc)
+#[derive(Debug, Clone, Copy)]
+pub struct Ivtc {
+    /// VFM field order: 1 = top field first, 0 = bottom field first
+    pub field_order: i64,
+    /// VFM matching mode (0-5)
+    pub vfm_mode: i64,
+    /// VDecimate drops one frame out of every `decimate_cycle` frames
+    pub decimate_cycle: i64,
+    /// VDecimate difference (in percent) under which a frame counts as a duplicate
+    pub decimate_dupthresh: f64,
+}
+
+impl Default for Ivtc {
+    fn default() -> Self {
+        Ivtc {
+            field_order: 1,
+            vfm_mode: 1,
+            decimate_cycle: 5,
+            decimate_dupthresh: 1.1,
+        }
+    }
+}
+
+pub fn inverse_telecine(
+    core: &Core,
+    input: &VideoNode,
+    field_order: i64,
+    vfm_mode: i64,
+    cycle: i64,
+    dupthresh: f64,
+) -> Result<VideoNode> {
     // Load vivtc plugin
     let vivtc = vivtc(core)?;
 
@@ -893,12 +1694,12 @@ pub fn inverse_telecine(core: &Core, input: &VideoNode) -> Result<VideoNode> {
     )?;
     vfm_args.set(
         KeyStr::from_cstr(&"order".to_cstring()),
-        Value::Int(1), // Top field first
+        Value::Int(field_order), // 1 = Top field first
         Replace,
     )?;
     vfm_args.set(
         KeyStr::from_cstr(&"mode".to_cstring()),
-        Value::Int(1), // Full field matching
+        Value::Int(vfm_mode), // 1 = Full field matching
         Replace,
     )?;
 
@@ -915,6 +1716,16 @@ pub fn inverse_telecine(core: &Core, input: &VideoNode) -> Result<VideoNode> {
         Value::VideoNode(vfm_clip.clone()),
         Replace,
     )?;
+    vdecimate_args.set(
+        KeyStr::from_cstr(&"cycle".to_cstring()),
+        Value::Int(cycle),
+        Replace,
+    )?;
+    vdecimate_args.set(
+        KeyStr::from_cstr(&"dupthresh".to_cstring()),
+        Value::Float(dupthresh),
+        Replace,
+    )?;
 
     let vdecimate_out = vivtc.invoke(&"VDecimate".to_cstring(), vdecimate_args);
     if let Some(err) = vdecimate_out.get_error() {
@@ -937,22 +1748,32 @@ pub fn prepare_clip(
     crop: Option<&str>,
     trim: Option<&str>,
     downscale: f64,
-    detelecine: bool,
+    downscale_kernel: DownscaleKernel,
+    detelecine: Option<Ivtc>,
 ) -> Result<VideoNode> {
     let mut input = match importer_plugin {
         SourcePlugin::Lsmash => lsmash_invoke(core, input_path, temp_folder)?,
         SourcePlugin::Bestsource => bestsource_invoke(core, input_path, temp_folder)?,
         SourcePlugin::Ffms2 => ffms2_invoke(core, input_path, temp_folder)?,
+        SourcePlugin::Imwri => imwri_invoke(core, input_path)?,
     };
 
     if verbose {
         println!("Original\nVideo: {:?}\n", input.info(),);
     }
+    let source_format = SourceFormat::of(&input);
 
     input = set_color_metadata(core, &input, color_metadata)?;
 
-    if detelecine {
-        input = inverse_telecine(core, &input)?;
+    if let Some(ivtc) = detelecine {
+        input = inverse_telecine(
+            core,
+            &input,
+            ivtc.field_order,
+            ivtc.vfm_mode,
+            ivtc.decimate_cycle,
+            ivtc.decimate_dupthresh,
+        )?;
     }
 
     if let Some(trim) = trim.filter(|s| !s.is_empty()) {
@@ -960,8 +1781,14 @@ pub fn prepare_clip(
     }
 
     if downscale < 1.0 {
-        input = downscale_resolution(core, &input, downscale)?;
-        input = set_output(core, &input, color_metadata)?;
+        input = downscale_resolution(
+            core,
+            &input,
+            downscale,
+            downscale_kernel.kernel.as_str(),
+            downscale_kernel.taps,
+        )?;
+        input = set_output(core, &input, color_metadata, source_format)?;
     }
 
     if let Some(crop_str) = crop.filter(|s| !s.is_empty()) {
@@ -975,6 +1802,40 @@ pub fn prepare_clip(
     Ok(input)
 }
 
+const COLOR_FAMILY_GRAY: i64 = 1;
+const COLOR_FAMILY_RGB: i64 = 2;
+const COLOR_FAMILY_YUV: i64 = 3;
+const SAMPLE_TYPE_INTEGER: i64 = 0;
+const SAMPLE_TYPE_FLOAT: i64 = 1;
+
+/// VapourSynth video format id, same as `VS_MAKE_VIDEO_ID` in the C API
+pub const fn make_video_id(
+    color_family: i64,
+    sample_type: i64,
+    bits_per_sample: i64,
+    sub_sampling_w: i64,
+    sub_sampling_h: i64,
+) -> i64 {
+    (color_family << 28)
+        | (sample_type << 24)
+        | (bits_per_sample << 16)
+        | (sub_sampling_w << 8)
+        | sub_sampling_h
+}
+
+pub const RGB24: i64 = make_video_id(COLOR_FAMILY_RGB, SAMPLE_TYPE_INTEGER, 8, 0, 0);
+pub const RGB30: i64 = make_video_id(COLOR_FAMILY_RGB, SAMPLE_TYPE_INTEGER, 10, 0, 0);
+pub const RGB48: i64 = make_video_id(COLOR_FAMILY_RGB, SAMPLE_TYPE_INTEGER, 16, 0, 0);
+pub const GRAY8: i64 = make_video_id(COLOR_FAMILY_GRAY, SAMPLE_TYPE_INTEGER, 8, 0, 0);
+pub const RGBS: i64 = make_video_id(COLOR_FAMILY_RGB, SAMPLE_TYPE_FLOAT, 32, 0, 0);
+pub const YUV420P10: i64 = make_video_id(COLOR_FAMILY_YUV, SAMPLE_TYPE_INTEGER, 10, 1, 1);
+
+// Same ids as the pfRGB24, pfGray8, pfRGBS and pfYUV420P10 presets of the C API
+const _: () = assert!(
+    RGB24 == 537395200 && GRAY8 == 268959744 && RGBS == 555745280 && YUV420P10 == 805961985
+);
+
+/// Converts the clip to `format` (RGB24, RGB30, RGB48 or GRAY8) at the given resolution
 pub fn resize_format(
     core: &Core,
     clip: &VideoNode,
@@ -986,8 +1847,13 @@ pub fn resize_format(
     let mut args = Map::default();
 
     let format = match format {
-        "RGB24" => 537395200,
-        _ => Err(eyre!("Color format is not supported"))?,
+        "RGB24" => RGB24,
+        "RGB30" => RGB30,
+        "RGB48" => RGB48,
+        "GRAY8" => GRAY8,
+        _ => Err(eyre!(
+            "Color format {format} is not supported (RGB24, RGB30, RGB48 or GRAY8)"
+        ))?,
     };
 
     args.set(
@@ -1024,18 +1890,121 @@ pub fn resize_format(
     Ok(func.get_video_node(KeyStr::from_cstr(&"clip".to_cstring()), 0)?)
 }
 
+/// Converts `frame` of `clip` to 8-bit RGB and saves it as a PNG
+pub fn save_frame_png(core: &Core, clip: &VideoNode, frame: u32, png_path: &Path) -> Result<()> {
+    let info = clip.info();
+    if frame >= info.num_frames as u32 {
+        return Err(eyre!(
+            "Frame {frame} is out of range, the clip has {} frames",
+            info.num_frames
+        ));
+    }
+
+    let clip = select_frames(core, clip, &[frame])?;
+    let rgb = resize_format(core, &clip, info.width.into(), info.height.into(), "RGB24")?;
+    let rgb_frame = rgb
+        .get_frame(0)
+        .map_err(|e| eyre!("Failed to load frame {}: {}", frame, e.to_string_lossy()))?;
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width as u32, height as u32)
+        .ok_or_eyre("Failed to allocate PNG buffer")?;
+
+    // RGB24 is planar, one 8-bit plane per channel
+    let planes: Vec<(&[u8], usize)> = (0..3)
+        .map(|c| {
+            let stride = rgb_frame.stride(c) as usize;
+            let plane = unsafe { std::slice::from_raw_parts(rgb_frame.plane(c), stride * height) };
+            (plane, stride)
+        })
+        .collect();
+    for (i, pixel) in pixmap.data_mut().chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        for (c, (plane, stride)) in planes.iter().enumerate() {
+            pixel[c] = plane[y * stride + x];
+        }
+        pixel[3] = u8::MAX;
+    }
+    pixmap.save_png(png_path)?;
+
+    Ok(())
+}
+
+/// Frame rate used to convert seconds to frames. `fps` overrides the one of the clip,
+/// and is required for variable frame rate clips (reported with a 0/0 fps by VapourSynth)
+pub fn clip_fps(clip: &VideoNode, fps: Option<f64>) -> Result<f64> {
+    let info = clip.info();
+    let variable = info.fps_num == 0 || info.fps_den == 0;
+    match fps {
+        Some(fps) if fps <= 0.0 || !fps.is_finite() => Err(eyre!("Invalid --fps {fps}")),
+        Some(fps) => {
+            if variable {
+                eprintln!(
+                    "Warning: variable frame rate source, using --fps {fps} to convert seconds to frames"
+                );
+            }
+            Ok(fps)
+        }
+        None if variable => Err(eyre!(
+            "The source has a variable frame rate, seconds can't be converted to frames. \
+             Pass --fps or give the lengths in frames"
+        )),
+        None => Ok(info.fps_num as f64 / info.fps_den as f64),
+    }
+}
+
+/// Rounds up so a length in seconds is never shorter in frames
+pub fn seconds_to_frames_at(seconds: f64, fps: f64) -> u32 {
+    (seconds * fps).ceil() as u32
+}
+
 pub fn seconds_to_frames(
     core: &Core,
     seconds: f64,
     input_path: &Path,
     importer_plugin: &SourcePlugin,
     temp_dir: &Path,
+    fps: Option<f64>,
 ) -> Result<u32> {
     let src = match importer_plugin {
         SourcePlugin::Lsmash => lsmash_invoke(core, input_path, temp_dir)?,
         SourcePlugin::Bestsource => bestsource_invoke(core, input_path, temp_dir)?,
         SourcePlugin::Ffms2 => ffms2_invoke(core, input_path, temp_dir)?,
+        SourcePlugin::Imwri => imwri_invoke(core, input_path)?,
     };
-    let video_info = src.info();
-    Ok(((seconds * video_info.fps_num as f64) / video_info.fps_den as f64).ceil() as u32)
+    Ok(seconds_to_frames_at(seconds, clip_fps(&src, fps)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscaled_output_keeps_source_format() {
+        // 4:2:0 10-bit source, downscaled through RGBS (no subsampling, 32-bit float)
+        let source = SourceFormat {
+            bits_per_sample: 10,
+            sub_sampling_w: 1,
+            sub_sampling_h: 1,
+        };
+        assert_eq!(source.output_format().unwrap(), YUV420P10);
+
+        let yuv444p12 = SourceFormat {
+            bits_per_sample: 12,
+            sub_sampling_w: 0,
+            sub_sampling_h: 0,
+        };
+        assert_eq!(
+            yuv444p12.output_format().unwrap(),
+            make_video_id(COLOR_FAMILY_YUV, SAMPLE_TYPE_INTEGER, 12, 0, 0)
+        );
+
+        let rgbs = SourceFormat {
+            bits_per_sample: 32,
+            sub_sampling_w: 0,
+            sub_sampling_h: 0,
+        };
+        assert!(rgbs.output_format().is_err());
+    }
 }
@@ -1,13 +1,18 @@
 use std::{
     fs,
-    path::{Path, absolute},
+    path::{Path, PathBuf, absolute},
     process::Stdio,
 };
 
-use crate::vapoursynth::{add_extension, parse_resolution, parse_trim};
-use crate::{scenes::SceneList, vapoursynth::SourcePlugin};
+use crate::vapoursynth::{
+    DownscaleKernel, Ivtc, add_extension, imwri_vpy_source, index_cache_name, parse_resolution,
+    parse_trim, prepare_clip, resize_resolution, save_frame_png,
+};
+use crate::{binaries::ffmsindex_command, scenes::SceneList, vapoursynth::SourcePlugin};
+use clap::ValueEnum;
 use eyre::{OptionExt, Result, eyre};
 use std::str::FromStr;
+use vapoursynth4_rs::core::Core;
 
 #[allow(clippy::too_many_arguments)]
 pub fn create_vpy_file<'a>(
@@ -17,12 +22,15 @@ pub fn create_vpy_file<'a>(
     source_plugin: &'a SourcePlugin,
     crop: Option<&str>,
     downscale: f64,
+    downscale_kernel: DownscaleKernel,
     resize: Option<&str>,
     trim: Option<&str>,
-    detelecine: bool,
+    detelecine: Option<Ivtc>,
     encoder_params: &str,
     temp_folder: &'a Path,
     override_file: bool,
+    vspipe_args: &[String],
+    prefilter: Option<&str>,
 ) -> Result<&'a Path> {
     if override_file && vpy_file.exists() {
         fs::remove_file(vpy_file)?;
@@ -36,18 +44,13 @@ pub fn create_vpy_file<'a>(
     // Configure source and cache
     let (source, cache) = {
         // Determine cache/index file path
+        let cache_name = index_cache_name(input)?;
         let cache_path = absolute(match source_plugin {
-            SourcePlugin::Lsmash => add_extension(
-                "lwi",
-                temp_folder.join(input.file_name().ok_or_eyre("Input path has no filename")?),
-            ),
-            SourcePlugin::Bestsource => {
-                temp_folder.join(input.file_name().ok_or_eyre("Input path has no filename")?)
-            }
-            SourcePlugin::Ffms2 => add_extension(
-                "ffindex",
-                temp_folder.join(input.file_name().ok_or_eyre("Input path has no filename")?),
-            ),
+            SourcePlugin::Lsmash => add_extension("lwi", temp_folder.join(&cache_name)),
+            SourcePlugin::Bestsource => temp_folder.join(&cache_name),
+            SourcePlugin::Ffms2 => add_extension("ffindex", temp_folder.join(&cache_name)),
+            // Image sequences have no index, the path is never used
+            SourcePlugin::Imwri => temp_folder.join(&cache_name),
         })?;
 
         let cache_str = cache_path.to_str().ok_or_eyre("Filename not UTF-8")?;
@@ -56,7 +59,7 @@ pub fn create_vpy_file<'a>(
         if let SourcePlugin::Ffms2 = source_plugin
             && !cache_path.exists()
         {
-            let status = std::process::Command::new("ffmsindex")
+            let status = ffmsindex_command()
                 .arg("-f")
                 .arg("-p")
                 .arg(input)
@@ -83,18 +86,26 @@ pub fn create_vpy_file<'a>(
                 format!("cachepath=\"{cache_str}\", cachemode=4"),
             ),
             SourcePlugin::Ffms2 => ("core.ffms2.Source", format!("cachefile=\"{cache_str}\"")),
+            SourcePlugin::Imwri => ("core.imwri.Read", String::new()),
         }
     };
 
+    let source_call = match source_plugin {
+        SourcePlugin::Imwri => imwri_vpy_source(input)?,
+        _ => format!("{source}(\"{input_str}\", {cache})"),
+    };
+
     // Build script sections
     let header = format!(
         r#"import vapoursynth as vs
 core = vs.core
 
-src = {source}("{input_str}", {cache})
+src = {source_call}
 "#
     );
 
+    let vspipe_args_section = vspipe_args_section(vspipe_args);
+
     let color_metadata_section = format!(
         r#"src = core.resize.Bicubic(
     src,
@@ -126,14 +137,17 @@ src = core.std.Splice(selected_frames)
         String::new()
     };
 
-    let detelecine_section = if detelecine {
-        r#"
+    let detelecine_section = if let Some(ivtc) = detelecine {
+        format!(
+            r#"
 # IVTC for 29.97fps to 23.976fps conversion
-src = core.vivtc.VFM(src, order=1, mode=1)
-src = core.vivtc.VDecimate(src)
-"#
+src = core.vivtc.VFM(src, order={}, mode={})
+src = core.vivtc.VDecimate(src, cycle={}, dupthresh={})
+"#,
+            ivtc.field_order, ivtc.vfm_mode, ivtc.decimate_cycle, ivtc.decimate_dupthresh
+        )
     } else {
-        ""
+        String::new()
     };
 
     let crop = if let Some(crop_str) = crop.filter(|s| !s.is_empty()) {
@@ -159,14 +173,21 @@ src = core.std.CropAbs(
         String::new()
     };
 
-    let trim_section = if let Some(trim_str) = trim.filter(|s| !s.is_empty()) {
-        let (start, end) = parse_trim(trim_str)?;
+    let prefilter_section = prefilter
+        .map(|prefilter| format!("\n# Prefilter\n{prefilter}\n"))
+        .unwrap_or_default();
 
-        match (start, end) {
-            (0, -1) => String::new(),
-            (start, -1) => format!("src = src[{start}:]", start = start),
-            (0, end) => format!("src = src[:{end}]", end = end),
-            (start, end) => format!("src = src[{start}:{end}]", start = start, end = end),
+    let trim_section = if let Some(trim_str) = trim.filter(|s| !s.is_empty()) {
+        let trim = parse_trim(trim_str)?;
+
+        // The trim end is inclusive, like std.Trim in prepare_clip
+        match (trim.first, trim.last) {
+            (None, None) => String::new(),
+            (Some(start), None) => format!("src = src[{start}:]", start = start),
+            (None, Some(end)) => format!("src = src[:{end}]", end = end + 1),
+            (Some(start), Some(end)) => {
+                format!("src = src[{start}:{end}]", start = start, end = end + 1)
+            }
         }
     } else {
         String::new()
@@ -176,9 +197,7 @@ src = core.std.CropAbs(
         format!(
             r#"
 rgb = core.resize.Bicubic(src, transfer_s="linear", format=vs.RGBS)
-if (rgb.height / 2) % 2 != 0:
-    rgb = core.std.Crop(rgb, top=1, bottom=1)
-downscaled = core.fmtc.resample(rgb, kernel="box", scale={downscale})
+{crop_rows}downscaled = core.fmtc.resample(rgb, kernel="{kernel}", w=int(rgb.width * {downscale} / 2 + 0.5) * 2, h=int(rgb.height * {downscale} / 2 + 0.5) * 2{taps})
 
 src = core.resize.Bicubic(
     downscaled,
@@ -197,6 +216,16 @@ src = core.resize.Bicubic(
             range = color_metadata.range,
             chromaloc = color_metadata.chromaloc,
             downscale = downscale,
+            crop_rows = if downscale == 0.5 {
+                "if (rgb.height / 2) % 2 != 0:\n    rgb = core.std.Crop(rgb, top=1, bottom=1)\n"
+            } else {
+                ""
+            },
+            kernel = downscale_kernel.kernel.as_str(),
+            taps = downscale_kernel
+                .taps
+                .map(|taps| format!(", taps={taps}"))
+                .unwrap_or_default(),
         )
     } else {
         String::new()
@@ -257,13 +286,130 @@ src = core.resize.Bicubic(
     };
 
     let vpy_script = format!(
-        "{header}\n{color_metadata_section}\n{detelecine_section}\n{trim_section}\n{frame_selection_section}\n{crop}\n{downscale_section}\n{resize_section}\n{out_section}\nsrc.set_output()\n",
+        "{header}\n{vspipe_args_section}{color_metadata_section}\n{detelecine_section}\n{trim_section}\n{frame_selection_section}\n{crop}\n{prefilter_section}\n{downscale_section}\n{resize_section}\n{out_section}\nsrc.set_output()\n",
     );
 
     fs::write(vpy_file, vpy_script)?;
     Ok(vpy_file)
 }
 
+/// Parses a `--vspipe-arg` given as `key=value`. vspipe sets the key as a global of the
+/// script, so it has to be a Python name, and av1an params are split on whitespace
+pub fn parse_vspipe_arg(arg: &str) -> Result<String, String> {
+    let (key, _) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("Expected key=value, got '{arg}'"))?;
+
+    let mut chars = key.chars();
+    let is_name = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_name {
+        return Err(format!("'{key}' is not a valid Python variable name"));
+    }
+    if arg.contains(char::is_whitespace) {
+        return Err(format!(
+            "'{arg}' contains whitespace, which av1an params can't carry"
+        ));
+    }
+
+    Ok(arg.to_owned())
+}
+
+/// Parses a `--prefilter` line, a Python statement that has to assign `src` from `src`,
+/// e.g. `src = core.bilateral.Bilateral(src, sigmaS=1.5)`
+pub fn parse_prefilter(prefilter: &str) -> Result<String, String> {
+    let prefilter = prefilter.trim();
+    if prefilter.contains('\n') {
+        return Err("The prefilter has to be a single line".to_owned());
+    }
+
+    let value = prefilter
+        .strip_prefix("src")
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix('='))
+        .filter(|value| !value.starts_with('='))
+        .ok_or_else(|| format!("The prefilter has to assign src (src = ...), got '{prefilter}'"))?;
+    if !value.contains("src") {
+        return Err(format!(
+            "The prefilter has to filter src, got '{prefilter}'"
+        ));
+    }
+
+    Ok(prefilter.to_owned())
+}
+
+/// Script section reading the `vspipe -a key=value` arguments. vspipe sets them as str
+/// globals, the values given to frame-boost are the defaults so the script also runs alone
+fn vspipe_args_section(vspipe_args: &[String]) -> String {
+    if vspipe_args.is_empty() {
+        return String::new();
+    }
+
+    let mut section = String::from(
+        "import sys\n\n# Arguments passed with vspipe -a key=value (av1an --vspipe-args)\n",
+    );
+    let mut names = Vec::new();
+    for (key, value) in vspipe_args.iter().filter_map(|arg| arg.split_once('=')) {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        section.push_str(&format!("{key} = globals().get(\"{key}\", \"{value}\")\n"));
+        names.push(format!("\"{key}\": {key}"));
+    }
+    section.push_str(&format!(
+        "vspipe_args = {{{}}}\nprint(f\"vspipe args: {{vspipe_args}}\", file=sys.stderr)\n\n",
+        names.join(", ")
+    ));
+
+    section
+}
+
+/// Renders `frame` through prepare_clip (and --resize) and saves it as a PNG next to `vpy_file`,
+/// to check a crop or a scale without a preview tool
+#[allow(clippy::too_many_arguments)]
+pub fn preview_vpy_frame(
+    core: &Core,
+    input: &Path,
+    vpy_file: &Path,
+    frame: u32,
+    source_plugin: &SourcePlugin,
+    crop: Option<&str>,
+    downscale: f64,
+    downscale_kernel: DownscaleKernel,
+    resize: Option<&str>,
+    trim: Option<&str>,
+    detelecine: Option<Ivtc>,
+    encoder_params: &str,
+    temp_folder: &Path,
+) -> Result<PathBuf> {
+    let mut clip = prepare_clip(
+        core,
+        input,
+        source_plugin,
+        temp_folder,
+        false,
+        encoder_params,
+        crop,
+        trim,
+        downscale,
+        downscale_kernel,
+        detelecine,
+    )?;
+    if let Some(resize) = resize.filter(|s| !s.is_empty()) {
+        clip = resize_resolution(core, &clip, resize)?;
+    }
+
+    let stem = vpy_file
+        .file_stem()
+        .ok_or_eyre("No file name")?
+        .to_str()
+        .ok_or_eyre("Invalid UTF-8 in vpy path")?;
+    let png_path = vpy_file.with_file_name(format!("{stem}_{frame}.png"));
+    save_frame_png(core, &clip, frame, &png_path)?;
+
+    Ok(png_path)
+}
+
 // Helper function to parse parameters
 pub fn parse_param<'a>(params: &'a str, name: &str) -> Option<&'a str> {
     params
@@ -306,6 +452,58 @@ impl FromStr for CropParams {
     }
 }
 
+/// Common color metadata, expanded to svt-av1 color flags
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ColorPreset {
+    /// HD, bt709
+    Hd,
+    /// SD NTSC (DVD), smpte170m
+    Sd,
+    /// SD PAL (DVD), bt470bg primaries and matrix with smpte170m transfer
+    SdPal,
+}
+
+impl ColorPreset {
+    pub const FLAGS: [&str; 5] = [
+        "--color-primaries",
+        "--transfer-characteristics",
+        "--matrix-coefficients",
+        "--color-range",
+        "--chroma-sample-position",
+    ];
+
+    pub fn params(self) -> &'static str {
+        match self {
+            ColorPreset::Hd => {
+                "--color-primaries bt709 --transfer-characteristics bt709 --matrix-coefficients bt709 --color-range studio --chroma-sample-position left"
+            }
+            ColorPreset::Sd => {
+                "--color-primaries smpte170m --transfer-characteristics smpte170m --matrix-coefficients smpte170m --color-range studio --chroma-sample-position left"
+            }
+            ColorPreset::SdPal => {
+                "--color-primaries bt470bg --transfer-characteristics smpte170m --matrix-coefficients bt470bg --color-range studio --chroma-sample-position left"
+            }
+        }
+    }
+
+    /// Replaces the color flags of `params` with the ones of the preset
+    pub fn apply(self, params: &str) -> String {
+        let mut tokens = params.split_whitespace();
+        let mut updated_tokens: Vec<&str> = Vec::new();
+
+        while let Some(token) = tokens.next() {
+            if Self::FLAGS.contains(&token) {
+                tokens.next(); // Skip old value
+            } else {
+                updated_tokens.push(token);
+            }
+        }
+        updated_tokens.extend(self.params().split_whitespace());
+
+        updated_tokens.join(" ")
+    }
+}
+
 #[derive(Debug)]
 pub struct ColorMetadata {
     pub matrix: u8,
@@ -328,40 +526,87 @@ impl Default for ColorMetadata {
 }
 
 impl ColorMetadata {
+    /// Maps the color flags of the encoder params to their H.273 / VapourSynth values.
+    /// Both svt-av1 and x265/ffmpeg names are recognized, in any case, as well as raw H.273 numbers.
+    /// Unknown values keep the bt709 defaults.
     pub fn from_params(params: &str) -> Self {
         let mut metadata = Self::default();
 
         if let Some(value) = parse_param(params, "--matrix-coefficients") {
-            metadata.matrix = match value {
-                "bt2020-ncl" => 9,
-                _ => metadata.matrix,
+            metadata.matrix = match value.to_ascii_lowercase().as_str() {
+                "identity" | "gbr" | "rgb" => 0,
+                "bt709" => 1,
+                "fcc" => 4,
+                "bt470bg" => 5,
+                "bt601" | "smpte170m" => 6,
+                "smpte240" | "smpte240m" => 7,
+                "ycgco" => 8,
+                "bt2020-ncl" | "bt2020nc" => 9,
+                "bt2020-cl" | "bt2020c" => 10,
+                "smpte2085" => 11,
+                "chroma-ncl" | "chroma-derived-nc" => 12,
+                "chroma-cl" | "chroma-derived-c" => 13,
+                "ictcp" => 14,
+                _ => value.parse().unwrap_or(metadata.matrix),
             };
         }
 
         if let Some(value) = parse_param(params, "--transfer-characteristics") {
-            metadata.transfer = match value {
+            metadata.transfer = match value.to_ascii_lowercase().as_str() {
+                "bt709" => 1,
+                "bt470m" => 4,
+                "bt470bg" => 5,
+                "bt601" | "smpte170m" => 6,
+                "smpte240" | "smpte240m" => 7,
+                "linear" => 8,
+                "log100" => 9,
+                "log100-sqrt10" | "log316" => 10,
+                "iec61966" | "iec61966-2-4" => 11,
+                "bt1361" | "bt1361e" => 12,
+                "srgb" | "iec61966-2-1" => 13,
+                "bt2020-10" => 14,
+                "bt2020-12" => 15,
                 "smpte2084" => 16,
-                _ => metadata.transfer,
+                "smpte428" => 17,
+                "hlg" | "arib-std-b67" => 18,
+                _ => value.parse().unwrap_or(metadata.transfer),
             };
         }
 
         if let Some(value) = parse_param(params, "--color-primaries") {
-            metadata.primaries = match value {
+            metadata.primaries = match value.to_ascii_lowercase().as_str() {
+                "bt709" => 1,
+                "bt470m" => 4,
+                "bt470bg" => 5,
+                "bt601" | "smpte170m" => 6,
+                "smpte240" | "smpte240m" => 7,
+                "film" => 8,
                 "bt2020" => 9,
-                _ => metadata.primaries,
+                "xyz" | "smpte428" => 10,
+                "smpte431" => 11,
+                "smpte432" => 12,
+                "ebu3213" => 22,
+                _ => value.parse().unwrap_or(metadata.primaries),
             };
         }
 
         if let Some(value) = parse_param(params, "--color-range") {
-            metadata.range = match value {
-                "full" => 1,
-                _ => metadata.range,
+            metadata.range = match value.to_ascii_lowercase().as_str() {
+                "studio" | "limited" | "tv" => 0,
+                "full" | "pc" => 1,
+                _ => value.parse().unwrap_or(metadata.range),
             };
         }
 
         if let Some(value) = parse_param(params, "--chroma-sample-position") {
-            metadata.chromaloc = match value {
-                "topleft" => 2,
+            metadata.chromaloc = match value.to_ascii_lowercase().as_str() {
+                "left" | "vertical" => 0,
+                "center" => 1,
+                "topleft" | "colocated" => 2,
+                "top" => 3,
+                "bottomleft" => 4,
+                "bottom" => 5,
+                // Encoder numbering differs from VapourSynth's here, so no raw numbers
                 _ => metadata.chromaloc,
             };
         }
@@ -369,3 +614,171 @@ impl ColorMetadata {
         metadata
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(flag: &str, field: fn(&ColorMetadata) -> u8, table: &[(&str, u8)]) {
+        for &(value, expected) in table {
+            let metadata =
+                ColorMetadata::from_params(&format!("--preset 4 {flag} {value} --crf 30"));
+            assert_eq!(field(&metadata), expected, "{flag} {value}");
+        }
+    }
+
+    #[test]
+    fn color_metadata_matrix() {
+        check(
+            "--matrix-coefficients",
+            |m| m.matrix,
+            &[
+                ("identity", 0),
+                ("gbr", 0),
+                ("rgb", 0),
+                ("bt709", 1),
+                ("fcc", 4),
+                ("bt470bg", 5),
+                ("bt601", 6),
+                ("smpte170m", 6),
+                ("smpte240", 7),
+                ("smpte240m", 7),
+                ("ycgco", 8),
+                ("bt2020-ncl", 9),
+                ("bt2020nc", 9),
+                ("bt2020-cl", 10),
+                ("bt2020c", 10),
+                ("smpte2085", 11),
+                ("chroma-ncl", 12),
+                ("chroma-derived-nc", 12),
+                ("chroma-cl", 13),
+                ("chroma-derived-c", 13),
+                ("ictcp", 14),
+                ("9", 9),
+                ("BT2020-NCL", 9),
+                ("Smpte170M", 6),
+                ("unknown", 1),
+                ("300", 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn color_metadata_transfer() {
+        check(
+            "--transfer-characteristics",
+            |m| m.transfer,
+            &[
+                ("bt709", 1),
+                ("bt470m", 4),
+                ("bt470bg", 5),
+                ("bt601", 6),
+                ("smpte170m", 6),
+                ("smpte240", 7),
+                ("smpte240m", 7),
+                ("linear", 8),
+                ("log100", 9),
+                ("log100-sqrt10", 10),
+                ("log316", 10),
+                ("iec61966", 11),
+                ("iec61966-2-4", 11),
+                ("bt1361", 12),
+                ("bt1361e", 12),
+                ("srgb", 13),
+                ("iec61966-2-1", 13),
+                ("bt2020-10", 14),
+                ("bt2020-12", 15),
+                ("smpte2084", 16),
+                ("smpte428", 17),
+                ("hlg", 18),
+                ("arib-std-b67", 18),
+                ("16", 16),
+                ("SMPTE2084", 16),
+                ("Arib-Std-B67", 18),
+                ("pq", 1),
+                ("-1", 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn color_metadata_primaries() {
+        check(
+            "--color-primaries",
+            |m| m.primaries,
+            &[
+                ("bt709", 1),
+                ("bt470m", 4),
+                ("bt470bg", 5),
+                ("bt601", 6),
+                ("smpte170m", 6),
+                ("smpte240", 7),
+                ("smpte240m", 7),
+                ("film", 8),
+                ("bt2020", 9),
+                ("xyz", 10),
+                ("smpte428", 10),
+                ("smpte431", 11),
+                ("smpte432", 12),
+                ("ebu3213", 22),
+                ("9", 9),
+                ("BT2020", 9),
+                ("EBU3213", 22),
+                ("p3", 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn color_metadata_range_and_chromaloc() {
+        check(
+            "--color-range",
+            |m| m.range,
+            &[
+                ("studio", 0),
+                ("limited", 0),
+                ("tv", 0),
+                ("full", 1),
+                ("pc", 1),
+                ("1", 1),
+                ("Full", 1),
+                ("PC", 1),
+                ("wide", 0),
+            ],
+        );
+        check(
+            "--chroma-sample-position",
+            |m| m.chromaloc,
+            &[
+                ("left", 0),
+                ("vertical", 0),
+                ("center", 1),
+                ("topleft", 2),
+                ("colocated", 2),
+                ("top", 3),
+                ("bottomleft", 4),
+                ("bottom", 5),
+                ("TopLeft", 2),
+                ("Center", 1),
+                // Raw numbers are ambiguous between encoders
+                ("2", 0),
+                ("unknown", 0),
+            ],
+        );
+    }
+
+    #[test]
+    fn color_metadata_defaults() {
+        let metadata = ColorMetadata::from_params("--preset 4 --crf 30");
+        assert_eq!(
+            (
+                metadata.matrix,
+                metadata.transfer,
+                metadata.primaries,
+                metadata.range,
+                metadata.chromaloc
+            ),
+            (1, 1, 1, 0, 0)
+        );
+    }
+}
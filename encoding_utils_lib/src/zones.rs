@@ -2,7 +2,7 @@ use std::{fs, path::Path};
 
 use eyre::Result;
 
-use crate::scenes::SceneList;
+use crate::{scenes::SceneList, vapoursynth::write_atomic};
 
 pub fn create_zone_file<'a>(
     zone_file: &'a Path,
@@ -29,7 +29,7 @@ pub fn create_zone_file<'a>(
         .collect::<Vec<_>>()
         .join("\n");
 
-    fs::write(zone_file, content)?;
+    write_atomic(zone_file, content)?;
 
     println!("Zone file created\n");
     Ok(zone_file)
@@ -63,7 +63,7 @@ pub fn create_temp_zone_file<'a>(
         .collect::<Vec<_>>()
         .join("\n");
 
-    fs::write(zone_file, content)?;
+    write_atomic(zone_file, content)?;
 
     println!("Zone file created\n");
     Ok(zone_file)
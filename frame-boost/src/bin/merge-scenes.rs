@@ -0,0 +1,33 @@
+use clap::Parser;
+use encoding_utils_lib::scenes::SceneList;
+use eyre::Result;
+use std::path::PathBuf;
+
+/// Merge boosted scene files covering consecutive frame ranges into one scene file
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Scene files in frame order, each starting where the previous one ends
+    #[arg(required = true, num_args = 2..)]
+    inputs: Vec<PathBuf>,
+
+    /// Merged scene file
+    #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
+    output: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mut merged = SceneList::parse_scene_file(&args.inputs[0])?;
+    for input in &args.inputs[1..] {
+        let scene_list = SceneList::parse_scene_file(input)?;
+        merged.concat(scene_list).map_err(|e| eyre::eyre!("{}: {e}", input.display()))?;
+    }
+
+    merged.write_scene_list_to_file(&args.output)?;
+    println!("Scenes: {}", merged.split_scenes.len());
+    println!("Total frames: {}", merged.frames);
+    println!("Scene file: {}", args.output.display());
+    Ok(())
+}
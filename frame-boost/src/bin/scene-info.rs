@@ -0,0 +1,34 @@
+use clap::Parser;
+use encoding_utils_lib::scenes::SceneList;
+use eyre::{OptionExt, Result};
+use std::path::PathBuf;
+
+/// Print the stats of a scene file without running anything
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Scene file, e.g. a [BOOST]_*.json
+    #[arg(value_parser = clap::value_parser!(PathBuf))]
+    input: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mut scene_list = SceneList::parse_scene_file(&args.input)?;
+    scene_list.sync_crf_from_zone_overrides()?;
+
+    let lengths = scene_list
+        .split_scenes
+        .iter()
+        .map(|scene| scene.end_frame - scene.start_frame);
+    let min_len = lengths.clone().min().ok_or_eyre("Scene file has no scenes")?;
+    let max_len = lengths.max().ok_or_eyre("Scene file has no scenes")?;
+
+    println!("Scene file: {}", args.input.display());
+    println!("Scenes: {}", scene_list.split_scenes.len());
+    println!("Total frames: {}", scene_list.frames);
+    println!("Scene length: {min_len} min, {max_len} max");
+    scene_list.print_crf_percentages();
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use clap::{ArgAction, Parser};
 use eyre::{OptionExt, Result};
-use encoding_utils_lib::{crf::crf_parser, frame_loop::run_frame_loop, scenes::{FramesDistribution, SceneDetectionMethod}, vapoursynth::SourcePlugin};
+use encoding_utils_lib::{binaries::{set_binary_paths, set_command_limits, BinaryPaths, CommandLimits}, chapters::{parse_target_percentile, TargetPercentile}, crf::{crf_parser, parse_crf_search, CrfOrder}, frame_loop::{check_chunk_method, get_encoder, parse_frame_scores_arg, read_frame_scores, run_frame_loop, verify_encode}, scenes::{AggregationMode, CrfSmoothing, FramesDistribution, SceneDetectionMethod, SceneList, ScoreTarget}, vapoursynth::{detect_crop, DownscaleKernel, Ivtc, parse_downscale, parse_trim, ResizeKernel, set_content_hash_index, SourcePlugin, AUTO_CROP_SAMPLES}, vpy_files::{create_vpy_file, parse_prefilter, parse_vspipe_arg, preview_vpy_frame, ColorPreset}};
+use vapoursynth4_rs::core::Core;
 
-use std::{fs, path::{absolute, PathBuf}};
+use std::{collections::BTreeSet, fs, io::ErrorKind, path::{absolute, Path, PathBuf}, time::Duration};
 
 /// Scene-based boost that dynamically adjusts CRF.
 /// It creates a scene-file with zone overrides
@@ -17,6 +18,10 @@ struct Args {
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     output: Option<PathBuf>,
 
+    /// Also write the boosted scenes as an av1an --zones file, for a plain av1an run
+    #[arg(long = "zones-file", value_parser = clap::value_parser!(PathBuf))]
+    zones_file: Option<PathBuf>,
+
     /// Temp folder (default: "[Temp]_<input>" if no temp folder given)
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     temp: Option<PathBuf>,
@@ -42,6 +47,10 @@ struct Args {
     )]
     zoning_params: String,
 
+    /// Color metadata preset, replaces the color flags of --encoder-params and --zoning-params
+    #[arg(long = "color-preset", value_enum)]
+    color_preset: Option<ColorPreset>,
+
     /// Target SSIMULACRA2 score (0-100)
     #[arg(short = 'q', long, default_value_t = 70.0)]
     target_quality: f64,
@@ -51,20 +60,77 @@ struct Args {
     min_target_quality: f64,
 
     /// Percentile (0-100). 20 means that 80 percent of all values in a scene will be above target-quality when selecting a crf value.
-    #[arg(short = 'p', long, default_value_t = 50)]
-    target_percentile: u8,
+    /// Chapters can use their own with {Chapter}:{PERCENTILE} (needs --chapters), e.g. 20,Ending:5
+    #[arg(short = 'p', long, default_value = "50", value_parser = parse_target_percentile)]
+    target_percentile: TargetPercentile,
+
+    /// How the scores of a scene are compared with the targets when selecting a crf value
+    #[arg(value_enum, long, default_value_t = AggregationMode::PercentileMin)]
+    aggregation: AggregationMode,
+
+    /// Also require a scene mean SSIMULACRA2 score of at least this value (0-100)
+    #[arg(long = "target-mean")]
+    target_mean: Option<f64>,
+
+    /// Also require a 5th percentile SSIMULACRA2 score of at least this value (0-100), so a scene
+    /// can't pass on average with an ugly worst frame
+    #[arg(long = "target-p5")]
+    target_p5: Option<f64>,
+
+    /// Max CRF difference between consecutive scenes. The higher CRF of a pair is lowered, so the
+    /// scenes never end below their target
+    #[arg(long = "max-crf-step")]
+    max_crf_step: Option<f64>,
+
+    /// Smooth the scene CRFs with a centered moving average over this many scenes, rounded to the
+    /// nearest CRF of the ladder. A scene never gets a higher CRF than the one it reached
+    #[arg(long = "crf-smooth", value_parser = clap::value_parser!(u32).range(2..))]
+    crf_smooth: Option<u32>,
+
+    /// Interpolate the CRF of each scene between the last failing and the first passing CRF of the
+    /// ladder (linear fit of the scores), in quarter steps, or whole steps for aomenc
+    #[arg(long = "interpolate-crf", action = ArgAction::SetTrue, default_value_t = false)]
+    interpolate_crf: bool,
+
+    /// Estimate the final encode size with a calibration encode of the measured frames at the final
+    /// CRFs, extrapolated to the full scene lengths. Helps deciding whether to run size-dampener
+    #[arg(long = "estimate-size", action = ArgAction::SetTrue, default_value_t = false)]
+    estimate_size: bool,
+
+    /// Stop the boost loop after this many CRF cycles, the scenes still under target get the next
+    /// CRF of the ladder without measuring it
+    #[arg(long = "max-cycles", value_parser = clap::value_parser!(u32).range(1..))]
+    max_cycles: Option<u32>,
+
+    /// Quick preview: only measure every Nth scene, the others get the CRF of the nearest
+    /// measured scene. The resulting CRFs are approximate
+    #[arg(long = "scene-stride", value_parser = clap::value_parser!(u32).range(1..))]
+    scene_stride: Option<u32>,
+
+    /// Average used by --crf-smooth
+    #[arg(value_enum, long = "crf-smooth-mode", default_value_t = CrfSmoothing::Mean)]
+    crf_smooth_mode: CrfSmoothing,
 
     /// Target CRF value(s) (1.0-70.0). Can be:
     /// - Single value (35 or 35.5)
     /// - Comma-separated list (35,27.2,21)
     /// - Backward range (36..21 or 36.0..21.0)
     /// - Stepped backward range (36..21:1.5 or 36.0..21.0:1.5)
+    /// - Logarithmic backward range (36..21:log:6), values evenly spaced by ratio
+    /// - File with one of the above per line (@ladder.txt)
+    ///
+    /// A single value is a fixed CRF for every scene, without boosting. Use --crf-search to search down from one
     #[arg(
         short = 'c',
         long,
         default_value = "32,28,24",
     )]
     crf: String,
+
+    /// Start at a CRF and lower it one step at a time for the scenes under target, down to a floor:
+    /// X:floor or X:floor:step (e.g. 35:28 = 35,34,...,28). Replaces --crf
+    #[arg(long = "crf-search", conflicts_with = "crf")]
+    crf_search: Option<String>,
     /// Number of frames to encode for scene. Higher value increase the confidence than all the frames in the scene will be above your quality target at cost of encoding time
     #[arg(short = 'n', long = "n-frames", value_parser = clap::value_parser!(u32).range(1..))]
     n_frames: Option<u32>,
@@ -73,11 +139,12 @@ struct Args {
     #[arg(short = 's', long = "s-frames", default_value_t = 0.5)]
     s_frames: f64,
 
-    /// XML Chapters file. Used for zoning.
+    /// XML or OGM text chapters file. Used for zoning.
     #[arg(long, value_parser = clap::value_parser!(PathBuf))]
     chapters: Option<PathBuf>,
 
-    /// Zoning by chapters. {Chapter}:{CRF} (e.g. Opening:21,Ending:35,Episode:24)
+    /// Zoning by chapters. {Chapter}:{CRF} for a fixed CRF, or {Chapter}:tq={TARGET} for a chapter target quality
+    /// that is still boosted (e.g. Opening:21,Ending:35,Episode:tq=75)
     #[arg(short = 'z', long = "chapters-zoning", default_value = "")]
    chapters_zoning: String,
 
@@ -97,7 +164,12 @@ struct Args {
     #[arg(value_enum, short = 'd', long = "frames-distribution", default_value_t = FramesDistribution::Evenly)]
     frames_distribution: FramesDistribution,
 
-    /// Velocity tuning preset (-1~13)
+    /// Seed of the random frames distribution
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Velocity tuning preset (-1~13), SVT-AV1 numbering. With --encoder x264/x265 it maps to the
+    /// named presets over the same range (-1 placebo ... 13 ultrafast)
     #[arg(short = 'v', long, default_value_t = 8, value_parser = clap::value_parser!(i32).range(-1..=13))]
     velocity_preset: i32,
 
@@ -105,7 +177,12 @@ struct Args {
     #[arg(value_enum, short = 'd', long = "scene-detection-method", default_value_t = SceneDetectionMethod::TransnetV2)]
     scene_detection_method: SceneDetectionMethod,
 
-    /// Keep temporary files (disables automatic cleanup)
+    /// Use this scene file (av1an JSON format) instead of detecting scenes
+    #[arg(long = "scene-file-input", value_parser = clap::value_parser!(PathBuf))]
+    scene_file_input: Option<PathBuf>,
+
+    /// Keep temporary files (disables automatic cleanup), including the encode_<crf>.mkv,
+    /// vpy and scenes files of every CRF cycle
     #[arg(
         short = 'k', 
         long = "keep-files",
@@ -114,6 +191,38 @@ struct Args {
     )]
     keep_files: bool,
 
+    /// Continue a previous run from its temp folder: reuse its scenes.json, scenes_<crf>.json,
+    /// encode_<crf>.mkv and metrics cache, only computing what is missing. Implies --keep-files
+    #[arg(long = "resume", visible_alias = "no-reencode", action = ArgAction::SetTrue, default_value_t = false)]
+    resume: bool,
+
+    /// av1an binary to run, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "av1an-path", default_value = "av1an", value_parser = clap::value_parser!(PathBuf))]
+    av1an_path: PathBuf,
+
+    /// ffprobe binary used by --seek-safe, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffprobe-path", default_value = "ffprobe", value_parser = clap::value_parser!(PathBuf))]
+    ffprobe_path: PathBuf,
+
+    /// ffmsindex binary used to index inputs for ffms2, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffmsindex-path", default_value = "ffmsindex", value_parser = clap::value_parser!(PathBuf))]
+    ffmsindex_path: PathBuf,
+
+    /// Kill an external command (av1an, vspipe, ffmpeg) and the processes it started once it has
+    /// run this many seconds in total, and run it again (av1an resumes its encode). This limits
+    /// the whole command, not a single chunk, so leave room for the longest healthy encode
+    #[arg(long = "command-timeout")]
+    command_timeout: Option<u64>,
+
+    /// Times a timed out command is run again before failing
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
+    /// Name source indexes by a hash of the file content instead of only the file name,
+    /// so overwriting an input with the same name doesn't reuse a stale index
+    #[arg(long = "hash-index", action = ArgAction::SetTrue, default_value_t = false)]
+    hash_index: bool,
+
     /// Disable overwrite protection (remove the scene file)
     #[arg(
         short = 'f', 
@@ -139,22 +248,43 @@ struct Args {
     #[arg(short, long = "crf-data-file")]
     crf_data_file: Option<PathBuf>,
 
-    /// Crop string (e.g. 1920:816:0:132)
+    /// Path to save the CRF of every scene as CSV, with its frame count and mean, 5th percentile
+    /// and min score (scene,crf,start,end,frames,mean,p5,min)
+    #[arg(long = "crf-data-csv")]
+    crf_data_csv: Option<PathBuf>,
+
+    /// Path to save the CRF-vs-quality curve of every scene (percentile and min score at each
+    /// measured CRF). Defaults to "[CURVES]_<input>.json" when --crf-data-file is given
+    #[arg(long = "curves-file")]
+    curves_file: Option<PathBuf>,
+
+    /// Crop string (e.g. 1920:816:0:132), or "auto" to detect black bars
     #[arg(short, long)]
     crop: Option<String>,
 
-    /// Downscale, using Box Kernel
+    /// Downscale factor in (0, 1] (e.g. 0.5, 0.333), using --downscale-kernel (Box by default).
+    /// "true" means 0.5
     #[arg(
         long, 
-        default_value_t = 1.0
+        default_value_t = 1.0,
+        value_parser = parse_downscale
     )]
     downscale: f64,
 
+    /// Kernel used by --downscale
+    #[arg(value_enum, long = "downscale-kernel", default_value_t = ResizeKernel::Box)]
+    downscale_kernel: ResizeKernel,
+
+    /// Taps of the --downscale-kernel (lanczos, blackman, spline). Kernel default if not set
+    #[arg(long = "downscale-taps", value_parser = clap::value_parser!(i64).range(1..=128))]
+    downscale_taps: Option<i64>,
+
     /// Resize, using Hermite Kernel. Format WIDTHxHEIGHT. Example: 1920x1080. 
     #[arg(long)]
     resize: Option<String>,
 
-    /// Trim source file. Format Start:End. Examples: 1261:5623, 0:2432, 2352:-1. 
+    /// Trim source file. Format Start:End, End included. Negative values count from the end and an empty side is open.
+    /// Examples: 1261:5623, 0:2432, 2352:-1, 2352:, :-25
     #[arg(short, long)]
     trim: Option<String>,
 
@@ -167,6 +297,57 @@ struct Args {
     )]
     detelecine: bool,
 
+    /// Field order used by --detelecine: 1 = top field first, 0 = bottom field first
+    #[arg(long = "field-order", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=1))]
+    field_order: i64,
+
+    /// VFM matching mode used by --detelecine (0-5)
+    #[arg(long = "ivtc-mode", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=5))]
+    ivtc_mode: i64,
+
+    /// VDecimate cycle used by --detelecine: one frame is dropped every N frames
+    #[arg(long = "decimate-cycle", default_value_t = 5, value_parser = clap::value_parser!(i64).range(2..=25))]
+    decimate_cycle: i64,
+
+    /// VDecimate duplicate threshold used by --detelecine (percent difference)
+    #[arg(long = "decimate-dupthresh", default_value_t = 1.1)]
+    decimate_dupthresh: f64,
+
+    /// Argument passed to the generated scripts with vspipe -a (av1an --vspipe-args), as key=value.
+    /// The scripts read it as a global named key. Can be repeated
+    #[arg(long = "vspipe-arg", value_parser = parse_vspipe_arg)]
+    vspipe_args: Vec<String>,
+
+    /// Python line inserted verbatim in the encode scripts after the crop, e.g. a denoiser:
+    /// "src = core.bilateral.Bilateral(src, sigmaS=1.5)". It has to assign src from src
+    #[arg(long, value_parser = parse_prefilter)]
+    prefilter: Option<String>,
+
+    /// Photon noise ISO of the grain table written next to the output scene file. The scenes point
+    /// the encoder at it (--fgs-table or --film-grain-table) instead of av1an's --photon-noise
+    #[arg(long = "photon-noise", value_parser = clap::value_parser!(u32).range(1..))]
+    photon_noise: Option<u32>,
+
+    /// Scale the --photon-noise ISO per scene with the measured grain of the source (median scene =
+    /// the given ISO), set as av1an photon noise in the scene zones instead of a grain table
+    #[arg(long = "adaptive-grain", requires = "photon_noise", action = ArgAction::SetTrue, default_value_t = false)]
+    adaptive_grain: bool,
+
+    /// Precomputed frame scores of a CRF of the ladder as CRF=file, a frame,score CSV (as written by
+    /// simple-ssimu2 --save-csv) of the source frame numbers, "-" reads it from stdin. That CRF is neither
+    /// encoded nor measured. Can be repeated
+    #[arg(long = "frame-scores", value_parser = parse_frame_scores_arg)]
+    frame_scores: Vec<(f64, PathBuf)>,
+
+    /// Move every scene start back to the nearest keyframe of the input (read with ffprobe), for
+    /// sources whose frame numbers don't line up with the container keyframes
+    #[arg(long = "seek-safe", action = ArgAction::SetTrue, default_value_t = false)]
+    seek_safe: bool,
+
+    /// Measure SSIMU2 on the luma plane only: the chroma of both clips is replaced with neutral gray
+    #[arg(long = "luma-only", action = ArgAction::SetTrue, default_value_t = false)]
+    luma_only: bool,
+
     // Enable verbose output
     #[arg(long, action = ArgAction::SetTrue, default_value_t = false)]
     verbose: bool,
@@ -187,6 +368,33 @@ struct Args {
     )]
     filter_frames: bool,
 
+    /// Merge adjacent scenes that ended on the same CRF into a single zone
+    #[arg(
+        long = "merge-scenes",
+        action = ArgAction::SetTrue,
+        default_value_t = false,
+    )]
+    merge_scenes: bool,
+
+    /// Run scene detection and frame selection, print the plan and exit before encoding.
+    /// The detected scenes are kept in "<temp>/scenes/scenes.json"
+    #[arg(
+        long = "dry-run",
+        action = ArgAction::SetTrue,
+        default_value_t = false,
+    )]
+    dry_run: bool,
+
+    /// Write "[PREVIEW]_<input>.vpy" and render frame N of it through the crop, trim,
+    /// downscale and resize settings to "[PREVIEW]_<input>_N.png", then exit
+    #[arg(long = "preview-frame")]
+    preview_frame: Option<u32>,
+
+    /// Skip the boost and measure every frame of this final encode against the scene file (--output),
+    /// reporting the scenes below --min-target-quality. Exits with an error if any scene missed it
+    #[arg(long, value_parser = clap::value_parser!(PathBuf))]
+    verify: Option<PathBuf>,
+
     /// Path to custom ONNX model (default: uses embedded TransNetV2 model)
     #[arg(long, value_parser = clap::value_parser!(PathBuf))]
     model: Option<PathBuf>,
@@ -234,6 +442,11 @@ struct Args {
     #[arg(long = "fade-threshold", default_value_t = 0.05)]
     fade_threshold: f32,
 
+    /// Hysteresis threshold for fade detection: a fade is only kept if it reaches this value,
+    /// and lasts while above `--fade-threshold` (default: same as `--fade-threshold`)
+    #[arg(long = "fade-threshold-high")]
+    fade_threshold_high: Option<f32>,
+
     /// Minimum fade length in frames
     #[arg(long = "min-fade-len", default_value_t = 5)]
     min_fade_len: u32,
@@ -265,10 +478,24 @@ struct Args {
     /// Num of threads Rayon uses. Matters when calculating metrics
     #[arg(long, default_value_t = 0)]
     threads: u32,
+
+    /// Threads used to calculate metrics, in their own pool so av1an workers keep priority (0 = all cores)
+    #[arg(long = "metric-threads", default_value_t = 0)]
+    metric_threads: usize,
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(preset) = args.color_preset {
+        args.encoder_params = preset.apply(&args.encoder_params);
+        args.zoning_params = preset.apply(&args.zoning_params);
+    }
+    set_content_hash_index(args.hash_index);
+    set_binary_paths(BinaryPaths { av1an: args.av1an_path.clone(), ffprobe: args.ffprobe_path.clone(), ffmsindex: args.ffmsindex_path.clone(), ..BinaryPaths::default() })?;
+    set_command_limits(CommandLimits { timeout: args.command_timeout.map(Duration::from_secs), retries: args.retries })?;
+    if let Some(trim) = &args.trim {
+        parse_trim(trim)?;
+    }
 
     // Configure global pool at startup
     rayon::ThreadPoolBuilder::new()
@@ -276,7 +503,11 @@ fn main() -> Result<()> {
         .build_global()
         .expect("Failed to initialize global thread pool");
 
-    let crf_values = crf_parser(&args.crf)?;
+    let crf_values = match &args.crf_search {
+        Some(search) => parse_crf_search(search)?,
+        None => crf_parser(&args.crf, CrfOrder::Descending)?,
+    };
+    check_chunk_method(&args.av1an_params)?;
     let input_path = absolute(&args.input)?;
     let scene_boosted = match args.output {
         Some(output) => output, 
@@ -293,7 +524,7 @@ fn main() -> Result<()> {
         }
     };
 
-    if scene_boosted.exists() {
+    if scene_boosted.exists() && args.preview_frame.is_none() && args.verify.is_none() {
         if args.force {
             fs::remove_file(&scene_boosted)?;
             println!("\nRemoved existing scene file: {}", scene_boosted.display());
@@ -319,8 +550,62 @@ fn main() -> Result<()> {
         }
     };
 
+    if args.resume && temp_folder.exists() {
+        println!("\nResuming from {}", temp_folder.display());
+    }
+    let temp_snapshot = TempSnapshot::take(&temp_folder)?;
     fs::create_dir_all(&temp_folder)?;
 
+    let crop = match args.crop.as_deref() {
+        Some("auto") => {
+            let indexes_folder = temp_folder.join("indexes");
+            fs::create_dir_all(&indexes_folder)?;
+            let core = Core::builder().build();
+            let crop = detect_crop(&core, &input_path, &args.source_scene_plugin, &indexes_folder, AUTO_CROP_SAMPLES)?.to_string();
+            println!("\nDetected crop: {crop}");
+            Some(crop)
+        }
+        _ => args.crop.clone(),
+    };
+
+    if let Some(frame) = args.preview_frame {
+        let indexes_folder = temp_folder.join("indexes");
+        fs::create_dir_all(&indexes_folder)?;
+        let core = Core::builder().build();
+        let detelecine = args.detelecine.then_some(Ivtc { field_order: args.field_order, vfm_mode: args.ivtc_mode, decimate_cycle: args.decimate_cycle, decimate_dupthresh: args.decimate_dupthresh });
+        let downscale_kernel = DownscaleKernel { kernel: args.downscale_kernel, taps: args.downscale_taps };
+        let vpy_path = input_path.with_file_name(format!(
+            "[PREVIEW]_{}.vpy",
+            input_path
+                .file_stem()
+                .ok_or_eyre("No file name")?
+                .to_str()
+                .ok_or_eyre("Invalid UTF-8 in input path")?
+        ));
+        create_vpy_file(&input_path, &vpy_path, None, &args.source_encoding_plugin, crop.as_deref(), args.downscale, downscale_kernel, args.resize.as_deref(), args.trim.as_deref(), detelecine, &args.encoder_params, &indexes_folder, true, &args.vspipe_args, args.prefilter.as_deref())?;
+        let png_path = preview_vpy_frame(&core, &input_path, &vpy_path, frame, &args.source_encoding_plugin, crop.as_deref(), args.downscale, downscale_kernel, args.resize.as_deref(), args.trim.as_deref(), detelecine, &args.encoder_params, &indexes_folder)?;
+        println!("\nPreview: {}\nScript: {}", png_path.display(), vpy_path.display());
+
+        if !(args.keep_files || args.resume) {
+            temp_snapshot.remove_new_entries(&temp_folder)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(encode) = &args.verify {
+        let detelecine = args.detelecine.then_some(Ivtc { field_order: args.field_order, vfm_mode: args.ivtc_mode, decimate_cycle: args.decimate_cycle, decimate_dupthresh: args.decimate_dupthresh });
+        let downscale_kernel = DownscaleKernel { kernel: args.downscale_kernel, taps: args.downscale_taps };
+        let missed = verify_encode(&input_path, encode, &scene_boosted, &args.source_metric_plugin, &temp_folder, args.verbose_verbose_verbose, &args.encoder_params, crop.as_deref(), args.downscale, downscale_kernel, args.resize.as_deref(), args.trim.as_deref(), detelecine, args.luma_only, args.min_target_quality, args.target_percentile.default, args.chapters.as_deref(), &args.target_percentile.chapters, args.overlap_chapters, args.metric_threads)?;
+
+        if !(args.keep_files || args.resume) {
+            temp_snapshot.remove_new_entries(&temp_folder)?;
+        }
+        if missed > 0 {
+            eyre::bail!("{missed} scenes are below the minimum target quality");
+        }
+        return Ok(());
+    }
+
     run_frame_loop(
         &input_path,
         &scene_boosted,
@@ -333,23 +618,32 @@ fn main() -> Result<()> {
         args.n_frames,
         args.s_frames,
         args.frames_distribution,
+        args.seed,
         args.scene_detection_method,
+        args.scene_file_input.as_deref(),
         args.filter_frames,
+        args.merge_scenes,
+        args.dry_run,
         args.chapters.as_deref(),
         args.chapters_zoning,
         &args.zoning_params,
         args.overlap_chapters,
         args.workers,
+        args.metric_threads,
         &args.source_metric_plugin,
         &args.source_encoding_plugin,
         &args.source_scene_plugin,
         args.crf_data_file.as_deref(),
-        args.crop.as_deref(),
+        args.curves_file.as_deref(),
+        args.crf_data_csv.as_deref(),
+        crop.as_deref(),
         args.downscale,
+        DownscaleKernel { kernel: args.downscale_kernel, taps: args.downscale_taps },
         args.resize.as_deref(),
         args.trim.as_deref(),
-        args.detelecine,
-        !args.keep_files,
+        args.detelecine.then_some(Ivtc { field_order: args.field_order, vfm_mode: args.ivtc_mode, decimate_cycle: args.decimate_cycle, decimate_dupthresh: args.decimate_dupthresh }),
+        args.luma_only,
+        !(args.keep_files || args.resume),
         args.verbose,
         args.verbose_verbose,
         args.verbose_verbose_verbose,
@@ -362,16 +656,83 @@ fn main() -> Result<()> {
         args.min_scene_len.map(|x| x.into()),
         args.threshold,
           args.fade_threshold,
+        args.fade_threshold_high,
         args.min_fade_len.into(),
         args.merge_gap_between_fades.into(),
         args.enable_fade_detection,
         args.scene_predictions,
-        args.target_percentile,
+        args.target_percentile.default,
+        &args.target_percentile.chapters,
+        args.aggregation,
+        &args.target_mean.map(ScoreTarget::Mean).into_iter().chain(args.target_p5.map(|target| ScoreTarget::Percentile(5, target))).collect::<Vec<_>>(),
+        args.max_crf_step,
+        args.crf_smooth.map(|window| window as usize),
+        args.crf_smooth_mode,
+        args.interpolate_crf,
+        args.estimate_size,
+        args.max_cycles.map(|cycles| cycles as usize),
+        args.scene_stride.map(|stride| stride as usize),
         args.hardcut_scenes,
         args.cpu,
+        &args.vspipe_args,
+        args.prefilter.as_deref(),
+        args.photon_noise,
+        args.adaptive_grain,
+        &read_frame_scores(&args.frame_scores)?,
+        args.seek_safe,
     )?;
 
+    if let Some(zones_file) = args.zones_file.as_deref().filter(|_| !args.dry_run) {
+        SceneList::parse_scene_file(&scene_boosted)?.write_zones_file(zones_file, get_encoder(&args.av1an_params).as_deref())?;
+        println!("Zones file: {}", zones_file.display());
+    }
+
     Ok(())
 }
 
+/// Entries of the temp folder before a preview or verify step, so its cleanup only removes
+/// what the step created and leaves a previous run (or the folder given with --temp) alone
+struct TempSnapshot {
+    existed: bool,
+    entries: BTreeSet<PathBuf>,
+}
+
+impl TempSnapshot {
+    fn take(temp_folder: &Path) -> Result<Self> {
+        let existed = temp_folder.exists();
+        let entries = if existed { list_entries(temp_folder)? } else { BTreeSet::new() };
+        Ok(Self { existed, entries })
+    }
+
+    /// Removes the files and folders added to `temp_folder` since the snapshot
+    fn remove_new_entries(&self, temp_folder: &Path) -> Result<()> {
+        if !self.existed {
+            fs::remove_dir_all(temp_folder)?;
+            return Ok(());
+        }
+        // Parents sort before their children, which are gone with them
+        for entry in list_entries(temp_folder)?.difference(&self.entries) {
+            let removed = if entry.is_dir() { fs::remove_dir_all(entry) } else { fs::remove_file(entry) };
+            match removed {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every file and folder under `dir`
+fn list_entries(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut entries = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            entries.extend(list_entries(&path)?);
+        }
+        entries.insert(path);
+    }
+    Ok(entries)
+}
+
 
@@ -1,10 +1,16 @@
 use std::{
     fs::{self, create_dir_all, remove_dir_all},
     path::{Path, PathBuf, absolute},
-    process::{Command, Stdio},
+    process::Stdio,
 };
 
-use encoding_utils_lib::vapoursynth::{SourcePlugin, add_extension};
+use encoding_utils_lib::{
+    binaries::{
+        command_limits, ffmpeg_command, ffmsindex_command, isolate_process_group,
+        kill_process_tree, vspipe_command, wait_with_timeout,
+    },
+    vapoursynth::{SourcePlugin, add_extension, imwri_vpy_source, index_cache_name},
+};
 
 use eyre::{OptionExt, Result};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -28,20 +34,15 @@ pub fn create_crops_vpy_file<'a>(
         SourcePlugin::Lsmash => "core.lsmas.LWLibavSource",
         SourcePlugin::Bestsource => "core.bs.VideoSource",
         SourcePlugin::Ffms2 => "core.ffms2.Source",
+        SourcePlugin::Imwri => "core.imwri.Read",
     };
 
-    let mut cache_path = temp_folder.join(
-        input
-            .file_name()
-            .ok_or_eyre("Input path has no filename")?
-            .to_str()
-            .ok_or_eyre("Filename not UTF-8")?,
-    );
+    let mut cache_path = temp_folder.join(index_cache_name(&input)?);
 
     cache_path = match source_plugin {
         SourcePlugin::Lsmash => add_extension("lwi", cache_path),
         SourcePlugin::Ffms2 => add_extension("ffindex", cache_path),
-        SourcePlugin::Bestsource => cache_path,
+        SourcePlugin::Bestsource | SourcePlugin::Imwri => cache_path,
     };
 
     // Ensure the path is absolute
@@ -52,7 +53,7 @@ pub fn create_crops_vpy_file<'a>(
     if let SourcePlugin::Ffms2 = source_plugin
         && !cache_path.exists()
     {
-        let status = std::process::Command::new("ffmsindex")
+        let status = ffmsindex_command()
             .arg("-f")
             .arg("-p")
             .arg(&input)
@@ -73,6 +74,12 @@ pub fn create_crops_vpy_file<'a>(
         SourcePlugin::Lsmash => format!("cachefile=\"{cache_str}\""),
         SourcePlugin::Bestsource => format!("cachepath=\"{cache_str}\", cachemode=4"),
         SourcePlugin::Ffms2 => format!("cachefile=\"{cache_str}\""),
+        SourcePlugin::Imwri => String::new(),
+    };
+
+    let source_call = match source_plugin {
+        SourcePlugin::Imwri => imwri_vpy_source(&input)?,
+        _ => format!("{source}(\"{input_str}\", {cache})"),
     };
 
     let crop_str = format!(
@@ -86,7 +93,7 @@ pub fn create_crops_vpy_file<'a>(
 
 core = vs.core
 
-src = {source_plugin}("{input_str}", {cache})
+src = {source_call}
 
 frames = src[{start_frame}:{end_frame}]
 
@@ -96,11 +103,9 @@ cropped = core.std.Crop(yuv, {crop_str})
 
 cropped.set_output()
 "#,
-        source_plugin = source,
-        input_str = input_str,
+        source_call = source_call,
         start_frame = frame_range.start.unwrap(),
         end_frame = frame_range.end.unwrap(),
-        cache = cache,
         crop_str = crop_str
     );
 
@@ -162,47 +167,64 @@ pub fn extract_frames(
                      frames_folder,
                  }|
                  -> Result<()> {
-                    let mut vspipe = Command::new("vspipe")
-                        .arg(&vpy)
-                        .arg("-")
-                        .arg("-c")
-                        .arg("y4m")
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::inherit())
-                        .spawn()?;
-
-                    let mut ffmpeg = Command::new("ffmpeg")
-                        .arg("-loglevel")
-                        .arg("error")
-                        .arg("-i")
-                        .arg("-")
-                        .arg("-f")
-                        .arg("image2")
-                        .arg("-qscale:v")
-                        .arg("2")
-                        .arg("-start_number")
-                        .arg(frame_range.start.unwrap().to_string())
-                        .arg(&ffmpeg_pattern)
-                        .stdin(vspipe.stdout.take().unwrap())
-                        .stderr(Stdio::inherit())
-                        .stdout(Stdio::inherit())
-                        .spawn()?;
-
-                    let status_ffmpeg = ffmpeg.wait()?;
-                    let status_vspipe = vspipe.wait()?;
-
                     let output_name = frames_folder
                         .file_name()
                         .ok_or_eyre("Input path has no filename")?
                         .to_str()
                         .ok_or_eyre("Filename not UTF-8")?;
-
-                    if status_ffmpeg.success() && status_vspipe.success() {
-                        println!("{} - Frame extraction complete", &output_name)
-                    } else {
-                        eprintln!("{} - Extraction frames failed.", &output_name);
+                    let limits = command_limits();
+
+                    for attempt in 0..=limits.retries {
+                        let mut vspipe = isolate_process_group(&mut vspipe_command())
+                            .arg(&vpy)
+                            .arg("-")
+                            .arg("-c")
+                            .arg("y4m")
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::inherit())
+                            .spawn()?;
+
+                        let mut ffmpeg = isolate_process_group(&mut ffmpeg_command())
+                            .arg("-loglevel")
+                            .arg("error")
+                            .arg("-y")
+                            .arg("-i")
+                            .arg("-")
+                            .arg("-f")
+                            .arg("image2")
+                            .arg("-qscale:v")
+                            .arg("2")
+                            .arg("-start_number")
+                            .arg(frame_range.start.unwrap().to_string())
+                            .arg(&ffmpeg_pattern)
+                            .stdin(vspipe.stdout.take().unwrap())
+                            .stderr(Stdio::inherit())
+                            .stdout(Stdio::inherit())
+                            .spawn()?;
+
+                        let Some(status_ffmpeg) = wait_with_timeout(&mut ffmpeg, limits.timeout)?
+                        else {
+                            kill_process_tree(&mut vspipe);
+                            vspipe.wait()?;
+                            eprintln!(
+                                "{} - Frame extraction timed out (attempt {}/{})",
+                                &output_name,
+                                attempt + 1,
+                                limits.retries + 1
+                            );
+                            continue;
+                        };
+                        let status_vspipe = vspipe.wait()?;
+
+                        if status_ffmpeg.success() && status_vspipe.success() {
+                            println!("{} - Frame extraction complete", &output_name)
+                        } else {
+                            eprintln!("{} - Extraction frames failed.", &output_name);
+                        }
+                        return Ok(());
                     }
 
+                    eprintln!("{} - Extraction frames failed.", &output_name);
                     Ok(())
                 },
             )?; // propagate any error from the closure
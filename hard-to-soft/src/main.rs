@@ -1,12 +1,12 @@
 use clap::{ArgAction, Parser};
-use encoding_utils_lib::
-    vapoursynth::{get_number_of_frames, SourcePlugin}
-;
+use encoding_utils_lib::{
+    binaries::{set_binary_paths, set_command_limits, BinaryPaths, CommandLimits}, vapoursynth::{get_number_of_frames, set_content_hash_index, SourcePlugin}
+};
 use eyre::{OptionExt, Result};
 use hard_to_soft::{crop_extract::extract_frames, sections::SectionFile};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use vapoursynth4_rs::core::Core;
-use std::{fs::{self, create_dir_all}, path::PathBuf};
+use std::{fs::{self, create_dir_all}, path::PathBuf, time::Duration};
 
 /// Calculate SSIMULACRA2 metric - Using vszip
 #[derive(Parser, Debug)]
@@ -38,6 +38,33 @@ struct Args {
     )]
     keep_files: bool,
 
+    /// Name source indexes by a hash of the file content instead of only the file name,
+    /// so overwriting an input with the same name doesn't reuse a stale index
+    #[arg(long = "hash-index", action = ArgAction::SetTrue, default_value_t = false)]
+    hash_index: bool,
+
+    /// vspipe binary to run, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "vspipe-path", default_value = "vspipe", value_parser = clap::value_parser!(PathBuf))]
+    vspipe_path: PathBuf,
+
+    /// ffmpeg binary to run, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffmpeg-path", default_value = "ffmpeg", value_parser = clap::value_parser!(PathBuf))]
+    ffmpeg_path: PathBuf,
+
+    /// ffmsindex binary used to index inputs for ffms2, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffmsindex-path", default_value = "ffmsindex", value_parser = clap::value_parser!(PathBuf))]
+    ffmsindex_path: PathBuf,
+
+    /// Kill an external command (av1an, vspipe, ffmpeg) and the processes it started once it has
+    /// run this many seconds in total, and run it again (av1an resumes its encode). This limits
+    /// the whole command, not a single chunk, so leave room for the longest healthy encode
+    #[arg(long = "command-timeout")]
+    command_timeout: Option<u64>,
+
+    /// Times a timed out command is run again before failing
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
     /// Temp folder (default: "[TEMP]_<input>.json" if no temp folder given)
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     temp: Option<PathBuf>,
@@ -45,6 +72,9 @@ struct Args {
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    set_content_hash_index(args.hash_index);
+    set_binary_paths(BinaryPaths { vspipe: args.vspipe_path.clone(), ffmpeg: args.ffmpeg_path.clone(), ffmsindex: args.ffmsindex_path.clone(), ..BinaryPaths::default() })?;
+    set_command_limits(CommandLimits { timeout: args.command_timeout.map(Duration::from_secs), retries: args.retries })?;
     let temp_folder = match args.temp {
         Some(temp) => temp, 
         None => { 
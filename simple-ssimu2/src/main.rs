@@ -1,5 +1,5 @@
 use clap::{ArgAction, Parser};
-use encoding_utils_lib::{ ssimulacra2::{create_plot, ssimu2}, vapoursynth::{add_extension, SourcePlugin, TrimComplex}
+use encoding_utils_lib::{ binaries::{set_binary_paths, BinaryPaths}, math::{write_stats_json, StatsSummary}, ssimulacra2::{create_histogram, create_plot, ssimu2, PlotFormat}, vpy_files::ColorPreset, vapoursynth::{add_extension, detect_crop, DownscaleKernel, Ivtc, parse_downscale, parse_resolution, parse_trim, ResizeKernel, set_content_hash_index, SourcePlugin, TrimComplex, AUTO_CROP_SAMPLES}
 };
 use eyre::{OptionExt, Result};
 use vapoursynth4_rs::core::Core;
@@ -13,9 +13,9 @@ struct Args {
     // #[arg(short, long)]
     reference: PathBuf,
 
-    /// Distorted video file (encoded version)
-    // #[arg(short, long)]
-    distorted: PathBuf,
+    /// Distorted video file (encoded version). Repeat to compare several encodes on one plot
+    #[arg(short = 'd', long = "distorted", required = true)]
+    distorted: Vec<PathBuf>,
 
     /// JSON file containing scene information. Use for plot file.
     #[arg(short = 'S', long)]
@@ -37,8 +37,12 @@ struct Args {
     #[arg(short, long = "stats-file")]
     stats_file: Option<PathBuf>,
 
+    /// Path to a JSON summary of the stats (mean, standard deviation, mode, percentiles, min frame)
+    #[arg(long = "json-out")]
+    json_out: Option<PathBuf>,
+
     /// Trim to sync video: format is "first,last,clip"
-    /// Example: "6,18,distorted" or "6,18,d"
+    /// Same rules as --trim (e.g. -1 or an empty value is open). Example: "6,18,distorted", "6,18,d" or "6,,r"
     #[arg(long)]
     trim_complex: Option<TrimComplex>,
 
@@ -59,6 +63,15 @@ struct Args {
     )]
     keep_files: bool,
 
+    /// Name source indexes by a hash of the file content instead of only the file name,
+    /// so overwriting an input with the same name doesn't reuse a stale index
+    #[arg(long = "hash-index", action = ArgAction::SetTrue, default_value_t = false)]
+    hash_index: bool,
+
+    /// ffmsindex binary used to index inputs for ffms2, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffmsindex-path", default_value = "ffmsindex", value_parser = clap::value_parser!(PathBuf))]
+    ffmsindex_path: PathBuf,
+
     /// Color params base on the svt-av1 params
     #[arg(
     long,
@@ -66,22 +79,38 @@ struct Args {
     )]
     color_metadata: String,
 
-    /// Crop (e.g. 1920:816:0:132)
+    /// Color metadata preset, replaces the color flags of --color-metadata
+    #[arg(long = "color-preset", value_enum)]
+    color_preset: Option<ColorPreset>,
+
+    /// Crop (e.g. 1920:816:0:132), or "auto" to detect black bars on the reference
     #[arg(long)]
     crop: Option<String>,
 
-    /// Downscale, using Box Kernel
+    /// Downscale factor in (0, 1] (e.g. 0.5, 0.333), using --downscale-kernel (Box by default).
+    /// "true" means 0.5
     #[arg(
         long, 
-        default_value_t = 1.0
+        default_value_t = 1.0,
+        value_parser = parse_downscale
     )]
     downscale: f64,
 
-    /// Resize, using Hermite Kernel. Format WIDTHxHEIGHT. Example: 1920x1080. 
-    #[arg(long)]
+    /// Kernel used by --downscale
+    #[arg(value_enum, long = "downscale-kernel", default_value_t = ResizeKernel::Box)]
+    downscale_kernel: ResizeKernel,
+
+    /// Taps of the --downscale-kernel (lanczos, blackman, spline). Kernel default if not set
+    #[arg(long = "downscale-taps", value_parser = clap::value_parser!(i64).range(1..=128))]
+    downscale_taps: Option<i64>,
+
+    /// Resize the reference to an exact resolution, using Hermite Kernel. Format WIDTHxHEIGHT. Example: 1920x1080. 
+    /// Useful to compare a 1080p encode against a 4K reference. Can't be used with --downscale
+    #[arg(long, conflicts_with = "downscale")]
     resize: Option<String>,
 
-    /// Trim source file. Format Start:End. Examples: 1261:5623, 0:2432, 2352:-1. 
+    /// Trim source file. Format Start:End, End included. Negative values count from the end and an empty side is open.
+    /// Examples: 1261:5623, 0:2432, 2352:-1, 2352:, :-25
     #[arg(short, long)]
     trim: Option<String>,
 
@@ -93,11 +122,47 @@ struct Args {
         value_parser = clap::value_parser!(bool)
     )]
     detelecine: bool,
+
+    /// Field order used by --detelecine: 1 = top field first, 0 = bottom field first
+    #[arg(long = "field-order", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=1))]
+    field_order: i64,
+
+    /// VFM matching mode used by --detelecine (0-5)
+    #[arg(long = "ivtc-mode", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=5))]
+    ivtc_mode: i64,
+
+    /// VDecimate cycle used by --detelecine: one frame is dropped every N frames
+    #[arg(long = "decimate-cycle", default_value_t = 5, value_parser = clap::value_parser!(i64).range(2..=25))]
+    decimate_cycle: i64,
+
+    /// VDecimate duplicate threshold used by --detelecine (percent difference)
+    #[arg(long = "decimate-dupthresh", default_value_t = 1.1)]
+    decimate_dupthresh: f64,
     
-    /// Save a plot of the SSIMU2 stats (Needs to be an .svg file)
+    /// Also measure PSNR and SSIM (vszip) and add them to the stats
+    #[arg(
+        long = "psnr-ssim",
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+    )]
+    psnr_ssim: bool,
+
+    /// Measure the luma plane only: the chroma of both clips is replaced with neutral gray
+    #[arg(
+        long = "luma-only",
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+    )]
+    luma_only: bool,
+
+    /// Save a plot of the SSIMU2 stats (.svg or .png file)
     #[arg(short, long = "plot-file")]
     plot_file: Option<PathBuf>,
 
+    /// Resolution used when the plot is rasterized to .png (96 = native size)
+    #[arg(long = "plot-dpi", default_value_t = 96.0)]
+    plot_dpi: f32,
+
     /// Temp folder (default: "[TEMP]_<input>.json" if no temp folder given)
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     temp: Option<PathBuf>,
@@ -111,13 +176,27 @@ struct Args {
     )]
     save_csv: bool,
 
+    /// Save an SVG histogram of the scores (1-point bins). Path: "[HISTOGRAM]_<input>.svg"
+    #[arg(
+        long, 
+        default_value_t = false,
+        action = ArgAction::SetTrue,
+        value_parser = clap::value_parser!(bool)
+    )]
+    histogram: bool,
+
     /// Threads to use
     #[arg(long, default_value_t = 0)]
     threads: u32,
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(preset) = args.color_preset {
+        args.color_metadata = preset.apply(&args.color_metadata);
+    }
+    set_content_hash_index(args.hash_index);
+    set_binary_paths(BinaryPaths { ffmsindex: args.ffmsindex_path.clone(), ..BinaryPaths::default() })?;
 
     // Configure global pool at startup
     rayon::ThreadPoolBuilder::new()
@@ -125,6 +204,17 @@ fn main() -> Result<()> {
         .build_global()
         .expect("Failed to initialize global thread pool");
 
+    // Fail before computing metrics if the resize or the plot are invalid
+    if let Some(resize) = &args.resize {
+        parse_resolution(resize)?;
+    }
+    if let Some(trim) = &args.trim {
+        parse_trim(trim)?;
+    }
+    if let Some(plot_file) = &args.plot_file {
+        PlotFormat::from_path(plot_file)?;
+    }
+
     let temp_folder = match args.temp {
         Some(temp) => temp, 
         None => { 
@@ -147,51 +237,103 @@ fn main() -> Result<()> {
 
     let core = Core::builder().build();
 
+    let crop = match args.crop.as_deref() {
+        Some("auto") => {
+            let crop = detect_crop(&core, &args.reference, &args.source_plugin, &indexes_folder, AUTO_CROP_SAMPLES)?.to_string();
+            println!("Detected crop: {crop}");
+            Some(crop)
+        }
+        _ => args.crop.clone(),
+    };
+
     // Process the videos
-    let score_list = ssimu2(
-        &core,
+    let mut distorted_scores = Vec::new();
+    let mut stats_with_filenames = Vec::new();
+    let mut stats_summaries = Vec::new();
+    for distorted in &args.distorted {
+        let scores = ssimu2(
+            &core,
             &args.reference,
-            &args.distorted,
+            distorted,
             args.steps as usize,
             args.source_plugin,
             args.trim.as_deref(),
-            args.trim_complex,
+            args.trim_complex.clone(),
             &indexes_folder,
             args.verbose,
             &args.color_metadata,
-            args.crop.as_deref(),
+            crop.as_deref(),
             args.downscale,
+            DownscaleKernel { kernel: args.downscale_kernel, taps: args.downscale_taps },
             args.resize.as_deref(),
-            args.detelecine,
+            args.detelecine.then_some(Ivtc { field_order: args.field_order, vfm_mode: args.ivtc_mode, decimate_cycle: args.decimate_cycle, decimate_dupthresh: args.decimate_dupthresh }),
+            args.psnr_ssim,
+            args.luma_only,
         )?;
 
-    let stats = score_list.get_stats()?;
-    let stats_with_filename = format!("\n[INFO]\nReference: {}\nDistorted: {}\nSteps: {}\n\n{}", args.reference.to_string_lossy(), args.distorted.to_string_lossy(), args.steps, stats);
-    if let Some(output_path) = args.stats_file {
-        println!("\n{stats_with_filename}");
-        std::fs::write(output_path, stats_with_filename)?;
-    } else {
+        let stats = scores.get_stats()?;
+        let score_list = scores.ssimu2;
+        let stats_with_filename = format!("\n[INFO]\nReference: {}\nDistorted: {}\nSteps: {}\n\n{}", args.reference.to_string_lossy(), distorted.to_string_lossy(), args.steps, stats);
         println!("\n{stats_with_filename}");
+        stats_with_filenames.push(stats_with_filename);
+        if args.json_out.is_some() {
+            stats_summaries.push(StatsSummary {
+                reference: args.reference.to_string_lossy().into_owned(),
+                distorted: distorted.to_string_lossy().into_owned(),
+                steps: args.steps,
+                ssimu2: score_list.to_stats_json()?,
+            });
+        }
+
+        if args.save_csv {
+            let csv_path = { 
+                let output_name = format!(
+                    "[FRAME-SCORES]_{}",
+                    distorted
+                        .file_stem()
+                        .ok_or_eyre("No file name")?
+                        .to_str()
+                        .ok_or_eyre("Invalid UTF-8 in input path")?
+                );
+                let path = distorted.with_file_name(output_name);
+                add_extension("csv", path)
+            };
+            score_list.write_to_csv(&csv_path)?;
+        }
+
+        if args.histogram {
+            let histogram_path = { 
+                let output_name = format!(
+                    "[HISTOGRAM]_{}",
+                    distorted
+                        .file_stem()
+                        .ok_or_eyre("No file name")?
+                        .to_str()
+                        .ok_or_eyre("Invalid UTF-8 in input path")?
+                );
+                let path = distorted.with_file_name(output_name);
+                add_extension("svg", path)
+            };
+            create_histogram(&histogram_path, &score_list, distorted, args.steps)?;
+        }
+
+        distorted_scores.push((distorted.as_path(), score_list));
     }
 
-    if args.save_csv {
-        let csv_path = { 
-            let output_name = format!(
-                "[FRAME-SCORES]_{}",
-                args.distorted
-                    .file_stem()
-                    .ok_or_eyre("No file name")?
-                    .to_str()
-                    .ok_or_eyre("Invalid UTF-8 in input path")?
-            );
-            let path = args.distorted.with_file_name(output_name);
-            add_extension("csv", path)
-        };
-        score_list.write_to_csv(&csv_path)?;
+    if let Some(output_path) = args.stats_file {
+        std::fs::write(output_path, stats_with_filenames.join("\n"))?;
+    }
+
+    if let Some(json_out) = args.json_out {
+        write_stats_json(&json_out, &stats_summaries)?;
     }
 
     if let Some(plot_file) = args.plot_file {
-        create_plot(&plot_file, &score_list, &args.reference, &args.distorted, args.scenes.as_deref(), args.steps)?;
+        let distorted_scores = distorted_scores
+            .iter()
+            .map(|(distorted, score_list)| (*distorted, score_list))
+            .collect();
+        create_plot(&plot_file, distorted_scores, &args.reference, args.scenes.as_deref(), args.steps, args.plot_dpi)?;
     }
 
     if !args.keep_files && fs::exists(&temp_folder)? {
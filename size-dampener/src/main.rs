@@ -1,9 +1,9 @@
 use bytesize::ByteSize;
 use clap::{ArgAction, Parser};
-use encoding_utils_lib::{crf::crf_parser, dampen::dampen_loop::dampen_loop};
+use encoding_utils_lib::{binaries::{set_binary_paths, set_command_limits, BinaryPaths, CommandLimits}, crf::{crf_parser, CrfOrder}, dampen::dampen_loop::{dampen_loop, SizeThreshold}, frame_loop::check_chunk_method};
 use eyre::{OptionExt, Result};
 
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{fs, path::PathBuf, str::FromStr, time::Duration};
 
 /// Scene Dampener that dynamically adjusts CRF.
 /// Re-encode av1an scenes until they are below a size threshold
@@ -41,12 +41,23 @@ struct Args {
     #[arg(short = 's', long, default_value = "10.0 MiB")]
     size_threshold: String,
 
-    /// Target CRF value(s) (70-1). Can be:
+    /// Total size budget of the output (e.g. "500 MiB"), split across scenes by frame count.
+    /// Replaces --size-threshold
+    #[arg(long)]
+    total_size: Option<String>,
+
+    /// Smallest threshold a scene can get with --total-size
+    #[arg(long, default_value = "256 KiB")]
+    min_scene_size: String,
+
+    /// Target CRF value(s) (1-70), in ascending order. Can be:
     /// - Single value (35)
-    /// - Comma-separated list (35,27,21)
-    /// - Range (36..21)
-    /// - Stepped range (36..21:3)
-    #[arg(short = 'c', long, default_value = "35,30,27,24,21,18")]
+    /// - Comma-separated list (21,27,35)
+    /// - Range (21..36)
+    /// - Stepped range (21..36:3)
+    /// - Logarithmic range (21..36:log:6)
+    /// - File with one of the above per line (@ladder.txt)
+    #[arg(short = 'c', long, default_value = "18,21,24,27,30,35")]
     crf: String,
 
     /// Input video file used to encode during size-dampener, you can also pass a .vpy script
@@ -77,11 +88,33 @@ struct Args {
         default_value_t = false,
     )]
     keep_files: bool,
+
+    /// av1an binary to run, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "av1an-path", default_value = "av1an", value_parser = clap::value_parser!(PathBuf))]
+    av1an_path: PathBuf,
+
+    /// Kill an external command (av1an, vspipe, ffmpeg) and the processes it started once it has
+    /// run this many seconds in total, and run it again (av1an resumes its encode). This limits
+    /// the whole command, not a single chunk, so leave room for the longest healthy encode
+    #[arg(long = "command-timeout")]
+    command_timeout: Option<u64>,
+
+    /// Times a timed out command is run again before failing
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
+    /// Also lower the CRF of scenes under 60% of the size threshold, one ladder step at a time,
+    /// keeping the lowest CRF that stays under the threshold
+    #[arg(long, action = ArgAction::SetTrue, default_value_t = false)]
+    reclaim: bool,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let crf_values = crf_parser(&args.crf)?;
+    set_binary_paths(BinaryPaths { av1an: args.av1an_path.clone(), ..BinaryPaths::default() })?;
+    set_command_limits(CommandLimits { timeout: args.command_timeout.map(Duration::from_secs), retries: args.retries })?;
+    let crf_values = crf_parser(&args.crf, CrfOrder::Ascending)?;
+    check_chunk_method(&args.av1an_params)?;
     let input_path = &args.input;
     let scene_boosted = match args.scene_file_input {
         Some(output) => output,
@@ -127,7 +160,13 @@ fn main() -> Result<()> {
 
     fs::create_dir_all(&temp_folder)?;
 
-    let size_threshold = ByteSize::from_str(&args.size_threshold).map_err(|e| eyre::eyre!(e))?;
+    let size_threshold = match args.total_size {
+        Some(total_size) => SizeThreshold::Total {
+            budget: ByteSize::from_str(&total_size).map_err(|e| eyre::eyre!(e))?,
+            min_scene_size: ByteSize::from_str(&args.min_scene_size).map_err(|e| eyre::eyre!(e))?,
+        },
+        None => SizeThreshold::PerScene(ByteSize::from_str(&args.size_threshold).map_err(|e| eyre::eyre!(e))?),
+    };
     dampen_loop(
         input_path,
         &args.output,
@@ -141,7 +180,8 @@ fn main() -> Result<()> {
         args.crf_data_file.as_deref(),
         &temp_folder,
         args.backup,
-        args.keep_files
+        args.keep_files,
+        args.reclaim,
     )?;
 
     Ok(())
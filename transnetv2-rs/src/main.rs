@@ -1,5 +1,5 @@
 use clap::{ArgAction, Parser};
-use encoding_utils_lib::{transnetv2::transnet::run_transnetv2, vapoursynth::SourcePlugin};
+use encoding_utils_lib::{binaries::{set_binary_paths, BinaryPaths}, scenes::{SceneFileFormat, SceneList}, transnetv2::{inference::{InferenceWindow, MergeDirection}, onnx::ExecutionProvider, transnet::{parse_inference_size, run_transnetv2}}, vapoursynth::{Ivtc, parse_trim, set_content_hash_index, SourcePlugin}, vpy_files::ColorPreset};
 use eyre::OptionExt;
 use vapoursynth4_rs::core::Core;
 use std::{fs, path::{absolute, PathBuf}};
@@ -12,10 +12,14 @@ struct Args {
     // #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     input: PathBuf,
 
-    /// Path to the scenes JSON output file (default: "[SCENES]_<input>.json" if no path given)
+    /// Path to the scenes output file (default: "[SCENES]_<input>.<json|txt|qpfile>" if no path given)
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     output: Option<PathBuf>,
 
+    /// Scenes output format
+    #[arg(long, value_enum, default_value_t = SceneFileFormat::Av1anJson)]
+    format: SceneFileFormat,
+
     /// Path to custom ONNX model (default: uses embedded TransNetV2 model)
     #[arg(long, value_parser = clap::value_parser!(PathBuf))]
     model: Option<PathBuf>,
@@ -39,7 +43,8 @@ struct Args {
     #[arg(long = "extra-split-fades", value_parser = clap::value_parser!(u32).range(0..))]
     extra_split_fades: Option<u32>,
 
-    /// Minimum number of frames for a scenecut.
+    /// Minimum scene length in seconds.
+    /// If both `--min-scene-len` (frames) and `--min-scene-len-sec` are provided, frames take priority.
     #[arg(long = "min-scene-len-sec", default_value_t = 1, value_parser = clap::value_parser!(u32).range(0..))]
     min_scene_len_sec: u32,
 
@@ -47,6 +52,20 @@ struct Args {
     #[arg(long = "min-scene-len", value_parser = clap::value_parser!(u32).range(0..))]
     min_scene_len: Option<u32>,
 
+    /// Neighbour scenes shorter than the minimum scene length are merged into
+    #[arg(long = "short-scene-merge", value_enum, default_value_t = MergeDirection::Forward)]
+    short_scene_merge: MergeDirection,
+
+    /// Split scenes longer than the maximum scene length at their strongest hardcut or fade
+    /// prediction instead of the midpoint
+    #[arg(long = "split-at-sub-cuts", action = ArgAction::SetTrue, default_value_t = false)]
+    split_at_sub_cuts: bool,
+
+    /// Frame rate used to convert the lengths in seconds to frames (default: the source fps).
+    /// Required for variable frame rate sources unless every length is given in frames
+    #[arg(long = "fps")]
+    fps: Option<f64>,
+
     /// Threshold to detect scene cut
     #[arg(long = "threshold", default_value_t = 0.4)]
     threshold: f32,
@@ -63,6 +82,11 @@ struct Args {
     #[arg(long = "fade-threshold", default_value_t = 0.05)]
     fade_threshold: f32,
 
+    /// Hysteresis threshold for fade detection: a fade is only kept if it reaches this value,
+    /// and lasts while above `--fade-threshold` (default: same as `--fade-threshold`)
+    #[arg(long = "fade-threshold-high")]
+    fade_threshold_high: Option<f32>,
+
     /// Minimum fade length in frames
     #[arg(long = "min-fade-len", default_value_t = 5,  value_parser = clap::value_parser!(u32).range(0..))]
     min_fade_len: u32,
@@ -71,10 +95,45 @@ struct Args {
     #[arg(long = "merge-gap-between-fades", default_value_t = 4, value_parser = clap::value_parser!(u32).range(0..))]
     merge_gap_between_fades: u32,
 
-    /// Skip GPU acceleration
+    /// Skip GPU acceleration, same as `--execution-provider cpu`
     #[arg(long, action = ArgAction::SetTrue, default_value_t = false)]
     cpu: bool,
 
+    /// ONNX Runtime execution provider. Falls back to CPU with a warning if it can't be used
+    #[arg(long = "execution-provider", value_enum, default_value_t = ExecutionProvider::Auto)]
+    execution_provider: ExecutionProvider,
+
+    /// Number of 100-frame windows inferred at once (default: auto from free VRAM, 1 on CPU)
+    #[arg(long = "batch-size", value_parser = clap::value_parser!(u32).range(1..))]
+    batch_size: Option<u32>,
+
+    /// ONNX Runtime intra-op threads (default: all cores). This mostly affects CPU inference, on
+    /// GPU only the ops that fall back to CPU use them
+    #[arg(long = "ort-threads", value_parser = clap::value_parser!(u32).range(1..))]
+    ort_threads: Option<u32>,
+
+    /// Fetch frames for every batch of windows instead of loading the whole clip in memory
+    /// first. Slower, but memory no longer grows with the clip length
+    #[arg(long = "low-memory", action = ArgAction::SetTrue, default_value_t = false)]
+    low_memory: bool,
+
+    /// Number of frames the model sees at once. Must match the ONNX model input
+    #[arg(long = "window-size", default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    window_size: u32,
+
+    /// Frame size (WIDTHxHEIGHT) the clip is resized to before inference. Must match the ONNX
+    /// model input, the bundled model uses 48x27
+    #[arg(long = "inference-size", default_value = "48x27", value_parser = parse_inference_size)]
+    inference_size: (u32, u32),
+
+    /// Distance in frames between consecutive windows
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(1..))]
+    stride: u32,
+
+    /// Frames of each window whose predictions are kept. Format START:END, END - START must equal the stride
+    #[arg(long = "center-range", default_value = "25:75")]
+    center_range: String,
+
     /// Temp folder (default: "[Temp]_<input>" if no temp folder given)
     #[arg(short, long, value_parser = clap::value_parser!(PathBuf))]
     temp: Option<PathBuf>,
@@ -91,7 +150,8 @@ struct Args {
     #[arg(short, long)]
     crop: Option<String>,
 
-    /// Trim source file. Format Start:End. Examples: 1261:5623, 0:2432, 2352:-1. 
+    /// Trim source file. Format Start:End, End included. Negative values count from the end and an empty side is open.
+    /// Examples: 1261:5623, 0:2432, 2352:-1, 2352:, :-25
     #[arg(short, long)]
     trim: Option<String>,
 
@@ -104,6 +164,22 @@ struct Args {
     )]
     detelecine: bool,
 
+    /// Field order used by --detelecine: 1 = top field first, 0 = bottom field first
+    #[arg(long = "field-order", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=1))]
+    field_order: i64,
+
+    /// VFM matching mode used by --detelecine (0-5)
+    #[arg(long = "ivtc-mode", default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=5))]
+    ivtc_mode: i64,
+
+    /// VDecimate cycle used by --detelecine: one frame is dropped every N frames
+    #[arg(long = "decimate-cycle", default_value_t = 5, value_parser = clap::value_parser!(i64).range(2..=25))]
+    decimate_cycle: i64,
+
+    /// VDecimate duplicate threshold used by --detelecine (percent difference)
+    #[arg(long = "decimate-dupthresh", default_value_t = 1.1)]
+    decimate_dupthresh: f64,
+
     /// Color params base on the svt-av1 params
     #[arg(
     long,
@@ -111,6 +187,10 @@ struct Args {
     )]
     color_metadata: String,
 
+    /// Color metadata preset, replaces the color flags of --color-metadata
+    #[arg(long = "color-preset", value_enum)]
+    color_preset: Option<ColorPreset>,
+
     /// Keep temporary files (disables automatic cleanup)
     #[arg(
         short = 'k', 
@@ -120,6 +200,15 @@ struct Args {
     )]
     keep_files: bool,
 
+    /// Name source indexes by a hash of the file content instead of only the file name,
+    /// so overwriting an input with the same name doesn't reuse a stale index
+    #[arg(long = "hash-index", action = ArgAction::SetTrue, default_value_t = false)]
+    hash_index: bool,
+
+    /// ffmsindex binary used to index inputs for ffms2, either a name looked up in the PATH or a path to the executable
+    #[arg(long = "ffmsindex-path", default_value = "ffmsindex", value_parser = clap::value_parser!(PathBuf))]
+    ffmsindex_path: PathBuf,
+
     /// Get [PREDICTIONS]_{input}.csv file
     #[arg(
         long = "scene-predictions",
@@ -128,7 +217,11 @@ struct Args {
     )]
     scene_predictions: bool,
 
-    /// Get [HARDCUTS-SCENE]_{input}.json file
+    /// Write the detected fade ranges to `[FADES]_<input>.json`
+    #[arg(long = "fades-file", action = ArgAction::SetTrue, default_value_t = false)]
+    fades_file: bool,
+
+    /// Get [HARDCUT-SCENES]_{input} file (same format as --format)
     #[arg(
         long = "hardcut-scenes",
         action = ArgAction::SetTrue,
@@ -136,14 +229,29 @@ struct Args {
     )]
     hardcut_scenes: bool,
 
+    /// Compare the detected scenes with an existing scene file and print the cuts that appeared
+    /// or disappeared, e.g. after changing detection parameters
+    #[arg(long = "diff", value_parser = clap::value_parser!(PathBuf))]
+    diff: Option<PathBuf>,
+
     /// Threads to use
     #[arg(long, default_value_t = 0)]
     threads: u32,
 }
 
 fn main() -> eyre::Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(preset) = args.color_preset {
+        args.color_metadata = preset.apply(&args.color_metadata);
+    }
+    set_content_hash_index(args.hash_index);
+    set_binary_paths(BinaryPaths { ffmsindex: args.ffmsindex_path.clone(), ..BinaryPaths::default() })?;
+    if let Some(trim) = &args.trim {
+        parse_trim(trim)?;
+    }
     let input_path = absolute(&args.input)?;
+    // Read before detection, the file to compare with may be the output
+    let previous_scenes = args.diff.as_deref().map(SceneList::parse_scene_file).transpose()?;
 
     // Configure global pool at startup
     rayon::ThreadPoolBuilder::new()
@@ -155,12 +263,13 @@ fn main() -> eyre::Result<()> {
         Some(path) => path,
         None => {
             let output_name = format!(
-                "[SCENES]_{}.json",
+                "[SCENES]_{}.{}",
                 args.input
                     .file_stem()
                     .ok_or_eyre("No file name")?
                     .to_str()
-                    .ok_or_eyre("Invalid UTF-8 in input path")?
+                    .ok_or_eyre("Invalid UTF-8 in input path")?,
+                args.format.extension()
             );
             input_path.with_file_name(output_name)
         }
@@ -184,47 +293,68 @@ fn main() -> eyre::Result<()> {
     let indexes_folder = temp_folder.join("indexes");
     fs::create_dir_all(&indexes_folder)?;
 
+    let window = InferenceWindow::new(args.window_size as usize, args.stride as usize, &args.center_range)?;
+
     let core = Core::builder().build();
 
     let (scene_list, hardcut_list) = run_transnetv2(
         &core,
         &input_path,
         args.model.as_deref(),
-        args.cpu,
+        if args.cpu { ExecutionProvider::Cpu } else { args.execution_provider },
+        args.batch_size.map(|x| x as usize),
+        args.ort_threads.map(|x| x as usize),
+        args.low_memory,
+        window,
+        args.inference_size,
         args.source_plugin,
         &indexes_folder,
          args.verbose,
         &args.color_metadata,
         args.crop.as_deref(),
         args.trim.as_deref(),
-        args.detelecine,
+        args.detelecine.then_some(Ivtc { field_order: args.field_order, vfm_mode: args.ivtc_mode, decimate_cycle: args.decimate_cycle, decimate_dupthresh: args.decimate_dupthresh }),
         args.extra_split_sec.into(),
         args.extra_split.map(|x| x.into()),
         args.extra_split_sec_fades.into(),
         args.extra_split_fades.map(|x| x.into()),
         args.min_scene_len_sec.into(),
         args.min_scene_len.map(|x| x.into()),
+        args.short_scene_merge,
+        args.split_at_sub_cuts,
+        args.fps,
         args.threshold,
         args.fade_threshold,
+        args.fade_threshold_high,
         args.min_fade_len.into(),
         args.merge_gap_between_fades.into(),
         args.enable_fade_detection,
-        args.scene_predictions
+        args.scene_predictions,
+        args.fades_file
     )?;
 
-    scene_list.write_scene_list_to_file( &scenes)?;
+    if args.verbose {
+        scene_list.print_cut_confidences();
+    }
+
+    if let Some(previous) = &previous_scenes {
+        print!("{}", previous.diff(&scene_list));
+    }
+
+    scene_list.write_scene_list_with_format(&scenes, args.format)?;
 
     if args.hardcut_scenes {
         let output_name = format!(
-            "[HARDCUT-SCENES]_{}.json",
+            "[HARDCUT-SCENES]_{}.{}",
             args.input
                 .file_stem()
                 .ok_or_eyre("No file name")?
                 .to_str()
-                .ok_or_eyre("Invalid UTF-8 in input path")?
+                .ok_or_eyre("Invalid UTF-8 in input path")?,
+            args.format.extension()
         );
         let hardcut_path = input_path.with_file_name(output_name);
-        hardcut_list.write_scene_list_to_file(&hardcut_path)?;
+        hardcut_list.write_scene_list_with_format(&hardcut_path, args.format)?;
     }
 
 